@@ -0,0 +1,154 @@
+//! Conditional sections: objects carrying a `when` predicate are kept or
+//! dropped at compile time.
+//!
+//! Predicates compare context variables with quoted strings and can be
+//! joined with `and`, e.g. `os == "linux" and env != 'prod'`.
+
+use std::collections::HashMap;
+
+use crate::error::{Result, SnapconfigError};
+use crate::value::{FlatValue, ValueIdx, ValueNode};
+
+/// Key holding an object's predicate.
+pub const WHEN_KEY: &str = "when";
+
+/// Rebuilds `flat` without the objects whose `when` predicate is false.
+/// Kept objects lose their `when` key. A dropped root becomes an empty object.
+pub fn apply_conditions(flat: &FlatValue, context: &HashMap<String, String>) -> Result<FlatValue> {
+    let mut out = FlatValue::with_capacity(flat.len());
+    let root_idx = match flat.root() {
+        Some(root) => copy_node(flat, root, context, &mut out)?,
+        None => None,
+    };
+    let root_idx = match root_idx {
+        Some(idx) => idx,
+        None => out.add_node(ValueNode::Object(Vec::new())),
+    };
+    out.set_root(root_idx);
+    Ok(out)
+}
+
+fn copy_node(
+    src: &FlatValue,
+    idx: ValueIdx,
+    context: &HashMap<String, String>,
+    out: &mut FlatValue,
+) -> Result<Option<ValueIdx>> {
+    let node = match &src.nodes[idx as usize] {
+        ValueNode::Array(indices) => {
+            let mut kept = Vec::with_capacity(indices.len());
+            for &child in indices {
+                if let Some(new_idx) = copy_node(src, child, context, out)? {
+                    kept.push(new_idx);
+                }
+            }
+            ValueNode::Array(kept)
+        }
+        ValueNode::Object(pairs) => {
+            if let Some((_, when_idx)) = pairs.iter().find(|(k, _)| k == WHEN_KEY) {
+                let ValueNode::String(predicate) = &src.nodes[*when_idx as usize] else {
+                    return Err(SnapconfigError::BadCondition(format!(
+                        "'{}' must be a string",
+                        WHEN_KEY
+                    )));
+                };
+                if !evaluate(predicate, context)? {
+                    return Ok(None);
+                }
+            }
+
+            let mut kept = Vec::with_capacity(pairs.len());
+            for (key, child) in pairs {
+                if key == WHEN_KEY {
+                    continue;
+                }
+                if let Some(new_idx) = copy_node(src, *child, context, out)? {
+                    kept.push((key.clone(), new_idx));
+                }
+            }
+            ValueNode::Object(kept)
+        }
+        scalar => scalar.clone(),
+    };
+    Ok(Some(out.add_node(node)))
+}
+
+/// Evaluates `name == "value"` / `name != "value"` clauses joined by `and`.
+pub fn evaluate(predicate: &str, context: &HashMap<String, String>) -> Result<bool> {
+    let bad = || SnapconfigError::BadCondition(format!("Unsupported predicate: {:?}", predicate));
+
+    for clause in predicate.split(" and ") {
+        let (name, negate, literal) = if let Some((name, literal)) = clause.split_once("==") {
+            (name, false, literal)
+        } else if let Some((name, literal)) = clause.split_once("!=") {
+            (name, true, literal)
+        } else {
+            return Err(bad());
+        };
+
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(bad());
+        }
+        let literal = literal.trim();
+        let quoted = (literal.starts_with('"') && literal.ends_with('"'))
+            || (literal.starts_with('\'') && literal.ends_with('\''));
+        if literal.len() < 2 || !quoted {
+            return Err(bad());
+        }
+        let expected = &literal[1..literal.len() - 1];
+
+        let actual = context.get(name).ok_or_else(|| {
+            SnapconfigError::BadCondition(format!("Unknown variable '{}' in {:?}", name, predicate))
+        })?;
+        if (actual == expected) == negate {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::parse_yaml;
+
+    fn context(os: &str) -> HashMap<String, String> {
+        HashMap::from([("os".to_string(), os.to_string())])
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let ctx = context("linux");
+        assert!(evaluate(r#"os == "linux""#, &ctx).unwrap());
+        assert!(!evaluate("os != 'linux'", &ctx).unwrap());
+        assert!(evaluate("os == 'linux' and os != 'mac'", &ctx).unwrap());
+        assert!(evaluate("os ~= linux", &ctx).is_err());
+        assert!(evaluate("arch == 'x86'", &ctx).is_err());
+    }
+
+    #[test]
+    fn test_apply_conditions_drops_false_blocks() {
+        let flat =
+            parse_yaml("paths:\n  when: os == \"linux\"\n  home: /home\nname: app\n").unwrap();
+
+        let kept = apply_conditions(&flat, &context("linux")).unwrap();
+        let root = &kept.nodes[kept.root().unwrap() as usize];
+        let ValueNode::Object(pairs) = root else {
+            panic!("expected object root");
+        };
+        assert_eq!(pairs[1].0, "paths");
+        let ValueNode::Object(paths) = &kept.nodes[pairs[1].1 as usize] else {
+            panic!("expected paths object");
+        };
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].0, "home");
+
+        let dropped = apply_conditions(&flat, &context("windows")).unwrap();
+        let ValueNode::Object(pairs) = &dropped.nodes[dropped.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].0, "name");
+    }
+}
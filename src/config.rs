@@ -1,11 +1,20 @@
 //! SnapConfig - Zero-copy configuration access.
 
-use memmap2::Mmap;
-use pyo3::exceptions::{PyKeyError, PyTypeError, PyValueError};
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+
+use memmap2::{Mmap, MmapMut};
+use pyo3::exceptions::{PyIOError, PyKeyError, PyOverflowError, PyTypeError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyInt, PyList, PyString};
+use pyo3::types::{
+    timezone_utc_bound, PyBool, PyBytes, PyDate, PyDateTime, PyDelta, PyDict, PyFloat, PyInt,
+    PyList, PyString, PyTime, PyTuple, PyTzInfo,
+};
+use rkyv::AlignedVec;
 
-use crate::value::{ArchivedFlatValue, ArchivedValueNode, FlatValue};
+use crate::schema;
+use crate::value::{ArchivedDatetimeKind, ArchivedFlatValue, ArchivedValueNode, FlatValue};
 
 #[pyclass]
 struct SnapConfigIter {
@@ -55,52 +64,929 @@ impl SnapConfigIter {
     }
 }
 
-/// Zero-copy view into cached configuration data.
+/// Iterator behind `SnapConfig.iter_records`; converts one element per step.
+#[pyclass]
+struct RecordIter {
+    config: Py<SnapConfig>,
+    array_idx: u32,
+    pos: usize,
+    record_type: PyObject,
+    path: String,
+}
+
+#[pymethods]
+impl RecordIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        let config = slf.config.borrow(py);
+        let ArchivedValueNode::Array(indices) = &config.archived().nodes[slf.array_idx as usize]
+        else {
+            return Ok(None);
+        };
+        let Some(&child) = indices.get(slf.pos) else {
+            return Ok(None);
+        };
+        let record = config.build_record(
+            py,
+            slf.record_type.bind(py),
+            child,
+            &format!("{}.{}", slf.path, slf.pos),
+        )?;
+        drop(config);
+        slf.pos += 1;
+        Ok(Some(record))
+    }
+}
+
+/// Read-only mapping over one object of a config, returned by
+/// `SnapConfig.lazy()`. Values are converted only when looked up; nested
+/// objects come back as further `LazyDict`s. Holding the config keeps its
+/// backing memory alive.
+#[pyclass]
+pub struct LazyDict {
+    config: Py<SnapConfig>,
+    idx: u32,
+}
+
+impl LazyDict {
+    /// Index of `key`'s value in this object, if present.
+    fn find(&self, py: Python<'_>, key: &str) -> Option<u32> {
+        let config = self.config.borrow(py);
+        let ArchivedValueNode::Object(pairs) = &config.archived().nodes[self.idx as usize] else {
+            return None;
+        };
+        find_key_in_object(pairs, key, config.preserve_order)
+    }
+
+    /// Keys and value indices of this object, in stored order.
+    fn children(&self, py: Python<'_>) -> Vec<(String, u32)> {
+        let config = self.config.borrow(py);
+        match &config.archived().nodes[self.idx as usize] {
+            ArchivedValueNode::Object(pairs) => pairs
+                .iter()
+                .map(|pair| (pair.0.as_str().to_string(), pair.1))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// A `LazyDict` for objects, a plain Python value for anything else.
+    fn wrap(&self, py: Python<'_>, idx: u32) -> PyResult<PyObject> {
+        let config = self.config.borrow(py);
+        let nodes = &config.archived().nodes;
+        match &nodes[idx as usize] {
+            ArchivedValueNode::Object(_) => Ok(Py::new(
+                py,
+                LazyDict {
+                    config: self.config.clone_ref(py),
+                    idx,
+                },
+            )?
+            .into_py(py)),
+            _ => node_to_python(py, nodes, idx),
+        }
+    }
+}
+
+#[pymethods]
+impl LazyDict {
+    fn __getitem__(&self, py: Python<'_>, key: &str) -> PyResult<PyObject> {
+        match self.find(py, key) {
+            Some(idx) => self.wrap(py, idx),
+            None => Err(PyKeyError::new_err(key.to_string())),
+        }
+    }
+
+    #[pyo3(signature = (key, default=None))]
+    fn get(&self, py: Python<'_>, key: &str, default: Option<PyObject>) -> PyResult<PyObject> {
+        match self.find(py, key) {
+            Some(idx) => self.wrap(py, idx),
+            None => Ok(default.unwrap_or_else(|| py.None())),
+        }
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &str) -> bool {
+        self.find(py, key).is_some()
+    }
+
+    fn keys(&self, py: Python<'_>) -> Vec<String> {
+        self.children(py).into_iter().map(|(key, _)| key).collect()
+    }
+
+    fn values(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        self.children(py)
+            .into_iter()
+            .map(|(_, idx)| self.wrap(py, idx))
+            .collect()
+    }
+
+    fn items(&self, py: Python<'_>) -> PyResult<Vec<(String, PyObject)>> {
+        self.children(py)
+            .into_iter()
+            .map(|(key, idx)| Ok((key, self.wrap(py, idx)?)))
+            .collect()
+    }
+
+    fn __eq__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        if let Ok(other) = other.downcast::<LazyDict>() {
+            let other = other.borrow();
+            let (config, other_config) = (self.config.borrow(py), other.config.borrow(py));
+            return Ok(same_value(
+                &config.archived().nodes,
+                self.idx,
+                &other_config.archived().nodes,
+                other.idx,
+            )
+            .into_py(py));
+        }
+        if !is_mapping(other)? {
+            return Ok(py.NotImplemented());
+        }
+        let config = self.config.borrow(py);
+        let this = node_to_python(py, &config.archived().nodes, self.idx)?;
+        let other = py.get_type_bound::<PyDict>().call1((other,))?;
+        Ok(this.bind(py).eq(other)?.into_py(py))
+    }
+
+    fn __iter__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        Ok(PyList::new_bound(py, self.keys(py))
+            .as_any()
+            .iter()?
+            .into_any()
+            .unbind())
+    }
+
+    fn __len__(&self, py: Python<'_>) -> usize {
+        let config = self.config.borrow(py);
+        match &config.archived().nodes[self.idx as usize] {
+            ArchivedValueNode::Object(pairs) => pairs.len(),
+            _ => 0,
+        }
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> String {
+        format!("<LazyDict with {} keys>", self.__len__(py))
+    }
+}
+
+/// Read-only, `os.environ`-style mapping of env names to string values,
+/// returned by `SnapConfig.as_environ()`. Built up front, so it does not
+/// keep the config alive.
 #[pyclass]
+pub struct EnvironMap {
+    /// Names in config order, for iteration.
+    names: Vec<String>,
+    vars: HashMap<String, String>,
+}
+
+#[pymethods]
+impl EnvironMap {
+    fn __getitem__(&self, key: &str) -> PyResult<String> {
+        self.vars
+            .get(key)
+            .cloned()
+            .ok_or_else(|| PyKeyError::new_err(key.to_string()))
+    }
+
+    #[pyo3(signature = (key, default=None))]
+    fn get(&self, py: Python<'_>, key: &str, default: Option<PyObject>) -> PyObject {
+        match self.vars.get(key) {
+            Some(value) => value.into_py(py),
+            None => default.unwrap_or_else(|| py.None()),
+        }
+    }
+
+    fn __contains__(&self, key: &str) -> bool {
+        self.vars.contains_key(key)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.names.clone()
+    }
+
+    fn values(&self) -> Vec<String> {
+        self.names
+            .iter()
+            .map(|name| self.vars[name].clone())
+            .collect()
+    }
+
+    fn items(&self) -> Vec<(String, String)> {
+        self.names
+            .iter()
+            .map(|name| (name.clone(), self.vars[name].clone()))
+            .collect()
+    }
+
+    fn __eq__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        if let Ok(other) = other.downcast::<EnvironMap>() {
+            return Ok((self.vars == other.borrow().vars).into_py(py));
+        }
+        if !is_mapping(other)? {
+            return Ok(py.NotImplemented());
+        }
+        let other = py.get_type_bound::<PyDict>().call1((other,))?;
+        Ok(self
+            .vars
+            .clone()
+            .into_py(py)
+            .bind(py)
+            .eq(other)?
+            .into_py(py))
+    }
+
+    fn __iter__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        Ok(PyList::new_bound(py, &self.names)
+            .as_any()
+            .iter()?
+            .into_any()
+            .unbind())
+    }
+
+    fn __len__(&self) -> usize {
+        self.names.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<EnvironMap with {} keys>", self.names.len())
+    }
+}
+
+/// Whether `obj` is a `collections.abc.Mapping`, which the mapping types
+/// compare equal to when their items match.
+fn is_mapping(obj: &Bound<'_, PyAny>) -> PyResult<bool> {
+    obj.is_instance(
+        &obj.py()
+            .import_bound("collections.abc")?
+            .getattr("Mapping")?,
+    )
+}
+
+/// A string that renders as `***` so it stays out of logs and tracebacks.
+/// `reveal()` returns the real value.
+#[pyclass(frozen)]
+pub struct Secret {
+    value: String,
+}
+
+#[pymethods]
+impl Secret {
+    fn reveal(&self) -> &str {
+        &self.value
+    }
+
+    fn __repr__(&self) -> &'static str {
+        "***"
+    }
+
+    fn __str__(&self) -> &'static str {
+        "***"
+    }
+}
+
+/// Memory holding a cache's bytes.
+pub(crate) enum Backing {
+    Mmap(Mmap),
+    /// Read-write mapping; required by `set_scalar`.
+    MmapMut(MmapMut),
+    /// Bytes held in memory: a decompressed zstd payload, a small cache read
+    /// instead of mapped, or a config built without a cache file. Aligned
+    /// for rkyv, but not shared between processes.
+    Owned(AlignedVec),
+}
+
+impl Deref for Backing {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Backing::Mmap(m) => m,
+            Backing::MmapMut(m) => m,
+            Backing::Owned(v) => v,
+        }
+    }
+}
+
+/// Reads the `[N]` at the start of `rest`, returning N and the text after
+/// `]`, which must be empty or another `[N]`. Anything else is a ValueError
+/// naming `path`.
+fn next_index<'a>(path: &str, rest: &'a str) -> PyResult<(usize, &'a str)> {
+    split_index(rest)
+        .map_err(|reason| PyValueError::new_err(format!("Invalid path {:?}: {}", path, reason)))
+}
+
+/// `next_index` without the Python error: a malformed bracket yields the
+/// reason it was rejected.
+fn split_index(rest: &str) -> Result<(usize, &str), String> {
+    let bracketed = &rest[1..];
+    let end = bracketed
+        .find(']')
+        .ok_or_else(|| "unclosed '['".to_string())?;
+    let index = &bracketed[..end];
+    if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("[{}] is not an array index", index));
+    }
+    let index = index
+        .parse()
+        .map_err(|_| format!("[{}] is out of range", index))?;
+    let rest = &bracketed[end + 1..];
+    if !rest.is_empty() && !rest.starts_with('[') {
+        return Err(format!("unexpected {:?} after ']'", rest));
+    }
+    Ok((index, rest))
+}
+
+/// Where a node sits: its parent container's index and its position there.
+/// Unlike node indices, which deduplicated caches share between equal
+/// scalars, a slot names one place in the tree.
+type Slot = (u32, usize);
+
+/// Result of walking a dotted path.
+enum Resolved {
+    Found(u32),
+    /// A segment was absent; carries the KeyError `get()` raises without a default.
+    Missing(PyErr),
+}
+
+/// Zero-copy view into cached configuration data.
+#[pyclass(module = "snapconfig")]
 pub struct SnapConfig {
-    mmap: Mmap,
+    /// Shared with any views made by `sub()`.
+    mmap: Arc<Backing>,
     data_offset: usize,
     root_idx: u32,
+    preserve_order: bool,
     #[pyo3(get)]
     cache_path: String,
     #[pyo3(get)]
     source_path: Option<String>,
+    /// Deprecated path (relative to `root_idx`) -> suggested replacement.
+    deprecated: HashMap<String, Option<String>>,
+    /// The slots the deprecated paths resolve to, so lookups warn however
+    /// the path is spelled. Resolved on the first lookup.
+    deprecated_slots: OnceLock<HashMap<Slot, Option<String>>>,
+    /// The source hash from the cache header; pickled so unpickling can
+    /// tell whether the cache was rebuilt since.
+    source_hash: u64,
 }
 
 impl SnapConfig {
-    pub fn new(
-        mmap: Mmap,
+    pub(crate) fn new(
+        mmap: Backing,
         data_offset: usize,
         root_idx: u32,
+        preserve_order: bool,
         cache_path: String,
         source_path: Option<String>,
     ) -> Self {
         Self {
-            mmap,
+            mmap: Arc::new(mmap),
             data_offset,
             root_idx,
+            preserve_order,
             cache_path,
             source_path,
+            deprecated: HashMap::new(),
+            deprecated_slots: OnceLock::new(),
+            source_hash: 0,
         }
     }
 
+    pub(crate) fn with_source_hash(mut self, source_hash: u64) -> Self {
+        self.source_hash = source_hash;
+        self
+    }
+
+    pub(crate) fn with_deprecated(mut self, deprecated: HashMap<String, Option<String>>) -> Self {
+        self.deprecated = deprecated;
+        self.deprecated_slots = OnceLock::new();
+        self
+    }
+
+    fn deprecated_slots(&self) -> &HashMap<Slot, Option<String>> {
+        self.deprecated_slots.get_or_init(|| {
+            self.deprecated
+                .iter()
+                .filter_map(|(old, new)| match self.resolve_path_slot(old, false) {
+                    Ok((Resolved::Found(_), Some(slot))) => Some((slot, new.clone())),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    pub(crate) fn deprecated(&self) -> &HashMap<String, Option<String>> {
+        &self.deprecated
+    }
+
+    /// The deprecated paths below `path`, made relative to it, for a view
+    /// re-rooted there.
+    fn deprecated_under(&self, path: &str) -> HashMap<String, Option<String>> {
+        let prefix = format!("{}.", path);
+        self.deprecated
+            .iter()
+            .filter_map(|(old, new)| Some((old.strip_prefix(&prefix)?.to_string(), new.clone())))
+            .collect()
+    }
+
+    /// Emits a `DeprecationWarning` naming `path` when the node it reached
+    /// at `slot` was compiled as deprecated.
+    fn warn_if_deprecated(&self, py: Python<'_>, path: &str, slot: Option<Slot>) -> PyResult<()> {
+        let Some(replacement) = slot.and_then(|slot| self.deprecated_slots().get(&slot)) else {
+            return Ok(());
+        };
+        let message = match replacement {
+            Some(new) => format!("'{}' is deprecated; use '{}' instead", path, new),
+            None => format!("'{}' is deprecated", path),
+        };
+        let category = py.get_type_bound::<pyo3::exceptions::PyDeprecationWarning>();
+        PyErr::warn_bound(py, &category, &message, 1)
+    }
+
     #[inline]
     pub(crate) fn archived(&self) -> &ArchivedFlatValue {
         let bytes = &self.mmap[self.data_offset..];
         unsafe { rkyv::archived_root::<FlatValue>(bytes) }
     }
 
+    /// Copies the archived tree into an owned `FlatValue`, rooted at this
+    /// config's root.
+    pub(crate) fn to_flat_value(&self) -> FlatValue {
+        use rkyv::Deserialize;
+
+        let mut flat: FlatValue = self
+            .archived()
+            .deserialize(&mut rkyv::Infallible)
+            .expect("deserializing into owned values cannot fail");
+        flat.set_root(self.root_idx);
+        flat
+    }
+
+    pub(crate) fn preserve_order(&self) -> bool {
+        self.preserve_order
+    }
+
+    fn diff_side(&self) -> DiffSide<'_> {
+        DiffSide {
+            nodes: &self.archived().nodes,
+            preserve_order: self.preserve_order,
+        }
+    }
+
+    fn diff_with(&self, other: &SnapConfig, ignore: Option<Vec<String>>) -> Vec<NodeDiff> {
+        let ignore: HashSet<String> = ignore.unwrap_or_default().into_iter().collect();
+        let mut out = Vec::new();
+        if !ignore.contains("") {
+            diff_nodes(
+                self.diff_side(),
+                self.root_idx,
+                other.diff_side(),
+                other.root_idx,
+                "",
+                &ignore,
+                &mut out,
+            );
+        }
+        out
+    }
+
+    /// Re-roots the config at the top-level section `name`, for
+    /// `load(env=...)`. The error lists the sections that do exist.
+    pub(crate) fn select_env(mut self, name: &str) -> PyResult<Self> {
+        let nodes = &self.archived().nodes;
+        let ArchivedValueNode::Object(pairs) = &nodes[self.root_idx as usize] else {
+            return Err(PyTypeError::new_err(format!(
+                "env={:?} needs a config whose root is an object",
+                name
+            )));
+        };
+        let Some(idx) = find_key_in_object(pairs, name, self.preserve_order) else {
+            let available: Vec<&str> = pairs
+                .iter()
+                .filter(|pair| matches!(nodes[pair.1 as usize], ArchivedValueNode::Object(_)))
+                .map(|pair| pair.0.as_str())
+                .collect();
+            return Err(PyValueError::new_err(format!(
+                "Environment '{}' not found; available sections: {}",
+                name,
+                available.join(", ")
+            )));
+        };
+        if !matches!(nodes[idx as usize], ArchivedValueNode::Object(_)) {
+            return Err(PyTypeError::new_err(format!(
+                "Environment '{}' is {}, not a section",
+                name,
+                Self::described_type(&nodes[idx as usize])
+            )));
+        }
+        self.root_idx = idx;
+        let deprecated = self.deprecated_under(name);
+        Ok(self.with_deprecated(deprecated))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_mapped(&self) -> bool {
+        !matches!(*self.mmap, Backing::Owned(_))
+    }
+
+    /// Walks a dotted path (`servers.0.host` or `servers[0].host`) from the
+    /// root. Type errors (indexing a scalar, non-integer array index) and
+    /// malformed brackets are raised; absent keys are reported as `Missing`.
+    fn resolve_path(&self, path: &str) -> PyResult<Resolved> {
+        self.resolve_path_with(path, false)
+    }
+
+    /// `resolve_path`, optionally retrying keys that have no exact match with
+    /// an ASCII case-insensitive scan.
+    fn resolve_path_with(&self, path: &str, case_insensitive: bool) -> PyResult<Resolved> {
+        Ok(self.resolve_path_slot(path, case_insensitive)?.0)
+    }
+
+    /// `resolve_path_with`, also returning the slot of the node found (None
+    /// for the root).
+    fn resolve_path_slot(
+        &self,
+        path: &str,
+        case_insensitive: bool,
+    ) -> PyResult<(Resolved, Option<Slot>)> {
+        let archived = self.archived();
+        let mut current_idx = self.root_idx;
+        let mut slot = None;
+        // The segment that led to `current_idx`, for errors about its node.
+        let mut current_name: Option<String> = None;
+        let cannot_traverse = |node: &ArchivedValueNode, name: &Option<String>| {
+            let what = match name {
+                Some(name) => format!("'{}'", name),
+                None => "the root".to_string(),
+            };
+            PyTypeError::new_err(format!(
+                "Cannot traverse into {} ({}) while resolving '{}'",
+                what,
+                Self::node_type_name(node),
+                path
+            ))
+        };
+
+        for part in path.split('.') {
+            // `a.b[2]` indexes `b`, unless the object holds a key spelled
+            // `b[2]` itself: keys may contain `[`, and an exact match wins.
+            let (key, mut brackets) = match part.find('[') {
+                Some(pos) => match &archived.nodes[current_idx as usize] {
+                    ArchivedValueNode::Object(pairs)
+                        if find_key_in_object(pairs, part, self.preserve_order).is_some() =>
+                    {
+                        (part, "")
+                    }
+                    _ => part.split_at(pos),
+                },
+                None => (part, ""),
+            };
+
+            if !key.is_empty() || brackets.is_empty() {
+                let node = &archived.nodes[current_idx as usize];
+                match node {
+                    ArchivedValueNode::Object(pairs) => {
+                        let found =
+                            find_key_position(pairs, key, self.preserve_order).or_else(|| {
+                                case_insensitive
+                                    .then(|| find_key_ignore_case(pairs, key))
+                                    .flatten()
+                            });
+                        match found {
+                            Some(pos) => {
+                                slot = Some((current_idx, pos));
+                                current_idx = pairs[pos].1;
+                            }
+                            None => {
+                                return Ok((
+                                    Resolved::Missing(PyKeyError::new_err(format!(
+                                        "Key not found: {}",
+                                        key
+                                    ))),
+                                    None,
+                                ))
+                            }
+                        }
+                    }
+                    ArchivedValueNode::Array(indices) => {
+                        let Ok(idx) = key.parse::<usize>() else {
+                            return Err(PyTypeError::new_err(
+                                "Cannot index array with non-integer",
+                            ));
+                        };
+                        if idx < indices.len() {
+                            slot = Some((current_idx, idx));
+                            current_idx = indices[idx];
+                        } else {
+                            return Ok((
+                                Resolved::Missing(PyKeyError::new_err(format!(
+                                    "Index out of bounds: {}",
+                                    idx
+                                ))),
+                                None,
+                            ));
+                        }
+                    }
+                    _ => return Err(cannot_traverse(node, &current_name)),
+                }
+                current_name = Some(key.to_string());
+            }
+
+            while !brackets.is_empty() {
+                let (idx, rest) = next_index(path, brackets)?;
+                brackets = rest;
+                let node = &archived.nodes[current_idx as usize];
+                match node {
+                    ArchivedValueNode::Array(indices) if idx < indices.len() => {
+                        slot = Some((current_idx, idx));
+                        current_idx = indices[idx];
+                    }
+                    ArchivedValueNode::Array(_) => {
+                        return Ok((
+                            Resolved::Missing(PyKeyError::new_err(format!(
+                                "Index out of bounds: {}",
+                                idx
+                            ))),
+                            None,
+                        ))
+                    }
+                    ArchivedValueNode::Object(_) => {
+                        return Ok((
+                            Resolved::Missing(PyKeyError::new_err(format!(
+                                "Key not found: [{}]",
+                                idx
+                            ))),
+                            None,
+                        ))
+                    }
+                    _ => return Err(cannot_traverse(node, &current_name)),
+                }
+                current_name = Some(format!("[{}]", idx));
+            }
+        }
+
+        Ok((Resolved::Found(current_idx), slot))
+    }
+
+    /// Like `resolve_path`, but a missing path is an error.
+    fn require_path(&self, path: &str) -> PyResult<u32> {
+        match self.resolve_path(path)? {
+            Resolved::Found(idx) => Ok(idx),
+            Resolved::Missing(err) => Err(err),
+        }
+    }
+
+    /// Run the string at `path` through the `ipaddress` constructor `factory`,
+    /// naming the path when it rejects the value.
+    fn parse_ipaddress(&self, py: Python<'_>, path: &str, factory: &str) -> PyResult<PyObject> {
+        let text = self.get_str(path)?;
+        py.import_bound("ipaddress")?
+            .getattr(factory)?
+            .call1((text.as_str(),))
+            .map(Bound::unbind)
+            .map_err(|e| PyValueError::new_err(format!("'{}': {}", path, e.value_bound(py))))
+    }
+
+    fn type_mismatch(path: &str, node: &ArchivedValueNode, expected: &str) -> PyErr {
+        PyTypeError::new_err(format!(
+            "'{}' is {}, expected {}",
+            path,
+            Self::described_type(node),
+            expected
+        ))
+    }
+
+    /// `node_type_name` with its article, e.g. "an int" or "a string".
+    fn described_type(node: &ArchivedValueNode) -> String {
+        let name = Self::node_type_name(node);
+        let article = if name.starts_with(['a', 'e', 'i', 'o', 'u']) {
+            "an"
+        } else {
+            "a"
+        };
+        format!("{} {}", article, name)
+    }
+
     fn node_type_name(node: &ArchivedValueNode) -> &'static str {
         match node {
             ArchivedValueNode::Null => "null",
             ArchivedValueNode::Bool(_) => "bool",
-            ArchivedValueNode::Int(_) => "int",
+            ArchivedValueNode::Int(_)
+            | ArchivedValueNode::UInt(_)
+            | ArchivedValueNode::BigInt(_) => "int",
             ArchivedValueNode::Float(_) => "float",
             ArchivedValueNode::String(_) => "string",
+            ArchivedValueNode::Bytes(_) => "bytes",
+            ArchivedValueNode::Datetime(kind, _) => match kind {
+                ArchivedDatetimeKind::OffsetDateTime | ArchivedDatetimeKind::LocalDateTime => {
+                    "datetime"
+                }
+                ArchivedDatetimeKind::LocalDate => "date",
+                ArchivedDatetimeKind::LocalTime => "time",
+            },
             ArchivedValueNode::Array(_) => "array",
             ArchivedValueNode::Object(_) => "object",
         }
     }
+
+    /// Calls `visit` with the joined path and index of every leaf in
+    /// document order. Scalars and empty objects/arrays are leaves.
+    fn for_each_leaf(
+        &self,
+        separator: &str,
+        mut visit: impl FnMut(String, u32) -> PyResult<()>,
+    ) -> PyResult<()> {
+        let nodes = &self.archived().nodes;
+        let mut stack = vec![(String::new(), self.root_idx)];
+        while let Some((path, idx)) = stack.pop() {
+            let join = |key: &str| {
+                if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}{}{}", path, separator, key)
+                }
+            };
+            match &nodes[idx as usize] {
+                ArchivedValueNode::Object(pairs) if !pairs.is_empty() => {
+                    stack.extend(
+                        pairs
+                            .iter()
+                            .rev()
+                            .map(|pair| (join(pair.0.as_str()), pair.1)),
+                    );
+                }
+                ArchivedValueNode::Array(indices) if !indices.is_empty() => {
+                    stack.extend(
+                        indices
+                            .iter()
+                            .enumerate()
+                            .rev()
+                            .map(|(i, child)| (join(&i.to_string()), *child)),
+                    );
+                }
+                _ => visit(path, idx)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `visit` with the env name and string value of every scalar:
+    /// `prefix` plus the upper-cased path segments joined by `sep`. Nulls
+    /// are empty strings; empty objects and arrays are skipped.
+    fn for_each_env_var(
+        &self,
+        prefix: &str,
+        sep: &str,
+        mut visit: impl FnMut(String, String),
+    ) -> PyResult<()> {
+        let nodes = &self.archived().nodes;
+        self.for_each_leaf(sep, |path, idx| {
+            if let Some(value) = scalar_to_string(&nodes[idx as usize]) {
+                visit(format!("{}{}", prefix, path.to_uppercase()), value);
+            }
+            Ok(())
+        })
+    }
+
+    /// Converts the node at `idx` to `record_type`: dataclasses are built
+    /// field by field, `int`/`float`/`str`/`bool` are type-checked, and any
+    /// other type is called with the plain value.
+    fn build_record(
+        &self,
+        py: Python<'_>,
+        record_type: &Bound<'_, PyAny>,
+        idx: u32,
+        path: &str,
+    ) -> PyResult<PyObject> {
+        let is_dataclass = py
+            .import_bound("dataclasses")?
+            .call_method1("is_dataclass", (record_type,))?
+            .is_truthy()?;
+        if is_dataclass {
+            return self.build_dataclass(py, record_type, idx, path, &PyDict::new_bound(py));
+        }
+
+        let value = node_to_python(py, &self.archived().nodes, idx)?;
+        let builtins = [
+            py.get_type_bound::<PyBool>(),
+            py.get_type_bound::<PyInt>(),
+            py.get_type_bound::<PyFloat>(),
+            py.get_type_bound::<PyString>(),
+        ];
+        if builtins.iter().any(|builtin| record_type.is(builtin)) {
+            return coerce_field(py, value, record_type, path);
+        }
+        Ok(record_type.call1((value,))?.unbind())
+    }
+
+    /// Builds `cls` from the object at `idx`, or a list of `cls` when it is
+    /// an array of objects.
+    fn build_mapped(
+        &self,
+        py: Python<'_>,
+        cls: &Bound<'_, PyAny>,
+        idx: u32,
+        path: &str,
+        type_map: &Bound<'_, PyDict>,
+    ) -> PyResult<PyObject> {
+        if let ArchivedValueNode::Array(indices) = &self.archived().nodes[idx as usize] {
+            let items = indices
+                .iter()
+                .enumerate()
+                .map(|(pos, child)| {
+                    self.build_dataclass(py, cls, *child, &format!("{}.{}", path, pos), type_map)
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+            return Ok(PyList::new_bound(py, items).into());
+        }
+        self.build_dataclass(py, cls, idx, path, type_map)
+    }
+
+    fn build_dataclass(
+        &self,
+        py: Python<'_>,
+        cls: &Bound<'_, PyAny>,
+        idx: u32,
+        path: &str,
+        type_map: &Bound<'_, PyDict>,
+    ) -> PyResult<PyObject> {
+        let dataclasses = py.import_bound("dataclasses")?;
+        let display_path = if path.is_empty() { "<root>" } else { path };
+        if !dataclasses
+            .call_method1("is_dataclass", (cls,))?
+            .is_truthy()?
+        {
+            return Err(PyTypeError::new_err(format!(
+                "'{}' maps to {}, which is not a dataclass",
+                display_path,
+                cls.repr()?
+            )));
+        }
+
+        let archived = self.archived();
+        let node = &archived.nodes[idx as usize];
+        let ArchivedValueNode::Object(pairs) = node else {
+            return Err(Self::type_mismatch(display_path, node, "an object"));
+        };
+
+        let missing = dataclasses.getattr("MISSING")?;
+        // Resolves string annotations (`from __future__ import annotations`);
+        // if a hint can't be evaluated, the raw field types are used instead.
+        let hints = py
+            .import_bound("typing")?
+            .call_method1("get_type_hints", (cls,))
+            .ok();
+        let kwargs = PyDict::new_bound(py);
+        for field in dataclasses.call_method1("fields", (cls,))?.iter()? {
+            let field = field?;
+            if !field.getattr("init")?.is_truthy()? {
+                continue;
+            }
+            let name: String = field.getattr("name")?.extract()?;
+            let field_path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", path, name)
+            };
+
+            let Some(child_idx) = find_key_in_object(pairs, &name, self.preserve_order) else {
+                let required = field.getattr("default")?.is(&missing)
+                    && field.getattr("default_factory")?.is(&missing);
+                if required {
+                    return Err(PyValueError::new_err(format!(
+                        "Missing required field '{}' for {}",
+                        field_path,
+                        cls.getattr("__name__")?
+                    )));
+                }
+                continue;
+            };
+
+            let value = match type_map.get_item(&field_path)? {
+                Some(child_cls) => {
+                    self.build_mapped(py, &child_cls, child_idx, &field_path, type_map)?
+                }
+                None => {
+                    let annotation = match hints.as_ref().map(|h| h.get_item(&name)) {
+                        Some(Ok(hint)) => hint,
+                        _ => field.getattr("type")?,
+                    };
+                    coerce_field(
+                        py,
+                        node_to_python(py, &archived.nodes, child_idx)?,
+                        &annotation,
+                        &field_path,
+                    )?
+                }
+            };
+            kwargs.set_item(name, value)?;
+        }
+        Ok(cls.call((), Some(&kwargs))?.unbind())
+    }
 }
 
 #[pymethods]
@@ -128,57 +1014,646 @@ impl SnapConfig {
     fn __getitem__(&self, py: Python<'_>, key: &Bound<'_, PyAny>) -> PyResult<PyObject> {
         let archived = self.archived();
         let root_node = &archived.nodes[self.root_idx as usize];
-        get_item_from_node(py, &archived.nodes, root_node, key)
+        let value = get_item_from_node(py, &archived.nodes, root_node, key, self.preserve_order)?;
+        if let (Ok(key), ArchivedValueNode::Object(pairs)) = (key.downcast::<PyString>(), root_node)
+        {
+            let key = key.to_str()?;
+            let slot =
+                find_key_position(pairs, key, self.preserve_order).map(|pos| (self.root_idx, pos));
+            self.warn_if_deprecated(py, key, slot)?;
+        }
+        Ok(value)
     }
 
-    /// Get nested value using dot notation (e.g., "database.host").
-    /// Returns `default` if the path is not found (or raises KeyError if no default).
-    #[pyo3(signature = (path, default=None))]
-    fn get(&self, py: Python<'_>, path: &str, default: Option<PyObject>) -> PyResult<PyObject> {
-        let archived = self.archived();
-        let parts: Vec<&str> = path.split('.').collect();
-        let mut current_idx = self.root_idx;
+    /// A `LazyDict` over the root object: a `Mapping` that converts values
+    /// only as they are looked up, unlike `to_dict()`.
+    fn lazy(slf: PyRef<'_, Self>) -> PyResult<LazyDict> {
+        if !matches!(
+            slf.archived().nodes[slf.root_idx as usize],
+            ArchivedValueNode::Object(_)
+        ) {
+            return Err(PyTypeError::new_err("lazy() only works on objects"));
+        }
+        Ok(LazyDict {
+            idx: slf.root_idx,
+            config: slf.into(),
+        })
+    }
 
-        for part in parts {
-            let node = &archived.nodes[current_idx as usize];
-            match node {
-                ArchivedValueNode::Object(pairs) => {
-                    if let Some(idx) = find_key_in_object(pairs, part) {
-                        current_idx = idx;
-                    } else {
-                        return match default {
-                            Some(d) => Ok(d),
-                            None => Err(PyKeyError::new_err(format!("Key not found: {}", part))),
-                        };
+    /// A `SnapConfig` rooted at the object or array at `path`. The view
+    /// shares this config's mapping rather than copying it, so paths on it
+    /// are relative to `path`.
+    fn sub(&self, path: &str) -> PyResult<SnapConfig> {
+        let idx = self.require_path(path)?;
+        let node = &self.archived().nodes[idx as usize];
+        if !matches!(
+            node,
+            ArchivedValueNode::Object(_) | ArchivedValueNode::Array(_)
+        ) {
+            return Err(PyTypeError::new_err(format!(
+                "'{}' is {}, not an object or array",
+                path,
+                Self::described_type(node)
+            )));
+        }
+        Ok(SnapConfig {
+            mmap: Arc::clone(&self.mmap),
+            data_offset: self.data_offset,
+            root_idx: idx,
+            preserve_order: self.preserve_order,
+            cache_path: self.cache_path.clone(),
+            source_path: self.source_path.clone(),
+            deprecated: self.deprecated_under(path),
+            // Slots are positions in the shared archive, so they still hold.
+            deprecated_slots: OnceLock::from(self.deprecated_slots().clone()),
+            source_hash: self.source_hash,
+        })
+    }
+
+    /// Pickles as the cache path: unpickling re-opens that cache read-only,
+    /// as `load_compiled` does, so it must exist where the config is
+    /// unpickled. `sub()` views keep their root. The cache's source hash is
+    /// pickled too, and unpickling raises if the cache was rebuilt since.
+    fn __reduce__(slf: &Bound<'_, Self>) -> PyResult<PyObject> {
+        let this = slf.borrow();
+        if this.cache_path.is_empty() {
+            return Err(PyTypeError::new_err(
+                "Cannot pickle a SnapConfig that has no cache file",
+            ));
+        }
+        let view_root =
+            (this.archived().root.as_ref() != Some(&this.root_idx)).then_some(this.root_idx);
+        let args = (
+            this.cache_path.clone(),
+            this.source_path.clone(),
+            view_root,
+            this.source_hash,
+        );
+        Ok((slf.get_type().getattr("_unpickle")?, args).into_py(slf.py()))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (cache_path, source_path, root_idx=None, source_hash=None))]
+    fn _unpickle(
+        cache_path: &str,
+        source_path: Option<&str>,
+        root_idx: Option<u32>,
+        source_hash: Option<u64>,
+    ) -> PyResult<SnapConfig> {
+        let mut config = crate::open_cache(cache_path, source_path, false).map_err(|e| {
+            PyIOError::new_err(format!(
+                "Cannot unpickle SnapConfig from cache '{}': {}",
+                cache_path, e
+            ))
+        })?;
+        if source_hash.is_some_and(|hash| hash != config.source_hash) {
+            return Err(PyValueError::new_err(format!(
+                "Cannot unpickle SnapConfig: cache '{}' changed since it was pickled",
+                cache_path
+            )));
+        }
+        if let Some(idx) = root_idx {
+            if idx as usize >= config.archived().nodes.len() {
+                return Err(PyValueError::new_err(format!(
+                    "Cannot unpickle SnapConfig: cache '{}' changed since it was pickled",
+                    cache_path
+                )));
+            }
+            config.root_idx = idx;
+        }
+        Ok(config)
+    }
+
+    /// Get nested value using dot notation (e.g., "database.host"); array
+    /// items are `servers.0.host` or `servers[0].host`.
+    /// Returns `default` (None unless given) if any segment is missing, like
+    /// `dict.get`; indexing into a scalar still raises TypeError.
+    /// `case_insensitive=True` lets segments match keys in any ASCII case,
+    /// preferring an exact match.
+    #[pyo3(signature = (path, default=None, case_insensitive=false))]
+    fn get(
+        &self,
+        py: Python<'_>,
+        path: &str,
+        default: Option<PyObject>,
+        case_insensitive: bool,
+    ) -> PyResult<PyObject> {
+        match self.resolve_path_slot(path, case_insensitive)? {
+            (Resolved::Found(idx), slot) => {
+                self.warn_if_deprecated(py, path, slot)?;
+                node_to_python(py, &self.archived().nodes, idx)
+            }
+            (Resolved::Missing(_), _) => Ok(default.unwrap_or_else(|| py.None())),
+        }
+    }
+
+    /// `[get(path, default) for path in paths]` in a single call, for hot
+    /// paths that pull many keys at once. Missing paths yield `default`.
+    #[pyo3(signature = (paths, default=None))]
+    fn get_many(
+        &self,
+        py: Python<'_>,
+        paths: Vec<String>,
+        default: Option<PyObject>,
+    ) -> PyResult<Vec<PyObject>> {
+        let nodes = &self.archived().nodes;
+        paths
+            .iter()
+            .map(|path| match self.resolve_path(path)? {
+                Resolved::Found(idx) => node_to_python(py, nodes, idx),
+                Resolved::Missing(_) => Ok(default
+                    .as_ref()
+                    .map_or_else(|| py.None(), |d| d.clone_ref(py))),
+            })
+            .collect()
+    }
+
+    /// Get a string wrapped in a `Secret`, which masks itself as `***`.
+    /// Raises KeyError for a missing path and TypeError for non-strings.
+    fn get_secret(&self, path: &str) -> PyResult<Secret> {
+        Ok(Secret {
+            value: self.get_str(path)?,
+        })
+    }
+
+    /// Like `get`, but a missing path falls back to the default of the first
+    /// glob pattern (`*`, `?`) in `patterns` that matches the whole path.
+    fn get_with_pattern_default(
+        &self,
+        py: Python<'_>,
+        path: &str,
+        patterns: &Bound<'_, PyDict>,
+    ) -> PyResult<PyObject> {
+        match self.resolve_path(path)? {
+            Resolved::Found(idx) => node_to_python(py, &self.archived().nodes, idx),
+            Resolved::Missing(_) => {
+                for (pattern, default) in patterns.iter() {
+                    if glob_match(pattern.extract::<&str>()?, path) {
+                        return Ok(default.unbind());
                     }
                 }
-                ArchivedValueNode::Array(indices) => {
-                    if let Ok(idx) = part.parse::<usize>() {
-                        if idx < indices.len() {
-                            current_idx = indices[idx];
-                        } else {
-                            return match default {
-                                Some(d) => Ok(d),
-                                None => Err(PyKeyError::new_err(format!(
-                                    "Index out of bounds: {}",
-                                    idx
-                                ))),
-                            };
-                        }
-                    } else {
-                        return Err(PyTypeError::new_err("Cannot index array with non-integer"));
+                Ok(py.None())
+            }
+        }
+    }
+
+    /// Build a tree of dataclasses. `type_map` maps dotted paths to dataclass
+    /// types, with `""` for the root; a mapped array becomes a list of
+    /// instances. Fields annotated `int`/`float`/`str`/`bool` (string
+    /// annotations included) are type-checked; strings are parsed into
+    /// int/float/bool and ints widen to float.
+    #[allow(clippy::wrong_self_convention)]
+    fn into_dataclasses(&self, py: Python<'_>, type_map: &Bound<'_, PyDict>) -> PyResult<PyObject> {
+        let Some(root_cls) = type_map.get_item("")? else {
+            return Err(PyValueError::new_err(
+                "type_map needs a \"\" entry for the root dataclass",
+            ));
+        };
+        self.build_mapped(py, &root_cls, self.root_idx, "", type_map)
+    }
+
+    /// Iterate over the array at `path`, converting each element to `type_`
+    /// only when it is reached. Dataclasses are built from object elements,
+    /// `int`/`float`/`str`/`bool` are type-checked (strings are parsed into
+    /// int/float/bool) and any other type is
+    /// called with the element. Errors name the element's index.
+    fn iter_records(slf: PyRef<'_, Self>, path: &str, type_: PyObject) -> PyResult<Py<RecordIter>> {
+        let py = slf.py();
+        let array_idx = slf.require_path(path)?;
+        let node = &slf.archived().nodes[array_idx as usize];
+        if !matches!(node, ArchivedValueNode::Array(_)) {
+            return Err(Self::type_mismatch(path, node, "an array"));
+        }
+        Py::new(
+            py,
+            RecordIter {
+                config: slf.into(),
+                array_idx,
+                pos: 0,
+                record_type: type_,
+                path: path.to_string(),
+            },
+        )
+    }
+
+    /// Like `get`, but the value must be an int; floats are not truncated.
+    fn get_int(&self, path: &str) -> PyResult<i64> {
+        let idx = self.require_path(path)?;
+        match &self.archived().nodes[idx as usize] {
+            ArchivedValueNode::Int(i) => Ok(*i),
+            ArchivedValueNode::UInt(u) => Err(PyOverflowError::new_err(format!(
+                "'{}' holds {}, which does not fit in a signed 64-bit int",
+                path, u
+            ))),
+            ArchivedValueNode::BigInt(digits) => Err(PyOverflowError::new_err(format!(
+                "'{}' holds {}, which does not fit in a signed 64-bit int",
+                path, digits
+            ))),
+            node => Err(Self::type_mismatch(path, node, "an int")),
+        }
+    }
+
+    /// Like `get`, but the value must be a number; ints are widened to float.
+    fn get_float(&self, path: &str) -> PyResult<f64> {
+        let idx = self.require_path(path)?;
+        match &self.archived().nodes[idx as usize] {
+            ArchivedValueNode::Float(f) => Ok(*f),
+            ArchivedValueNode::Int(i) => Ok(*i as f64),
+            ArchivedValueNode::UInt(u) => Ok(*u as f64),
+            node => Err(Self::type_mismatch(path, node, "a float")),
+        }
+    }
+
+    /// Like `get`, but the value must be a bool.
+    fn get_bool(&self, path: &str) -> PyResult<bool> {
+        let idx = self.require_path(path)?;
+        match &self.archived().nodes[idx as usize] {
+            ArchivedValueNode::Bool(b) => Ok(*b),
+            node => Err(Self::type_mismatch(path, node, "a bool")),
+        }
+    }
+
+    /// Like `get`, but the value must be a string.
+    fn get_str(&self, path: &str) -> PyResult<String> {
+        let idx = self.require_path(path)?;
+        match &self.archived().nodes[idx as usize] {
+            ArchivedValueNode::String(s) => Ok(s.as_str().to_string()),
+            node => Err(Self::type_mismatch(path, node, "a string")),
+        }
+    }
+
+    /// Like `get_str`, but the string must match the regex `pattern`
+    /// (searched, like `re.search`; anchor with `^...$` for a full match).
+    /// Raises ValueError naming the path and pattern when it does not.
+    fn get_matching(&self, path: &str, pattern: &str) -> PyResult<String> {
+        let regex = regex::Regex::new(pattern)
+            .map_err(|e| PyValueError::new_err(format!("Invalid pattern {:?}: {}", pattern, e)))?;
+        let value = self.get_str(path)?;
+        if !regex.is_match(&value) {
+            return Err(PyValueError::new_err(format!(
+                "'{}' does not match {:?}: {:?}",
+                path, pattern, value
+            )));
+        }
+        Ok(value)
+    }
+
+    /// Parse a string into an `ipaddress.IPv4Address`/`IPv6Address`.
+    fn get_ip(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        self.parse_ipaddress(py, path, "ip_address")
+    }
+
+    /// Parse a CIDR string into an `ipaddress.IPv4Network`/`IPv6Network`.
+    /// Host bits must be zero, as `ipaddress.ip_network` requires.
+    fn get_network(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        self.parse_ipaddress(py, path, "ip_network")
+    }
+
+    /// Parse a duration string such as `"1h30m"`, `"500ms"` or `"2d"` into a
+    /// `datetime.timedelta`. Units are `w`, `d`, `h`, `m`, `s`, `ms` and
+    /// `us`; amounts may be fractional (`"1.5h"`).
+    fn get_timedelta(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        let text = self.get_str(path)?;
+        let invalid = || PyValueError::new_err(format!("'{}': invalid duration {:?}", path, text));
+        let micros = parse_duration_micros(&text).ok_or_else(invalid)?;
+        const DAY: i64 = 86_400_000_000;
+        let days = i32::try_from(micros / DAY).map_err(|_| invalid())?;
+        let rest = micros % DAY;
+        let delta = PyDelta::new_bound(
+            py,
+            days,
+            (rest / 1_000_000) as i32,
+            (rest % 1_000_000) as i32,
+            true,
+        )
+        .map_err(|_| invalid())?;
+        Ok(delta.into())
+    }
+
+    /// Check that each dotted path in `spec` exists and has the named type
+    /// (`"int"`, `"float"`, `"bool"`, `"str"`, `"list"`, `"dict"`, ...).
+    /// Raises ValueError naming the first path that fails.
+    fn require_all(&self, spec: &Bound<'_, PyDict>) -> PyResult<()> {
+        let archived = self.archived();
+        for (path, expected) in spec.iter() {
+            let path: String = path.extract()?;
+            let expected: String = expected.extract()?;
+            let wanted = match expected.as_str() {
+                "str" | "string" => "string",
+                "list" | "array" => "array",
+                "dict" | "object" => "object",
+                "none" | "None" | "null" => "null",
+                name @ ("int" | "float" | "bool" | "datetime" | "date" | "time") => name,
+                _ => {
+                    return Err(PyValueError::new_err(format!(
+                        "Unknown type name '{}' for '{}'",
+                        expected, path
+                    )))
+                }
+            };
+
+            let Some(idx) = lookup_path(&archived.nodes, self.root_idx, &path) else {
+                return Err(PyValueError::new_err(format!(
+                    "Missing required path '{}'",
+                    path
+                )));
+            };
+            let node = &archived.nodes[idx as usize];
+            if Self::node_type_name(node) != wanted {
+                return Err(PyValueError::new_err(format!(
+                    "'{}' is {}, expected {}",
+                    path,
+                    Self::described_type(node),
+                    expected
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Overwrite an Int/Float/Bool value in place. The config must have been
+    /// opened with `load_compiled(..., writable=True)`; the change is written
+    /// straight to the cache file. Fails while `sub()` views of it are alive.
+    fn set_scalar(&mut self, path: &str, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let idx = self.require_path(path)?;
+        let base = self.mmap.as_ptr() as usize;
+        let node = &self.archived().nodes[idx as usize];
+
+        // Locate the scalar inside the mapping and encode the replacement.
+        let (offset, bytes): (usize, Vec<u8>) = match node {
+            ArchivedValueNode::Int(i) => {
+                if value.is_instance_of::<PyBool>() || !value.is_instance_of::<PyInt>() {
+                    return Err(PyTypeError::new_err(format!("'{}' holds an int", path)));
+                }
+                let v: i64 = value.extract()?;
+                (i as *const _ as usize - base, v.to_ne_bytes().to_vec())
+            }
+            ArchivedValueNode::Float(f) => {
+                if value.is_instance_of::<PyBool>()
+                    || !(value.is_instance_of::<PyFloat>() || value.is_instance_of::<PyInt>())
+                {
+                    return Err(PyTypeError::new_err(format!("'{}' holds a float", path)));
+                }
+                let v: f64 = value.extract()?;
+                (f as *const _ as usize - base, v.to_ne_bytes().to_vec())
+            }
+            ArchivedValueNode::Bool(b) => {
+                if !value.is_instance_of::<PyBool>() {
+                    return Err(PyTypeError::new_err(format!("'{}' holds a bool", path)));
+                }
+                let v: bool = value.extract()?;
+                (b as *const _ as usize - base, vec![v as u8])
+            }
+            _ => {
+                return Err(PyTypeError::new_err(format!(
+                    "'{}' is {}, not a fixed-width scalar",
+                    path,
+                    Self::described_type(node)
+                )))
+            }
+        };
+
+        if !matches!(*self.mmap, Backing::MmapMut(_)) {
+            return Err(PyValueError::new_err(
+                "Config is read-only; open it with load_compiled(..., writable=True)",
+            ));
+        }
+        let Some(Backing::MmapMut(mmap)) = Arc::get_mut(&mut self.mmap) else {
+            return Err(PyValueError::new_err(
+                "Config is shared with sub() views and cannot be modified",
+            ));
+        };
+        mmap[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        mmap.flush_range(offset, bytes.len())?;
+        Ok(())
+    }
+
+    /// Resolve an array of numbers into a `numpy.ndarray` (int64 when every
+    /// element is an int, uint64 when every element is above the int64
+    /// range, float64 otherwise). Ints too large for uint64 are an error.
+    #[cfg(feature = "numpy")]
+    fn get_ndarray(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        let idx = self.require_path(path)?;
+        let archived = self.archived();
+        let ArchivedValueNode::Array(indices) = &archived.nodes[idx as usize] else {
+            return Err(PyValueError::new_err(format!("'{}' is not an array", path)));
+        };
+
+        let mut ints: Vec<i64> = Vec::with_capacity(indices.len());
+        let mut uints: Vec<u64> = Vec::new();
+        let mut floats: Vec<f64> = Vec::with_capacity(indices.len());
+        let (mut all_ints, mut all_uints) = (true, true);
+        for (pos, child_idx) in indices.iter().enumerate() {
+            match &archived.nodes[*child_idx as usize] {
+                ArchivedValueNode::Int(i) => {
+                    all_uints = false;
+                    ints.push(*i);
+                    floats.push(*i as f64);
+                }
+                ArchivedValueNode::UInt(u) => {
+                    all_ints = false;
+                    uints.push(*u);
+                    floats.push(*u as f64);
+                }
+                ArchivedValueNode::Float(f) => {
+                    all_ints = false;
+                    all_uints = false;
+                    floats.push(*f);
+                }
+                ArchivedValueNode::BigInt(digits) => {
+                    return Err(PyOverflowError::new_err(format!(
+                        "'{}[{}]' holds {}, which is too large for int64 or uint64",
+                        path,
+                        pos,
+                        digits.as_str()
+                    )))
+                }
+                other => {
+                    return Err(PyValueError::new_err(format!(
+                        "'{}[{}]' is {}, expected a number",
+                        path,
+                        pos,
+                        Self::described_type(other)
+                    )))
+                }
+            }
+        }
+
+        let numpy = py.import_bound("numpy")?;
+        let kwargs = PyDict::new_bound(py);
+        let array = if indices.is_empty() {
+            kwargs.set_item("dtype", "float64")?;
+            numpy.call_method("asarray", (floats,), Some(&kwargs))?
+        } else if all_ints {
+            kwargs.set_item("dtype", "int64")?;
+            numpy.call_method("asarray", (ints,), Some(&kwargs))?
+        } else if all_uints {
+            kwargs.set_item("dtype", "uint64")?;
+            numpy.call_method("asarray", (uints,), Some(&kwargs))?
+        } else {
+            kwargs.set_item("dtype", "float64")?;
+            numpy.call_method("asarray", (floats,), Some(&kwargs))?
+        };
+        Ok(array.into())
+    }
+
+    /// Parse a `"<number> <unit>"` string such as `"5 meters"`. Returns a
+    /// `pint.Quantity` when pint is importable, else a `(value, unit)` tuple.
+    fn get_quantity(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        let idx = self.require_path(path)?;
+        let node = &self.archived().nodes[idx as usize];
+        let ArchivedValueNode::String(s) = node else {
+            return Err(PyTypeError::new_err(format!(
+                "'{}' is {}, expected a string like '5 meters'",
+                path,
+                Self::described_type(node)
+            )));
+        };
+
+        let malformed = || {
+            PyValueError::new_err(format!(
+                "'{}' is not '<number> <unit>': {:?}",
+                path,
+                s.as_str()
+            ))
+        };
+        let (number, unit) = s
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(malformed)?;
+        let unit = unit.trim();
+        let value = if let Ok(i) = number.parse::<i64>() {
+            i.to_object(py)
+        } else if let Ok(f) = number.parse::<f64>() {
+            f.to_object(py)
+        } else {
+            return Err(malformed());
+        };
+
+        match py.import_bound("pint") {
+            Ok(pint) => Ok(pint.getattr("Quantity")?.call1((value, unit))?.into()),
+            Err(_) => Ok(PyTuple::new_bound(py, [value, unit.to_object(py)]).into()),
+        }
+    }
+
+    /// Turn an array of objects (e.g. TOML `[[servers]]`) into a dict keyed
+    /// by each object's `key` field.
+    fn index_array_by(&self, py: Python<'_>, path: &str, key: &str) -> PyResult<PyObject> {
+        let idx = self.require_path(path)?;
+        let archived = self.archived();
+        let node = &archived.nodes[idx as usize];
+        let ArchivedValueNode::Array(indices) = node else {
+            return Err(Self::type_mismatch(path, node, "an array"));
+        };
+
+        let dict = PyDict::new_bound(py);
+        for (pos, child_idx) in indices.iter().enumerate() {
+            let ArchivedValueNode::Object(pairs) = &archived.nodes[*child_idx as usize] else {
+                return Err(PyValueError::new_err(format!(
+                    "'{}[{}]' is not an object",
+                    path, pos
+                )));
+            };
+            let Some(key_idx) = find_key_in_object(pairs, key, self.preserve_order) else {
+                return Err(PyValueError::new_err(format!(
+                    "'{}[{}]' has no '{}' field",
+                    path, pos, key
+                )));
+            };
+            let entry_key = node_to_python(py, &archived.nodes, key_idx)?;
+            if dict.contains(&entry_key)? {
+                return Err(PyValueError::new_err(format!(
+                    "Duplicate '{}' value {} in '{}'",
+                    key,
+                    entry_key.bind(py).repr()?,
+                    path
+                )));
+            }
+            dict.set_item(entry_key, node_to_python(py, &archived.nodes, *child_idx)?)?;
+        }
+        Ok(dict.into())
+    }
+
+    /// Count, for each key seen in the array of objects at `path`, how many
+    /// elements contain it. Keys with a count below `len(array)` mark ragged
+    /// records. Keys are listed in the order first seen.
+    fn array_key_coverage(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        let idx = self.require_path(path)?;
+        let archived = self.archived();
+        let node = &archived.nodes[idx as usize];
+        let ArchivedValueNode::Array(indices) = node else {
+            return Err(Self::type_mismatch(path, node, "an array"));
+        };
+
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for (pos, child_idx) in indices.iter().enumerate() {
+            let ArchivedValueNode::Object(pairs) = &archived.nodes[*child_idx as usize] else {
+                return Err(PyValueError::new_err(format!(
+                    "'{}[{}]' is not an object",
+                    path, pos
+                )));
+            };
+            for pair in pairs.iter() {
+                let key = pair.0.as_str();
+                match positions.get(key) {
+                    Some(&slot) => counts[slot].1 += 1,
+                    None => {
+                        positions.insert(key, counts.len());
+                        counts.push((key, 1));
                     }
                 }
-                _ => {
-                    return Err(PyTypeError::new_err(format!(
-                        "Cannot traverse into {:?}",
-                        Self::node_type_name(node)
-                    )));
+            }
+        }
+
+        let dict = PyDict::new_bound(py);
+        for (key, count) in counts {
+            dict.set_item(key, count)?;
+        }
+        Ok(dict.into())
+    }
+
+    /// Combine an array of flag names into a bitmask using `mapping`
+    /// (name -> bit value). Unknown names raise ValueError.
+    fn get_flags(&self, path: &str, mapping: &Bound<'_, PyDict>) -> PyResult<i64> {
+        let idx = self.require_path(path)?;
+        let archived = self.archived();
+        let ArchivedValueNode::Array(indices) = &archived.nodes[idx as usize] else {
+            return Err(PyTypeError::new_err(format!(
+                "'{}' is not an array of flag names",
+                path
+            )));
+        };
+
+        let mut flags = 0i64;
+        for child_idx in indices.iter() {
+            let ArchivedValueNode::String(name) = &archived.nodes[*child_idx as usize] else {
+                return Err(PyTypeError::new_err(format!(
+                    "'{}' must contain only flag names",
+                    path
+                )));
+            };
+            match mapping.get_item(name.as_str())? {
+                Some(bit) => flags |= bit.extract::<i64>()?,
+                None => {
+                    return Err(PyValueError::new_err(format!(
+                        "Unknown flag '{}' in '{}'",
+                        name.as_str(),
+                        path
+                    )))
                 }
             }
         }
+        Ok(flags)
+    }
 
-        node_to_python(py, &archived.nodes, current_idx)
+    /// `adapter(value)` for the value at `path`, e.g. `get_adapted("port",
+    /// int)`. Raises KeyError when the path is missing; anything the adapter
+    /// raises propagates unchanged.
+    fn get_adapted(&self, py: Python<'_>, path: &str, adapter: PyObject) -> PyResult<PyObject> {
+        let idx = self.require_path(path)?;
+        let value = node_to_python(py, &self.archived().nodes, idx)?;
+        adapter.call1(py, (value,))
     }
 
     fn keys(&self, py: Python<'_>) -> PyResult<PyObject> {
@@ -191,37 +1666,464 @@ impl SnapConfig {
                 for pair in pairs.iter() {
                     list.append(pair.0.as_str())?;
                 }
-                Ok(list.into())
+                Ok(list.into())
+            }
+            _ => Err(PyTypeError::new_err("keys() only works on objects")),
+        }
+    }
+
+    /// `(key, value)` tuples in the same order as `keys()`.
+    fn items(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let archived = self.archived();
+        let root_node = &archived.nodes[self.root_idx as usize];
+
+        match root_node {
+            ArchivedValueNode::Object(pairs) => {
+                let list = PyList::empty_bound(py);
+                for pair in pairs.iter() {
+                    let value = node_to_python(py, &archived.nodes, pair.1)?;
+                    list.append(PyTuple::new_bound(
+                        py,
+                        [pair.0.as_str().to_object(py), value],
+                    ))?;
+                }
+                Ok(list.into())
+            }
+            _ => Err(PyTypeError::new_err("items() only works on objects")),
+        }
+    }
+
+    /// Values in the same order as `keys()` (or the elements of an array).
+    fn values(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let archived = self.archived();
+        let root_node = &archived.nodes[self.root_idx as usize];
+
+        let list = PyList::empty_bound(py);
+        match root_node {
+            ArchivedValueNode::Object(pairs) => {
+                for pair in pairs.iter() {
+                    list.append(node_to_python(py, &archived.nodes, pair.1)?)?;
+                }
+            }
+            ArchivedValueNode::Array(indices) => {
+                for idx in indices.iter() {
+                    list.append(node_to_python(py, &archived.nodes, *idx)?)?;
+                }
+            }
+            _ => {
+                return Err(PyTypeError::new_err(
+                    "values() only works on objects and arrays",
+                ))
+            }
+        }
+        Ok(list.into())
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        let archived = self.archived();
+        let root_node = &archived.nodes[self.root_idx as usize];
+
+        match root_node {
+            ArchivedValueNode::Object(pairs) => Ok(pairs.len()),
+            ArchivedValueNode::Array(indices) => Ok(indices.len()),
+            _ => Err(PyTypeError::new_err("Object has no length")),
+        }
+    }
+
+    /// Dict of dotted path -> `(old, new)` for every value that differs from
+    /// `other` ("" when the roots themselves differ), skipping the `ignore`
+    /// paths and their subtrees. `old` is this config's value and `new` is
+    /// `other`'s; a key only one side has maps to `None` on the other.
+    #[pyo3(signature = (other, ignore=None))]
+    fn diff(
+        &self,
+        py: Python<'_>,
+        other: PyRef<'_, SnapConfig>,
+        ignore: Option<Vec<String>>,
+    ) -> PyResult<PyObject> {
+        let old_nodes = &self.archived().nodes;
+        let new_nodes = &other.archived().nodes;
+        let convert = |nodes, idx: Option<u32>| match idx {
+            Some(idx) => node_to_python(py, nodes, idx),
+            None => Ok(py.None()),
+        };
+        let out = PyDict::new_bound(py);
+        for (path, old, new) in self.diff_with(&other, ignore) {
+            let change = (convert(old_nodes, old)?, convert(new_nodes, new)?);
+            out.set_item(path, change)?;
+        }
+        Ok(out.into())
+    }
+
+    /// Dotted paths where this config's value differs from `defaults` (a
+    /// dict/list tree like `load(defaults=...)` takes), including keys the
+    /// defaults don't have. Keys only the defaults have aren't overrides and
+    /// are left out.
+    fn overrides(&self, defaults: &Bound<'_, PyAny>) -> PyResult<Vec<String>> {
+        let flat = python_to_flat_value(defaults, true)?;
+        let bytes = rkyv::to_bytes::<_, 4096>(&flat)
+            .map_err(|e| PyValueError::new_err(format!("Cannot archive defaults: {}", e)))?;
+        let defaults = rkyv::check_archived_root::<FlatValue>(&bytes)
+            .map_err(|e| PyValueError::new_err(format!("Cannot archive defaults: {}", e)))?;
+        let mut out = Vec::new();
+        diff_nodes(
+            self.diff_side(),
+            self.root_idx,
+            DiffSide {
+                nodes: &defaults.nodes,
+                preserve_order: false,
+            },
+            flat.root.unwrap_or_default(),
+            "",
+            &HashSet::new(),
+            &mut out,
+        );
+        Ok(out
+            .into_iter()
+            .filter(|(_, ours, _)| ours.is_some())
+            .map(|(path, _, _)| path)
+            .collect())
+    }
+
+    /// True when `diff(other, ignore)` finds nothing.
+    #[pyo3(signature = (other, ignore=None))]
+    fn equals(&self, other: PyRef<'_, SnapConfig>, ignore: Option<Vec<String>>) -> bool {
+        self.diff_with(&other, ignore).is_empty()
+    }
+
+    /// Structural equality from each root, whatever cache backs either side.
+    /// Object key order is ignored and arrays compare in order. Floats must
+    /// be exactly equal (so NaN never matches), and an int never equals a
+    /// float.
+    fn __eq__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyObject {
+        let Ok(other) = other.downcast::<SnapConfig>() else {
+            return py.NotImplemented();
+        };
+        let other = other.borrow();
+        same_value(
+            &self.archived().nodes,
+            self.root_idx,
+            &other.archived().nodes,
+            other.root_idx,
+        )
+        .into_py(py)
+    }
+
+    fn __contains__(&self, key: &str) -> PyResult<bool> {
+        let archived = self.archived();
+        let root_node = &archived.nodes[self.root_idx as usize];
+
+        match root_node {
+            ArchivedValueNode::Object(pairs) => {
+                Ok(find_key_in_object(pairs, key, self.preserve_order).is_some())
+            }
+            _ => Err(PyTypeError::new_err("'in' only works on objects")),
+        }
+    }
+
+    /// Convert to Python dict/list (loses zero-copy benefits).
+    /// With `ordered=True` on a `preserve_order` config, objects become
+    /// `collections.OrderedDict`s in document order. With `max_depth`,
+    /// containers nested deeper than that are replaced by summaries such as
+    /// `"<object: 12 keys>"`. NaN and infinite floats are returned as they
+    /// are unless `non_finite` is `"error"` (ValueError) or `"null"` (None).
+    #[pyo3(signature = (ordered=false, max_depth=None, non_finite="keep"))]
+    fn to_dict(
+        &self,
+        py: Python<'_>,
+        ordered: bool,
+        max_depth: Option<usize>,
+        non_finite: &str,
+    ) -> PyResult<PyObject> {
+        let non_finite = NonFinite::parse(non_finite, true)?;
+        let archived = self.archived();
+        if !(ordered && self.preserve_order) && max_depth.is_none() && non_finite == NonFinite::Keep
+        {
+            return node_to_python(py, &archived.nodes, self.root_idx);
+        }
+        let ordered_dict = if ordered && self.preserve_order {
+            Some(py.import_bound("collections")?.getattr("OrderedDict")?)
+        } else {
+            None
+        };
+        node_to_shaped_python(
+            py,
+            &archived.nodes,
+            self.root_idx,
+            ordered_dict.as_ref(),
+            max_depth,
+            non_finite,
+        )
+    }
+
+    /// Flatten to a dict mapping joined paths (`"servers.0.host"`) to leaf
+    /// values. Empty objects and arrays are kept as leaves. Nulls stay
+    /// `None` unless `null_repr` gives a string for them, or are left out
+    /// entirely with `drop_nulls=True`.
+    #[pyo3(signature = (separator=".", null_repr=None, drop_nulls=false))]
+    fn flatten(
+        &self,
+        py: Python<'_>,
+        separator: &str,
+        null_repr: Option<&str>,
+        drop_nulls: bool,
+    ) -> PyResult<PyObject> {
+        let nodes = &self.archived().nodes;
+        let out = PyDict::new_bound(py);
+        self.for_each_leaf(separator, |path, idx| match &nodes[idx as usize] {
+            ArchivedValueNode::Null if drop_nulls => Ok(()),
+            ArchivedValueNode::Null if null_repr.is_some() => out.set_item(path, null_repr),
+            _ => out.set_item(path, node_to_python(py, nodes, idx)?),
+        })?;
+        Ok(out.into())
+    }
+
+    /// Every leaf path, in the order and form of `flatten()`'s keys:
+    /// `["database.host", "database.port", "servers.0", ...]`. No values
+    /// are converted.
+    fn paths(&self) -> PyResult<Vec<String>> {
+        let mut paths = Vec::new();
+        self.for_each_leaf(".", |path, _| {
+            paths.push(path);
+            Ok(())
+        })?;
+        Ok(paths)
+    }
+
+    /// A new in-memory config with every key and string value in Unicode
+    /// normalization `form` ("NFC", "NFD", "NFKC" or "NFKD"). Keys are
+    /// re-sorted, as normalizing can change their order; ValueError if two
+    /// keys of one object normalize to the same string.
+    #[pyo3(signature = (form="NFC"))]
+    fn normalize_unicode(&self, form: &str) -> PyResult<SnapConfig> {
+        use unicode_normalization::UnicodeNormalization;
+
+        let normalize: fn(&str) -> String = match form {
+            "NFC" => |s| s.nfc().collect(),
+            "NFD" => |s| s.nfd().collect(),
+            "NFKC" => |s| s.nfkc().collect(),
+            "NFKD" => |s| s.nfkd().collect(),
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "form must be 'NFC', 'NFD', 'NFKC' or 'NFKD', not '{}'",
+                    form
+                )))
+            }
+        };
+        let mut flat = self.to_flat_value();
+        flat.map_strings(normalize, !self.preserve_order)
+            .map_err(|key| {
+                PyValueError::new_err(format!(
+                    "Keys collide after {} normalization: {:?}",
+                    form, key
+                ))
+            })?;
+        Ok(crate::in_memory_config(
+            &flat,
+            self.preserve_order,
+            self.source_path.clone(),
+        )?)
+    }
+
+    /// Render as `export NAME='value'` lines for `source`-ing. Names follow
+    /// `load(env_override=True)`: `prefix` plus upper-cased path segments
+    /// joined by `sep`, with array items named by index. Characters that
+    /// cannot appear in a shell variable name become `_`; nulls export as
+    /// empty strings and empty objects/arrays are skipped.
+    #[pyo3(signature = (prefix="", sep="_"))]
+    fn to_shell(&self, prefix: &str, sep: &str) -> PyResult<String> {
+        let mut out = String::new();
+        self.for_each_env_var(prefix, sep, |name, value| {
+            out.push_str("export ");
+            out.push_str(&shell_name(&name));
+            out.push_str("='");
+            out.push_str(&value.replace('\'', r"'\''"));
+            out.push_str("'\n");
+        })?;
+        Ok(out)
+    }
+
+    /// A read-only `os.environ`-style mapping over the config, for libraries
+    /// that read settings from an environment map. Names are built as in
+    /// `to_shell` (`database.host` is `APP__DATABASE__HOST` for prefix
+    /// "APP__"), and values are strings as `load_dotenv` writes them.
+    #[pyo3(signature = (prefix="", sep="__"))]
+    fn as_environ(&self, prefix: &str, sep: &str) -> PyResult<EnvironMap> {
+        let mut names = Vec::new();
+        let mut vars = HashMap::new();
+        self.for_each_env_var(prefix, sep, |name, value| {
+            names.push(name.clone());
+            vars.insert(name, value);
+        })?;
+        Ok(EnvironMap { names, vars })
+    }
+
+    /// Serialize to a JSON string without building Python objects first.
+    /// JSON has no NaN or infinity: they raise ValueError, or are written
+    /// as `null` with `non_finite="null"`.
+    #[pyo3(signature = (indent=None, non_finite="error"))]
+    fn to_json(&self, indent: Option<usize>, non_finite: &str) -> PyResult<String> {
+        let non_finite = NonFinite::parse(non_finite, false)?;
+        let archived = self.archived();
+        let mut out = String::new();
+        write_json(
+            &archived.nodes,
+            self.root_idx,
+            indent,
+            non_finite,
+            0,
+            &mut out,
+        )?;
+        Ok(out)
+    }
+
+    /// Canonical JSON for signing, following RFC 8785 (JCS): no whitespace,
+    /// object keys sorted by UTF-16 code units, minimal string escaping and
+    /// ECMAScript number formatting for floats (`1.0` -> `1`, `1e21` ->
+    /// `1e+21`). Ints are written exactly, even past 2**53 where JCS would
+    /// round them through a double. Equal configs give identical strings.
+    fn to_canonical_json(&self) -> PyResult<String> {
+        let archived = self.archived();
+        let mut out = String::new();
+        write_canonical_json(&archived.nodes, self.root_idx, &mut out)?;
+        Ok(out)
+    }
+
+    /// Build a `configparser.ConfigParser`: top-level objects become sections,
+    /// top-level scalars go to `DEFAULT`. Values are stringified as
+    /// `load_dotenv` does; option names keep their case and `%` is not
+    /// interpolated. Anything nested deeper raises ValueError.
+    fn to_configparser(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let nodes = &self.archived().nodes;
+        let ArchivedValueNode::Object(top) = &nodes[self.root_idx as usize] else {
+            return Err(PyValueError::new_err(
+                "to_configparser() needs an object at the root",
+            ));
+        };
+
+        let not_ini = |path: String| {
+            PyValueError::new_err(format!(
+                "'{}' is nested too deeply to be written as INI",
+                path
+            ))
+        };
+        let sections = PyDict::new_bound(py);
+        let defaults = PyDict::new_bound(py);
+        for pair in top.iter() {
+            let name = pair.0.as_str();
+            match &nodes[pair.1 as usize] {
+                ArchivedValueNode::Object(options) => {
+                    let section = PyDict::new_bound(py);
+                    for option in options.iter() {
+                        let value = scalar_to_string(&nodes[option.1 as usize])
+                            .ok_or_else(|| not_ini(format!("{}.{}", name, option.0.as_str())))?;
+                        section.set_item(option.0.as_str(), value)?;
+                    }
+                    sections.set_item(name, section)?;
+                }
+                node => {
+                    let value = scalar_to_string(node).ok_or_else(|| not_ini(name.to_string()))?;
+                    defaults.set_item(name, value)?;
+                }
             }
-            _ => Err(PyTypeError::new_err("keys() only works on objects")),
         }
-    }
-
-    fn __len__(&self) -> PyResult<usize> {
-        let archived = self.archived();
-        let root_node = &archived.nodes[self.root_idx as usize];
 
-        match root_node {
-            ArchivedValueNode::Object(pairs) => Ok(pairs.len()),
-            ArchivedValueNode::Array(indices) => Ok(indices.len()),
-            _ => Err(PyTypeError::new_err("Object has no length")),
+        let configparser = py.import_bound("configparser")?;
+        let kwargs = PyDict::new_bound(py);
+        kwargs.set_item("interpolation", py.None())?;
+        let parser = configparser
+            .getattr("ConfigParser")?
+            .call((), Some(&kwargs))?;
+        parser.setattr("optionxform", py.get_type_bound::<PyString>())?;
+        parser.call_method1("read_dict", (sections,))?;
+        if !defaults.is_empty() {
+            let default_section = PyDict::new_bound(py);
+            default_section.set_item("DEFAULT", defaults)?;
+            parser.call_method1("read_dict", (default_section,))?;
         }
+        Ok(parser.unbind())
     }
 
-    fn __contains__(&self, key: &str) -> PyResult<bool> {
-        let archived = self.archived();
-        let root_node = &archived.nodes[self.root_idx as usize];
+    /// Serialize to MessagePack bytes straight from the cache. Dates and
+    /// times are written as strings.
+    fn to_msgpack(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let node = ArchivedNodeRef {
+            nodes: &self.archived().nodes,
+            idx: self.root_idx,
+        };
+        let bytes = rmp_serde::to_vec(&node).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(pyo3::types::PyBytes::new_bound(py, &bytes).into())
+    }
 
-        match root_node {
-            ArchivedValueNode::Object(pairs) => Ok(find_key_in_object(pairs, key).is_some()),
-            _ => Err(PyTypeError::new_err("'in' only works on objects")),
-        }
+    /// Encode an object root as a serialized `google.protobuf.Struct`.
+    #[cfg(feature = "protobuf")]
+    fn to_protobuf_struct(&self, py: Python<'_>) -> PyResult<PyObject> {
+        use prost::Message;
+
+        let archived = self.archived();
+        let value = node_to_prost_value(&archived.nodes, self.root_idx);
+        let Some(prost_types::value::Kind::StructValue(message)) = value.kind else {
+            return Err(PyTypeError::new_err(
+                "to_protobuf_struct() only works on objects",
+            ));
+        };
+        Ok(pyo3::types::PyBytes::new_bound(py, &message.encode_to_vec()).into())
     }
 
-    /// Convert to Python dict/list (loses zero-copy benefits).
-    fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+    /// A JSON Schema (draft 2020-12) dict inferred from this config: every
+    /// present key is required, and arrays whose items differ get an
+    /// `anyOf` of the distinct item schemas.
+    fn infer_schema(&self, py: Python<'_>) -> PyResult<PyObject> {
         let archived = self.archived();
-        node_to_python(py, &archived.nodes, self.root_idx)
+        let schema = infer_node_schema(py, &archived.nodes, self.root_idx)?;
+        schema.set_item("$schema", "https://json-schema.org/draft/2020-12/schema")?;
+        Ok(schema.into())
+    }
+
+    /// Check the config against a JSON Schema, given as a path to a JSON
+    /// file or as JSON text. Every failure is reported at once, in one
+    /// ValueError with a `path: message` line each. Values are checked as
+    /// `to_json()` would write them (dates and times are strings).
+    #[cfg(feature = "jsonschema")]
+    fn validate(&self, schema: &str) -> PyResult<()> {
+        let text = if std::path::Path::new(schema).is_file() {
+            std::fs::read_to_string(schema)?
+        } else {
+            schema.to_string()
+        };
+        let schema: serde_json::Value = serde_json::from_str(&text).map_err(|e| {
+            PyValueError::new_err(format!(
+                "Schema is neither an existing file nor valid JSON: {}",
+                e
+            ))
+        })?;
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|e| PyValueError::new_err(format!("Invalid JSON Schema: {}", e)))?;
+        let instance = serde_json::to_value(ArchivedNodeRef {
+            nodes: &self.archived().nodes,
+            idx: self.root_idx,
+        })
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+        let failures: Vec<String> = validator
+            .iter_errors(&instance)
+            .map(|error| {
+                format!(
+                    "  {}: {}",
+                    pointer_to_path(error.instance_path.as_str()),
+                    error
+                )
+            })
+            .collect();
+        if failures.is_empty() {
+            return Ok(());
+        }
+        Err(PyValueError::new_err(format!(
+            "Config does not match the schema ({} error{}):\n{}",
+            failures.len(),
+            if failures.len() == 1 { "" } else { "s" },
+            failures.join("\n")
+        )))
     }
 
     fn root_type(&self) -> &'static str {
@@ -248,14 +2150,335 @@ impl SnapConfig {
     }
 }
 
+/// Looks up `key` in an object's pairs. Sorted objects use binary search;
+/// objects compiled with `preserve_order` are scanned linearly.
 pub fn find_key_in_object(
     pairs: &rkyv::vec::ArchivedVec<(rkyv::string::ArchivedString, u32)>,
     key: &str,
+    preserve_order: bool,
 ) -> Option<u32> {
+    find_key_position(pairs, key, preserve_order).map(|pos| pairs[pos].1)
+}
+
+/// `find_key_in_object`, returning the key's position among `pairs`.
+fn find_key_position(
+    pairs: &rkyv::vec::ArchivedVec<(rkyv::string::ArchivedString, u32)>,
+    key: &str,
+    preserve_order: bool,
+) -> Option<usize> {
+    if preserve_order {
+        return pairs.iter().position(|pair| pair.0.as_str() == key);
+    }
+    pairs.binary_search_by(|pair| pair.0.as_str().cmp(key)).ok()
+}
+
+/// Matches `text` against a glob where `*` is any run of characters and `?`
+/// is exactly one.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text index it is currently absorbing up to.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, absorbed)) = backtrack {
+            p = star + 1;
+            t = absorbed + 1;
+            backtrack = Some((star, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Fits `value` to a builtin scalar annotation: strings are parsed as
+/// int/float/bool (`yes`/`no`, `on`/`off` and `1`/`0` count as bools) and
+/// ints widen to float. Other annotations pass the value through. TypeError
+/// names `path` when the value can't be read as the annotated type.
+fn coerce_field(
+    py: Python<'_>,
+    value: PyObject,
+    annotation: &Bound<'_, PyAny>,
+    path: &str,
+) -> PyResult<PyObject> {
+    let bound = value.bind(py);
+    let is_bool = bound.is_instance_of::<PyBool>();
+    let is_int = bound.is_instance_of::<PyInt>() && !is_bool;
+    let text = match bound.downcast::<PyString>() {
+        Ok(s) => Some(s.to_str()?.trim()),
+        Err(_) => None,
+    };
+    let (coerced, expected) = if annotation.is(&py.get_type_bound::<PyBool>()) {
+        let coerced = match text {
+            Some(text) => schema::parse_bool_word(text).map(|b| b.into_py(py)),
+            None => is_bool.then(|| value.clone_ref(py)),
+        };
+        (coerced, "bool")
+    } else if annotation.is(&py.get_type_bound::<PyInt>()) {
+        let coerced = match text {
+            Some(text) => text.parse::<i64>().ok().map(|i| i.into_py(py)),
+            None => is_int.then(|| value.clone_ref(py)),
+        };
+        (coerced, "int")
+    } else if annotation.is(&py.get_type_bound::<PyFloat>()) {
+        let coerced = match text {
+            Some(text) => text.parse::<f64>().ok().map(|f| f.into_py(py)),
+            None if is_int => Some(bound.extract::<f64>()?.into_py(py)),
+            None => bound
+                .is_instance_of::<PyFloat>()
+                .then(|| value.clone_ref(py)),
+        };
+        (coerced, "float")
+    } else if annotation.is(&py.get_type_bound::<PyString>()) {
+        (text.is_some().then(|| value.clone_ref(py)), "str")
+    } else {
+        return Ok(value);
+    };
+
+    match coerced {
+        Some(coerced) => Ok(coerced),
+        None => Err(PyTypeError::new_err(format!(
+            "'{}' is {}, expected {}",
+            path,
+            bound.get_type().name()?,
+            expected
+        ))),
+    }
+}
+
+/// Linear scan for the position of `key` ignoring ASCII case; the first match in
+/// stored order wins. Sorted pairs can't be binary-searched this way.
+fn find_key_ignore_case(
+    pairs: &rkyv::vec::ArchivedVec<(rkyv::string::ArchivedString, u32)>,
+    key: &str,
+) -> Option<usize> {
     pairs
-        .binary_search_by(|pair| pair.0.as_str().cmp(key))
-        .ok()
-        .map(|idx| pairs[idx].1)
+        .iter()
+        .position(|pair| pair.0.as_str().eq_ignore_ascii_case(key))
+}
+
+/// Text form of a scalar for env/INI output; None for arrays and objects.
+pub(crate) fn scalar_to_string(node: &ArchivedValueNode) -> Option<String> {
+    Some(match node {
+        ArchivedValueNode::String(s) | ArchivedValueNode::Datetime(_, s) => s.as_str().to_string(),
+        ArchivedValueNode::Bytes(b) => encode_base64(b),
+        ArchivedValueNode::Int(i) => i.to_string(),
+        ArchivedValueNode::UInt(u) => u.to_string(),
+        ArchivedValueNode::BigInt(digits) => digits.as_str().to_string(),
+        ArchivedValueNode::Float(f) => f.to_string(),
+        ArchivedValueNode::Bool(b) => if *b { "true" } else { "false" }.to_string(),
+        ArchivedValueNode::Null => String::new(),
+        ArchivedValueNode::Array(_) | ArchivedValueNode::Object(_) => return None,
+    })
+}
+
+/// Text formats have no binary type, so `Bytes` nodes are written as
+/// standard base64.
+fn encode_base64(bytes: &[u8]) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+/// Total microseconds in a duration like `"1h30m"` or `"1.5d 2h"`: one or
+/// more `<amount><unit>` parts, optionally separated by spaces.
+fn parse_duration_micros(text: &str) -> Option<i64> {
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let mut total = 0.0f64;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let amount: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "w" => 604_800_000_000.0,
+            "d" => 86_400_000_000.0,
+            "h" => 3_600_000_000.0,
+            "m" => 60_000_000.0,
+            "s" => 1_000_000.0,
+            "ms" => 1_000.0,
+            "us" => 1.0,
+            _ => return None,
+        };
+        total += amount * scale;
+        rest = rest[unit_len..].trim_start();
+    }
+    let total = total.round();
+    (total < i64::MAX as f64).then_some(total as i64)
+}
+
+/// `name` as a valid shell variable name: `[A-Za-z_][A-Za-z0-9_]*`.
+fn shell_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !out.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        out.insert(0, '_');
+    }
+    out
+}
+
+/// Resolves a path with the same dotted and `[N]` syntax as `get`, without
+/// Python error reporting; `None` when any segment is missing, malformed or
+/// can't be traversed.
+pub(crate) fn lookup_path(
+    nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    root_idx: u32,
+    path: &str,
+) -> Option<u32> {
+    // A linear scan is correct for both sorted and ordered caches.
+    let step = |idx: u32, key: &str| match &nodes[idx as usize] {
+        ArchivedValueNode::Object(pairs) => find_key_in_object(pairs, key, true),
+        ArchivedValueNode::Array(indices) => indices.get(key.parse::<usize>().ok()?).copied(),
+        _ => None,
+    };
+    let mut current_idx = root_idx;
+    for part in path.split('.') {
+        // As in `resolve_path_slot`, a key spelled `b[2]` beats indexing `b`.
+        if let Some(idx) = part
+            .contains('[')
+            .then(|| step(current_idx, part))
+            .flatten()
+        {
+            current_idx = idx;
+            continue;
+        }
+        let (key, mut brackets) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if !key.is_empty() || brackets.is_empty() {
+            current_idx = step(current_idx, key)?;
+        }
+        while !brackets.is_empty() {
+            let (idx, rest) = split_index(brackets).ok()?;
+            brackets = rest;
+            let ArchivedValueNode::Array(indices) = &nodes[current_idx as usize] else {
+                return None;
+            };
+            current_idx = *indices.get(idx)?;
+        }
+    }
+    Some(current_idx)
+}
+
+/// Structural equality between two archived subtrees, ignoring key order.
+pub(crate) fn same_value(
+    a_nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    a_idx: u32,
+    b_nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    b_idx: u32,
+) -> bool {
+    match (&a_nodes[a_idx as usize], &b_nodes[b_idx as usize]) {
+        (ArchivedValueNode::Null, ArchivedValueNode::Null) => true,
+        (ArchivedValueNode::Bool(a), ArchivedValueNode::Bool(b)) => a == b,
+        (ArchivedValueNode::Int(a), ArchivedValueNode::Int(b)) => a == b,
+        (ArchivedValueNode::UInt(a), ArchivedValueNode::UInt(b)) => a == b,
+        (ArchivedValueNode::BigInt(a), ArchivedValueNode::BigInt(b)) => a == b,
+        (ArchivedValueNode::Float(a), ArchivedValueNode::Float(b)) => a == b,
+        (ArchivedValueNode::String(a), ArchivedValueNode::String(b)) => a == b,
+        (ArchivedValueNode::Bytes(a), ArchivedValueNode::Bytes(b)) => a == b,
+        (ArchivedValueNode::Datetime(_, a), ArchivedValueNode::Datetime(_, b)) => a == b,
+        (ArchivedValueNode::Array(a), ArchivedValueNode::Array(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b.iter())
+                    .all(|(x, y)| same_value(a_nodes, *x, b_nodes, *y))
+        }
+        (ArchivedValueNode::Object(a), ArchivedValueNode::Object(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|pair| {
+                    find_key_in_object(b, pair.0.as_str(), true)
+                        .is_some_and(|y| same_value(a_nodes, pair.1, b_nodes, y))
+                })
+        }
+        _ => false,
+    }
+}
+
+/// One difference found by `diff_nodes`: the dotted path and the node on
+/// each side, `None` where that side lacks the path.
+type NodeDiff = (String, Option<u32>, Option<u32>);
+
+/// One side of a `diff_nodes` comparison: its nodes and whether its
+/// objects keep source order, which decides how keys are looked up in them.
+#[derive(Clone, Copy)]
+struct DiffSide<'a> {
+    nodes: &'a rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    preserve_order: bool,
+}
+
+/// Appends the dotted paths where two archived subtrees differ. Paths in
+/// `ignore` are skipped along with everything below them. Keys present on
+/// only one side and array items past the shorter length count as differences.
+fn diff_nodes(
+    a: DiffSide<'_>,
+    a_idx: u32,
+    b: DiffSide<'_>,
+    b_idx: u32,
+    path: &str,
+    ignore: &HashSet<String>,
+    out: &mut Vec<NodeDiff>,
+) {
+    let child_path = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+
+    match (&a.nodes[a_idx as usize], &b.nodes[b_idx as usize]) {
+        (ArchivedValueNode::Object(a_pairs), ArchivedValueNode::Object(b_pairs)) => {
+            for pair in a_pairs.iter() {
+                let child = child_path(pair.0.as_str());
+                if ignore.contains(&child) {
+                    continue;
+                }
+                match find_key_in_object(b_pairs, pair.0.as_str(), b.preserve_order) {
+                    Some(other) => diff_nodes(a, pair.1, b, other, &child, ignore, out),
+                    None => out.push((child, Some(pair.1), None)),
+                }
+            }
+            for pair in b_pairs.iter() {
+                let child = child_path(pair.0.as_str());
+                if !ignore.contains(&child)
+                    && find_key_in_object(a_pairs, pair.0.as_str(), a.preserve_order).is_none()
+                {
+                    out.push((child, None, Some(pair.1)));
+                }
+            }
+        }
+        (ArchivedValueNode::Array(a_items), ArchivedValueNode::Array(b_items)) => {
+            for pos in 0..a_items.len().max(b_items.len()) {
+                let child = child_path(&pos.to_string());
+                if ignore.contains(&child) {
+                    continue;
+                }
+                match (a_items.get(pos), b_items.get(pos)) {
+                    (Some(x), Some(y)) => diff_nodes(a, *x, b, *y, &child, ignore, out),
+                    (x, y) => out.push((child, x.copied(), y.copied())),
+                }
+            }
+        }
+        _ => {
+            if !same_value(a.nodes, a_idx, b.nodes, b_idx) {
+                out.push((path.to_string(), Some(a_idx), Some(b_idx)));
+            }
+        }
+    }
 }
 
 fn get_item_from_node(
@@ -263,13 +2486,14 @@ fn get_item_from_node(
     nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
     node: &ArchivedValueNode,
     key: &Bound<'_, PyAny>,
+    preserve_order: bool,
 ) -> PyResult<PyObject> {
     if let Ok(key_str) = key.downcast::<PyString>() {
         let key_str = key_str.to_str()?;
 
         match node {
             ArchivedValueNode::Object(pairs) => {
-                if let Some(idx) = find_key_in_object(pairs, key_str) {
+                if let Some(idx) = find_key_in_object(pairs, key_str, preserve_order) {
                     node_to_python(py, nodes, idx)
                 } else {
                     Err(PyKeyError::new_err(format!("Key not found: {}", key_str)))
@@ -306,8 +2530,12 @@ pub fn node_to_python(
         ArchivedValueNode::Null => Ok(py.None()),
         ArchivedValueNode::Bool(b) => Ok(b.to_object(py)),
         ArchivedValueNode::Int(i) => Ok(i.to_object(py)),
+        ArchivedValueNode::UInt(u) => Ok(u.to_object(py)),
+        ArchivedValueNode::BigInt(digits) => big_int_to_python(py, digits),
         ArchivedValueNode::Float(f) => Ok(f.to_object(py)),
         ArchivedValueNode::String(s) => Ok(s.as_str().to_object(py)),
+        ArchivedValueNode::Bytes(b) => Ok(PyBytes::new_bound(py, b).into()),
+        ArchivedValueNode::Datetime(_, text) => datetime_to_python(py, text.as_str()),
         ArchivedValueNode::Array(indices) => {
             let list = PyList::empty_bound(py);
             for child_idx in indices.iter() {
@@ -327,6 +2555,557 @@ pub fn node_to_python(
     }
 }
 
+/// A JSON Pointer (`/servers/0/host`) as a dotted path (`servers.0.host`);
+/// the root is `(root)`.
+#[cfg(feature = "jsonschema")]
+fn pointer_to_path(pointer: &str) -> String {
+    if pointer.is_empty() {
+        return "(root)".to_string();
+    }
+    pointer
+        .trim_start_matches('/')
+        .split('/')
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// JSON Schema for the node at `idx`; see `SnapConfig::infer_schema`.
+fn infer_node_schema<'py>(
+    py: Python<'py>,
+    nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    idx: u32,
+) -> PyResult<Bound<'py, PyDict>> {
+    let schema = PyDict::new_bound(py);
+    match &nodes[idx as usize] {
+        ArchivedValueNode::Null => schema.set_item("type", "null")?,
+        ArchivedValueNode::Bool(_) => schema.set_item("type", "boolean")?,
+        ArchivedValueNode::Int(_) | ArchivedValueNode::UInt(_) | ArchivedValueNode::BigInt(_) => {
+            schema.set_item("type", "integer")?
+        }
+        ArchivedValueNode::Float(_) => schema.set_item("type", "number")?,
+        ArchivedValueNode::String(_) => schema.set_item("type", "string")?,
+        ArchivedValueNode::Bytes(_) => {
+            schema.set_item("type", "string")?;
+            schema.set_item("contentEncoding", "base64")?;
+        }
+        ArchivedValueNode::Datetime(kind, _) => {
+            schema.set_item("type", "string")?;
+            let format = match kind {
+                ArchivedDatetimeKind::OffsetDateTime | ArchivedDatetimeKind::LocalDateTime => {
+                    "date-time"
+                }
+                ArchivedDatetimeKind::LocalDate => "date",
+                ArchivedDatetimeKind::LocalTime => "time",
+            };
+            schema.set_item("format", format)?;
+        }
+        ArchivedValueNode::Array(indices) => {
+            schema.set_item("type", "array")?;
+            let mut variants: Vec<Bound<'py, PyDict>> = Vec::new();
+            for child_idx in indices.iter() {
+                let item = infer_node_schema(py, nodes, *child_idx)?;
+                let mut seen = false;
+                for variant in &variants {
+                    if variant.eq(&item)? {
+                        seen = true;
+                        break;
+                    }
+                }
+                if !seen {
+                    variants.push(item);
+                }
+            }
+            match variants.len() {
+                0 => {}
+                1 => schema.set_item("items", variants.pop())?,
+                _ => {
+                    let any_of = PyDict::new_bound(py);
+                    any_of.set_item("anyOf", PyList::new_bound(py, variants))?;
+                    schema.set_item("items", any_of)?;
+                }
+            }
+        }
+        ArchivedValueNode::Object(pairs) => {
+            schema.set_item("type", "object")?;
+            let properties = PyDict::new_bound(py);
+            let required = PyList::empty_bound(py);
+            for pair in pairs.iter() {
+                properties.set_item(pair.0.as_str(), infer_node_schema(py, nodes, pair.1)?)?;
+                required.append(pair.0.as_str())?;
+            }
+            schema.set_item("properties", properties)?;
+            schema.set_item("required", required)?;
+        }
+    }
+    Ok(schema)
+}
+
+/// `int(digits)`: Python ints have no size limit, so nothing is lost.
+fn big_int_to_python(py: Python<'_>, digits: &str) -> PyResult<PyObject> {
+    Ok(py.get_type_bound::<PyInt>().call1((digits,))?.unbind())
+}
+
+/// Converts stored TOML date/time text into `datetime.datetime`, `date` or
+/// `time`; values with an offset become timezone-aware.
+fn datetime_to_python(py: Python<'_>, text: &str) -> PyResult<PyObject> {
+    use toml::value::{Datetime, Offset};
+
+    let dt: Datetime = text
+        .parse()
+        .map_err(|e| PyValueError::new_err(format!("Invalid datetime {:?}: {}", text, e)))?;
+    let tzinfo = match dt.offset {
+        None => None,
+        Some(Offset::Z) => Some(timezone_utc_bound(py)),
+        Some(Offset::Custom { minutes }) => {
+            let delta = PyDelta::new_bound(py, 0, i32::from(minutes) * 60, 0, true)?;
+            let timezone = py.import_bound("datetime")?.getattr("timezone")?;
+            Some(timezone.call1((delta,))?.downcast_into::<PyTzInfo>()?)
+        }
+    };
+
+    match (dt.date, dt.time) {
+        (Some(d), Some(t)) => Ok(PyDateTime::new_bound(
+            py,
+            i32::from(d.year),
+            d.month,
+            d.day,
+            t.hour,
+            t.minute,
+            t.second,
+            t.nanosecond / 1000,
+            tzinfo.as_ref(),
+        )?
+        .into()),
+        (Some(d), None) => Ok(PyDate::new_bound(py, i32::from(d.year), d.month, d.day)?.into()),
+        (None, Some(t)) => {
+            Ok(
+                PyTime::new_bound(py, t.hour, t.minute, t.second, t.nanosecond / 1000, None)?
+                    .into(),
+            )
+        }
+        (None, None) => Err(PyValueError::new_err(format!(
+            "Invalid datetime {:?}",
+            text
+        ))),
+    }
+}
+
+/// What `to_json`/`to_dict` do with NaN and infinite floats.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NonFinite {
+    /// Return them as Python floats.
+    Keep,
+    /// Raise ValueError.
+    Error,
+    /// Replace them with null / None.
+    Null,
+}
+
+impl NonFinite {
+    fn parse(value: &str, allow_keep: bool) -> PyResult<Self> {
+        match value {
+            "keep" if allow_keep => Ok(NonFinite::Keep),
+            "error" => Ok(NonFinite::Error),
+            "null" => Ok(NonFinite::Null),
+            _ if allow_keep => Err(PyValueError::new_err(format!(
+                "non_finite must be 'keep', 'error' or 'null', not '{}'",
+                value
+            ))),
+            _ => Err(PyValueError::new_err(format!(
+                "non_finite must be 'error' or 'null', not '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+/// `node_to_python` for `to_dict` options: objects are built with
+/// `ordered_dict` when given, containers below `depth_left` levels are
+/// summarized instead of converted, and non-finite floats are handled as
+/// `non_finite` says.
+fn node_to_shaped_python(
+    py: Python<'_>,
+    nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    idx: u32,
+    ordered_dict: Option<&Bound<'_, PyAny>>,
+    depth_left: Option<usize>,
+    non_finite: NonFinite,
+) -> PyResult<PyObject> {
+    if let ArchivedValueNode::Float(f) = &nodes[idx as usize] {
+        if !f.is_finite() {
+            match non_finite {
+                NonFinite::Keep => {}
+                NonFinite::Error => {
+                    return Err(PyValueError::new_err(format!(
+                        "Config holds a non-finite float ({})",
+                        f
+                    )))
+                }
+                NonFinite::Null => return Ok(py.None()),
+            }
+        }
+    }
+    let child_depth = match depth_left {
+        Some(0) => {
+            let summary = match &nodes[idx as usize] {
+                ArchivedValueNode::Array(indices) => {
+                    let n = indices.len();
+                    format!("<array: {} item{}>", n, if n == 1 { "" } else { "s" })
+                }
+                ArchivedValueNode::Object(pairs) => {
+                    let n = pairs.len();
+                    format!("<object: {} key{}>", n, if n == 1 { "" } else { "s" })
+                }
+                _ => return node_to_python(py, nodes, idx),
+            };
+            return Ok(summary.to_object(py));
+        }
+        Some(depth) => Some(depth - 1),
+        None => None,
+    };
+
+    match &nodes[idx as usize] {
+        ArchivedValueNode::Array(indices) => {
+            let list = PyList::empty_bound(py);
+            for child_idx in indices.iter() {
+                list.append(node_to_shaped_python(
+                    py,
+                    nodes,
+                    *child_idx,
+                    ordered_dict,
+                    child_depth,
+                    non_finite,
+                )?)?;
+            }
+            Ok(list.into())
+        }
+        ArchivedValueNode::Object(pairs) => {
+            let dict = match ordered_dict {
+                Some(ordered_dict) => ordered_dict.call0()?,
+                None => PyDict::new_bound(py).into_any(),
+            };
+            for pair in pairs.iter() {
+                let value = node_to_shaped_python(
+                    py,
+                    nodes,
+                    pair.1,
+                    ordered_dict,
+                    child_depth,
+                    non_finite,
+                )?;
+                dict.set_item(pair.0.as_str(), value)?;
+            }
+            Ok(dict.into())
+        }
+        _ => node_to_python(py, nodes, idx),
+    }
+}
+
+#[cfg(feature = "protobuf")]
+fn node_to_prost_value(
+    nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    idx: u32,
+) -> prost_types::Value {
+    use prost_types::value::Kind;
+
+    let kind = match &nodes[idx as usize] {
+        ArchivedValueNode::Null => Kind::NullValue(prost_types::NullValue::NullValue as i32),
+        ArchivedValueNode::Bool(b) => Kind::BoolValue(*b),
+        ArchivedValueNode::Int(i) => Kind::NumberValue(*i as f64),
+        ArchivedValueNode::UInt(u) => Kind::NumberValue(*u as f64),
+        ArchivedValueNode::BigInt(digits) => Kind::NumberValue(digits.parse().unwrap_or(f64::NAN)),
+        ArchivedValueNode::Float(f) => Kind::NumberValue(*f),
+        ArchivedValueNode::String(s) | ArchivedValueNode::Datetime(_, s) => {
+            Kind::StringValue(s.as_str().to_string())
+        }
+        ArchivedValueNode::Bytes(b) => Kind::StringValue(encode_base64(b)),
+        ArchivedValueNode::Array(indices) => Kind::ListValue(prost_types::ListValue {
+            values: indices
+                .iter()
+                .map(|child_idx| node_to_prost_value(nodes, *child_idx))
+                .collect(),
+        }),
+        ArchivedValueNode::Object(pairs) => Kind::StructValue(prost_types::Struct {
+            fields: pairs
+                .iter()
+                .map(|pair| {
+                    (
+                        pair.0.as_str().to_string(),
+                        node_to_prost_value(nodes, pair.1),
+                    )
+                })
+                .collect(),
+        }),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+/// Serde view of an archived node, so serializers can walk the cache
+/// without building an owned tree first.
+struct ArchivedNodeRef<'a> {
+    nodes: &'a rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    idx: u32,
+}
+
+impl serde::Serialize for ArchivedNodeRef<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+
+        match &self.nodes[self.idx as usize] {
+            ArchivedValueNode::Null => serializer.serialize_unit(),
+            ArchivedValueNode::Bool(b) => serializer.serialize_bool(*b),
+            ArchivedValueNode::Int(i) => serializer.serialize_i64(*i),
+            ArchivedValueNode::UInt(u) => serializer.serialize_u64(*u),
+            // Past 128 bits serde has no integer type left; keep the digits.
+            ArchivedValueNode::BigInt(digits) => match (digits.parse::<i128>(), digits.parse()) {
+                (Ok(i), _) => serializer.serialize_i128(i),
+                (_, Ok(u)) => serializer.serialize_u128(u),
+                _ => serializer.serialize_str(digits),
+            },
+            ArchivedValueNode::Float(f) => serializer.serialize_f64(*f),
+            ArchivedValueNode::String(s) | ArchivedValueNode::Datetime(_, s) => {
+                serializer.serialize_str(s.as_str())
+            }
+            ArchivedValueNode::Bytes(b) => serializer.serialize_str(&encode_base64(b)),
+            ArchivedValueNode::Array(indices) => {
+                let mut seq = serializer.serialize_seq(Some(indices.len()))?;
+                for &idx in indices.iter() {
+                    seq.serialize_element(&ArchivedNodeRef {
+                        nodes: self.nodes,
+                        idx,
+                    })?;
+                }
+                seq.end()
+            }
+            ArchivedValueNode::Object(pairs) => {
+                let mut map = serializer.serialize_map(Some(pairs.len()))?;
+                for pair in pairs.iter() {
+                    map.serialize_entry(
+                        pair.0.as_str(),
+                        &ArchivedNodeRef {
+                            nodes: self.nodes,
+                            idx: pair.1,
+                        },
+                    )?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_json_newline(indent: Option<usize>, depth: usize, out: &mut String) {
+    if let Some(width) = indent {
+        out.push('\n');
+        out.push_str(&" ".repeat(width * depth));
+    }
+}
+
+fn write_json(
+    nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    idx: u32,
+    indent: Option<usize>,
+    non_finite: NonFinite,
+    depth: usize,
+    out: &mut String,
+) -> PyResult<()> {
+    match &nodes[idx as usize] {
+        ArchivedValueNode::Null => out.push_str("null"),
+        ArchivedValueNode::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        ArchivedValueNode::Int(i) => out.push_str(&i.to_string()),
+        ArchivedValueNode::UInt(u) => out.push_str(&u.to_string()),
+        ArchivedValueNode::BigInt(digits) => out.push_str(digits),
+        ArchivedValueNode::Float(f) if !f.is_finite() && non_finite == NonFinite::Null => {
+            out.push_str("null")
+        }
+        ArchivedValueNode::Float(f) => {
+            if !f.is_finite() {
+                return Err(PyValueError::new_err(format!(
+                    "Cannot serialize {} as JSON",
+                    f
+                )));
+            }
+            // Keep integral floats distinguishable from ints (`1.0`, not `1`).
+            let mut text = f.to_string();
+            if !text.contains(['.', 'e', 'E']) {
+                text.push_str(".0");
+            }
+            out.push_str(&text);
+        }
+        ArchivedValueNode::String(s) | ArchivedValueNode::Datetime(_, s) => {
+            write_json_string(s.as_str(), out)
+        }
+        ArchivedValueNode::Bytes(b) => write_json_string(&encode_base64(b), out),
+        ArchivedValueNode::Array(indices) => {
+            if indices.is_empty() {
+                out.push_str("[]");
+                return Ok(());
+            }
+            out.push('[');
+            for (i, child_idx) in indices.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_newline(indent, depth + 1, out);
+                write_json(nodes, *child_idx, indent, non_finite, depth + 1, out)?;
+            }
+            write_json_newline(indent, depth, out);
+            out.push(']');
+        }
+        ArchivedValueNode::Object(pairs) => {
+            if pairs.is_empty() {
+                out.push_str("{}");
+                return Ok(());
+            }
+            out.push('{');
+            for (i, pair) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_newline(indent, depth + 1, out);
+                write_json_string(pair.0.as_str(), out);
+                out.push_str(if indent.is_some() { ": " } else { ":" });
+                write_json(nodes, pair.1, indent, non_finite, depth + 1, out)?;
+            }
+            write_json_newline(indent, depth, out);
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+fn write_canonical_json(
+    nodes: &rkyv::vec::ArchivedVec<ArchivedValueNode>,
+    idx: u32,
+    out: &mut String,
+) -> PyResult<()> {
+    match &nodes[idx as usize] {
+        ArchivedValueNode::Float(f) => {
+            if !f.is_finite() {
+                return Err(PyValueError::new_err(format!(
+                    "Cannot serialize {} as JSON",
+                    f
+                )));
+            }
+            out.push_str(&ecmascript_number(*f));
+        }
+        ArchivedValueNode::String(s) | ArchivedValueNode::Datetime(_, s) => {
+            write_canonical_string(s.as_str(), out)
+        }
+        ArchivedValueNode::Array(indices) => {
+            out.push('[');
+            for (i, child_idx) in indices.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(nodes, *child_idx, out)?;
+            }
+            out.push(']');
+        }
+        ArchivedValueNode::Object(pairs) => {
+            // Stored keys sort by UTF-8 bytes (or not at all with
+            // preserve_order); JCS orders them by UTF-16 code units.
+            let mut sorted: Vec<_> = pairs.iter().collect();
+            sorted.sort_by(|a, b| a.0.as_str().encode_utf16().cmp(b.0.as_str().encode_utf16()));
+            out.push('{');
+            for (i, pair) in sorted.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_string(pair.0.as_str(), out);
+                out.push(':');
+                write_canonical_json(nodes, pair.1, out)?;
+            }
+            out.push('}');
+        }
+        _ => write_json(nodes, idx, None, NonFinite::Error, 0, out)?,
+    }
+    Ok(())
+}
+
+/// JCS string escaping: only `"`, `\` and control characters, using the
+/// short forms where JSON has them.
+fn write_canonical_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// A finite float as ECMAScript's `Number.prototype.toString` writes it:
+/// shortest round-trip digits, plain notation when `1e-6 <= |f| < 1e21`
+/// and `e+N`/`e-N` otherwise.
+fn ecmascript_number(f: f64) -> String {
+    if f == 0.0 {
+        return "0".to_string();
+    }
+    // `{:e}` gives the shortest round-trip digits as `d.ddde-N`.
+    let sci = format!("{:e}", f.abs());
+    let (mantissa, exponent) = sci.split_once('e').expect("`{:e}` always has an exponent");
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    // Decimal point position: the value is 0.<digits> * 10^n.
+    let n = exponent
+        .parse::<i32>()
+        .expect("`{:e}` exponent is an integer")
+        + 1;
+
+    let mut out = String::new();
+    if f < 0.0 {
+        out.push('-');
+    }
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        out.push_str(&"0".repeat((n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.push_str(&"0".repeat((-n) as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if n > 0 { '+' } else { '-' });
+        out.push_str(&(n - 1).abs().to_string());
+    }
+    out
+}
+
 /// Converts FlatValue to Python object (for loads() which doesn't use mmap).
 pub fn flat_value_to_python(py: Python<'_>, flat: &crate::value::FlatValue) -> PyResult<PyObject> {
     use crate::value::ValueNode;
@@ -338,8 +3117,12 @@ pub fn flat_value_to_python(py: Python<'_>, flat: &crate::value::FlatValue) -> P
             ValueNode::Null => Ok(py.None()),
             ValueNode::Bool(b) => Ok(b.to_object(py)),
             ValueNode::Int(i) => Ok(i.to_object(py)),
+            ValueNode::UInt(u) => Ok(u.to_object(py)),
+            ValueNode::BigInt(digits) => big_int_to_python(py, digits),
             ValueNode::Float(f) => Ok(f.to_object(py)),
             ValueNode::String(s) => Ok(s.to_object(py)),
+            ValueNode::Bytes(b) => Ok(PyBytes::new_bound(py, b).into()),
+            ValueNode::Datetime(_, text) => datetime_to_python(py, text),
             ValueNode::Array(indices) => {
                 let list = PyList::empty_bound(py);
                 for &child_idx in indices {
@@ -362,3 +3145,58 @@ pub fn flat_value_to_python(py: Python<'_>, flat: &crate::value::FlatValue) -> P
         .ok_or_else(|| PyValueError::new_err("FlatValue missing root node"))?;
     convert(py, &flat.nodes, root_idx)
 }
+
+/// Builds a FlatValue from plain Python data: None, bool, int, float, str,
+/// lists/tuples and dicts with str keys. Keys are sorted when `sort_keys`.
+pub fn python_to_flat_value(obj: &Bound<'_, PyAny>, sort_keys: bool) -> PyResult<FlatValue> {
+    use crate::value::ValueNode;
+
+    fn convert(flat: &mut FlatValue, obj: &Bound<'_, PyAny>, sort_keys: bool) -> PyResult<u32> {
+        let node = if obj.is_none() {
+            ValueNode::Null
+        } else if let Ok(b) = obj.downcast::<PyBool>() {
+            ValueNode::Bool(b.is_true())
+        } else if obj.is_instance_of::<PyInt>() {
+            match (obj.extract::<i64>(), obj.extract::<u64>()) {
+                (Ok(i), _) => ValueNode::Int(i),
+                (_, Ok(u)) => ValueNode::UInt(u),
+                _ => ValueNode::BigInt(obj.str()?.to_str()?.to_string()),
+            }
+        } else if let Ok(f) = obj.downcast::<PyFloat>() {
+            ValueNode::Float(f.value())
+        } else if let Ok(s) = obj.downcast::<PyString>() {
+            ValueNode::String(s.to_str()?.to_string())
+        } else if let Ok(b) = obj.downcast::<PyBytes>() {
+            ValueNode::Bytes(b.as_bytes().to_vec())
+        } else if let Ok(dict) = obj.downcast::<PyDict>() {
+            let mut pairs = Vec::with_capacity(dict.len());
+            for (key, value) in dict.iter() {
+                let key: String = key.extract().map_err(|_| {
+                    PyTypeError::new_err(format!("Config keys must be str, not {}", key.get_type()))
+                })?;
+                pairs.push((key, convert(flat, &value, sort_keys)?));
+            }
+            if sort_keys {
+                pairs.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+            ValueNode::Object(pairs)
+        } else if obj.is_instance_of::<PyList>() || obj.is_instance_of::<PyTuple>() {
+            let indices = obj
+                .iter()?
+                .map(|item| convert(flat, &item?, sort_keys))
+                .collect::<PyResult<Vec<_>>>()?;
+            ValueNode::Array(indices)
+        } else {
+            return Err(PyTypeError::new_err(format!(
+                "Cannot store {} in a config",
+                obj.get_type()
+            )));
+        };
+        Ok(flat.add_node(node))
+    }
+
+    let mut flat = FlatValue::new();
+    let root_idx = convert(&mut flat, obj, sort_keys)?;
+    flat.set_root(root_idx);
+    Ok(flat)
+}
@@ -4,13 +4,28 @@ use pyo3::exceptions::{PyIOError, PyValueError};
 use pyo3::PyErr;
 use thiserror::Error;
 
+// pyo3 0.22's macro expansion checks a `gil-refs` feature this crate lacks.
+#[allow(unexpected_cfgs)]
+mod warning {
+    pyo3::create_exception!(
+        snapconfig,
+        SnapconfigWarning,
+        pyo3::exceptions::PyUserWarning,
+        "Soft issue found while parsing a config (only raised with emit_warnings=True)."
+    );
+}
+pub use warning::SnapconfigWarning;
+
 #[derive(Error, Debug)]
 pub enum SnapconfigError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
     #[error("JSON parse error: {0}")]
-    JsonParse(#[from] simd_json::Error),
+    JsonParse(#[from] serde_json::Error),
+
+    #[error("JSON5 parse error: {0}")]
+    Json5Parse(String),
 
     #[error("YAML parse error: {0}")]
     YamlParse(#[from] serde_yaml::Error),
@@ -21,6 +36,18 @@ pub enum SnapconfigError {
     #[error("INI parse error: {0}")]
     IniParse(String),
 
+    #[error("Properties parse error: {0}")]
+    PropertiesParse(String),
+
+    #[error("XML parse error: {0}")]
+    XmlParse(String),
+
+    #[error("RON parse error: {0}")]
+    RonParse(String),
+
+    #[error("CSV parse error: {0}")]
+    CsvParse(String),
+
     #[error("Serialization error: {0}")]
     Serialize(String),
 
@@ -30,10 +57,51 @@ pub enum SnapconfigError {
     #[error("Unknown format: {0}")]
     UnknownFormat(String),
 
+    #[error("Bad condition: {0}")]
+    BadCondition(String),
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("Duplicate key: {0}")]
+    DuplicateKey(String),
+
+    #[error("Non-finite float: {0}")]
+    NonFiniteFloat(String),
+
+    #[error("Number overflow: {0}")]
+    NumberOverflow(String),
+
+    /// An object or array over `max_keys`/`max_array_len`. `path` holds the
+    /// container's segments, empty for the root.
+    #[error("Limit exceeded: {} has {len} {what} ({option}={max})", limit_subject(.path))]
+    LimitExceeded {
+        path: Vec<String>,
+        len: usize,
+        what: &'static str,
+        option: &'static str,
+        max: usize,
+    },
+
+    #[error("Schema violation: {0}")]
+    SchemaViolation(String),
+
+    /// A parse error prefixed with `file:line:column`.
+    #[error("{0}")]
+    Located(String),
+
     #[error("Invalid cache: {0}")]
     InvalidCache(String),
 }
 
+fn limit_subject(path: &[String]) -> String {
+    if path.is_empty() {
+        "the root".to_string()
+    } else {
+        format!("'{}'", path.join("."))
+    }
+}
+
 impl From<SnapconfigError> for PyErr {
     fn from(err: SnapconfigError) -> PyErr {
         match err {
@@ -5,38 +5,311 @@
 //!
 //! Supported formats: JSON, YAML, TOML, INI, dotenv
 
+// pyo3 0.22's generated wrappers convert `PyErr` into itself.
+#![allow(clippy::useless_conversion)]
+
+pub mod conditions;
 pub mod config;
 pub mod error;
 pub mod parsers;
+pub mod schema;
 pub mod value;
 
 use std::collections::HashMap;
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-use memmap2::Mmap;
-use pyo3::exceptions::PyValueError;
+use memmap2::{Mmap, MmapMut};
+use pyo3::exceptions::{PyTypeError, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyIterator};
+use rayon::prelude::*;
+use rkyv::AlignedVec;
 use tempfile::Builder;
 
+use config::Backing;
 pub use config::SnapConfig;
-pub use error::{Result, SnapconfigError};
-pub use parsers::Format;
+pub use error::{Result, SnapconfigError, SnapconfigWarning};
+pub use parsers::{Format, ParseOptions};
 pub use value::{FlatValue, ValueNode};
 
 const CACHE_MAGIC: &[u8; 8] = b"SNAPCFG\0";
-const CACHE_VERSION: u32 = 1;
-const CACHE_HEADER_LEN: usize = 16; // keep payload aligned for rkyv access
+const CACHE_VERSION: u32 = 6;
+const CACHE_HEADER_LEN: usize = 32; // keep payload aligned for rkyv access
+
+/// Object keys are stored in document order rather than sorted.
+const CACHE_FLAG_PRESERVE_ORDER: u32 = 1;
+/// The payload is zstd-compressed and must be decompressed before access.
+const CACHE_FLAG_ZSTD: u32 = 2;
+/// Equal scalars share one node, so patching one in place would change all.
+const CACHE_FLAG_DEDUP: u32 = 4;
+/// Env values had `${VAR}` references expanded.
+const CACHE_FLAG_INTERPOLATE: u32 = 8;
+/// Built by `compile_overlay`: the header is followed by the list of
+/// merged input files, and the payload starts after it.
+const CACHE_FLAG_OVERLAY: u32 = 16;
+/// Compiled with `deprecated=`: a list of deprecated paths follows the
+/// header (after the overlay inputs, if any).
+const CACHE_FLAG_DEPRECATED: u32 = 32;
+/// Empty env values were stored as null.
+const CACHE_FLAG_EMPTY_AS_NULL: u32 = 64;
+/// Compiled with `eval_conditions=True`: the `key=value` context follows
+/// the header (after the deprecated paths, if any), so rebuilds filter
+/// `when` blocks the same way.
+const CACHE_FLAG_CONDITIONS: u32 = 128;
+/// Compiled with parse options `load` takes no argument for: their
+/// `name=value` entries follow the header (after the condition context,
+/// if any), so rebuilds parse the source the same way.
+const CACHE_FLAG_OPTIONS: u32 = 256;
+/// Keys were rewritten by a `key_transform`. The callable is not stored,
+/// so `load` refuses to rebuild such a cache.
+const CACHE_FLAG_KEY_TRANSFORM: u32 = 512;
+
+/// Read-only caches smaller than this are read into memory; setting up a
+/// mapping costs more than copying them.
+const SMALL_CACHE_LEN: u64 = 4096;
+
+fn cache_flags(opts: &ParseOptions, compress: bool) -> u32 {
+    let mut flags = 0;
+    if opts.preserve_order {
+        flags |= CACHE_FLAG_PRESERVE_ORDER;
+    }
+    if compress {
+        flags |= CACHE_FLAG_ZSTD;
+    }
+    if opts.dedup {
+        flags |= CACHE_FLAG_DEDUP;
+    }
+    if opts.interpolate {
+        flags |= CACHE_FLAG_INTERPOLATE;
+    }
+    if opts.empty_as_null {
+        flags |= CACHE_FLAG_EMPTY_AS_NULL;
+    }
+    if !opts.deprecated.is_empty() {
+        flags |= CACHE_FLAG_DEPRECATED;
+    }
+    if opts.conditions.is_some() {
+        flags |= CACHE_FLAG_CONDITIONS;
+    }
+    if !recorded_options(opts).is_empty() {
+        flags |= CACHE_FLAG_OPTIONS;
+    }
+    if opts.key_transformed {
+        flags |= CACHE_FLAG_KEY_TRANSFORM;
+    }
+    flags
+}
+
+/// The `name=value` entries stored under `CACHE_FLAG_OPTIONS` for the
+/// compile-only options in `opts` that differ from their defaults.
+fn recorded_options(opts: &ParseOptions) -> Vec<String> {
+    let mut entries = Vec::new();
+    if let Some(key) = &opts.toml_merge_key {
+        entries.push(format!("toml_merge_key={}", key));
+    }
+    if opts.csv_delimiter != b',' {
+        entries.push(format!("csv_delimiter={}", opts.csv_delimiter as char));
+    }
+    if !opts.csv_has_header {
+        entries.push("csv_has_header=false".to_string());
+    }
+    if opts.allow_nan {
+        entries.push("allow_nan=true".to_string());
+    }
+    if opts.strip_comments {
+        entries.push("strip_comments=true".to_string());
+    }
+    if !opts.trim_keys {
+        entries.push("trim_keys=false".to_string());
+    }
+    if opts.strict_keys {
+        entries.push("strict_keys=true".to_string());
+    }
+    // The resolved `strict` preset and its overrides.
+    for (name, set) in [
+        ("reject_duplicate_keys", opts.reject_duplicate_keys),
+        ("reject_non_finite", opts.reject_non_finite),
+        ("reject_overflow", opts.reject_overflow),
+        ("reject_unknown_format", opts.reject_unknown_format),
+    ] {
+        if set {
+            entries.push(format!("{}=true", name));
+        }
+    }
+    if let Some(max) = opts.max_keys {
+        entries.push(format!("max_keys={}", max));
+    }
+    if let Some(max) = opts.max_array_len {
+        entries.push(format!("max_array_len={}", max));
+    }
+    entries
+}
+
+/// Applies entries written by `recorded_options`; unknown names are skipped.
+fn apply_recorded_options(opts: &mut ParseOptions, entries: &[String]) {
+    for (name, value) in entries.iter().filter_map(|entry| entry.split_once('=')) {
+        match name {
+            "toml_merge_key" => opts.toml_merge_key = Some(value.to_string()),
+            "csv_delimiter" => opts.csv_delimiter = value.bytes().next().unwrap_or(b','),
+            "csv_has_header" => opts.csv_has_header = value == "true",
+            "allow_nan" => opts.allow_nan = value == "true",
+            "strip_comments" => opts.strip_comments = value == "true",
+            "trim_keys" => opts.trim_keys = value == "true",
+            "strict_keys" => opts.strict_keys = value == "true",
+            "reject_duplicate_keys" => opts.reject_duplicate_keys = value == "true",
+            "reject_non_finite" => opts.reject_non_finite = value == "true",
+            "reject_overflow" => opts.reject_overflow = value == "true",
+            "reject_unknown_format" => opts.reject_unknown_format = value == "true",
+            "max_keys" => opts.max_keys = value.parse().ok(),
+            "max_array_len" => opts.max_array_len = value.parse().ok(),
+            _ => {}
+        }
+    }
+}
 
-fn cache_header() -> [u8; CACHE_HEADER_LEN] {
+/// Layout: magic (8) | version u32 | flags u32 | source xxh3 u64 | reserved (8).
+/// With `CACHE_FLAG_OVERLAY`, `CACHE_FLAG_DEPRECATED`,
+/// `CACHE_FLAG_CONDITIONS` or `CACHE_FLAG_OPTIONS`, see `list_block` for
+/// what follows.
+fn cache_header(flags: u32, source_hash: u64) -> [u8; CACHE_HEADER_LEN] {
     let mut header = [0u8; CACHE_HEADER_LEN];
     header[..8].copy_from_slice(CACHE_MAGIC);
     header[8..12].copy_from_slice(&CACHE_VERSION.to_le_bytes());
+    header[12..16].copy_from_slice(&flags.to_le_bytes());
+    header[16..24].copy_from_slice(&source_hash.to_le_bytes());
     header
 }
 
-fn split_cache_bytes(mmap: &Mmap) -> std::result::Result<(usize, &[u8]), SnapconfigError> {
+fn source_hash(content: &[u8]) -> u64 {
+    xxhash_rust::xxh3::xxh3_64(content)
+}
+
+/// A list stored after the header (overlay inputs, deprecated paths,
+/// condition context, recorded options):
+/// byte length u32 | entries joined by newlines | zero padding, so the
+/// next block or the payload stays 16-byte aligned for rkyv.
+fn list_block(entries: &[String]) -> Vec<u8> {
+    let list = entries.join("\n");
+    let mut block = Vec::with_capacity(4 + list.len() + 16);
+    block.extend_from_slice(&(list.len() as u32).to_le_bytes());
+    block.extend_from_slice(list.as_bytes());
+    block.resize(block.len().next_multiple_of(16), 0);
+    block
+}
+
+/// The list block at `start` in `bytes` (a cache starting at its header),
+/// and the offset just past it.
+fn read_list_block(bytes: &[u8], start: usize) -> Result<(Vec<String>, usize)> {
+    let truncated = || SnapconfigError::InvalidCache("Cache header list is truncated".to_string());
+    let list_start = start + 4;
+    let len_bytes = bytes.get(start..list_start).ok_or_else(truncated)?;
+    let list_end = list_start + u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+    let list = bytes.get(list_start..list_end).ok_or_else(truncated)?;
+    let list = std::str::from_utf8(list)
+        .map_err(|_| SnapconfigError::InvalidCache("Cache header list is not UTF-8".to_string()))?;
+    Ok((
+        list.split('\n').map(String::from).collect(),
+        start + (list_end - start).next_multiple_of(16),
+    ))
+}
+
+/// The lists a cache's `flags` say follow its header.
+struct CacheLists {
+    overlay_inputs: Vec<String>,
+    deprecated: Vec<String>,
+    /// The `eval_conditions` context, when the cache was compiled with one.
+    conditions: Option<HashMap<String, String>>,
+    /// `recorded_options` entries.
+    options: Vec<String>,
+    /// Where the payload starts, after the header and any lists.
+    data_offset: usize,
+}
+
+fn read_cache_lists(bytes: &[u8], flags: u32) -> Result<CacheLists> {
+    let mut offset = CACHE_HEADER_LEN;
+    let mut next_list = |present: bool| -> Result<Vec<String>> {
+        if !present {
+            return Ok(Vec::new());
+        }
+        let (list, end) = read_list_block(bytes, offset)?;
+        offset = end;
+        Ok(list)
+    };
+    let overlay_inputs = next_list(flags & CACHE_FLAG_OVERLAY != 0)?;
+    let deprecated = next_list(flags & CACHE_FLAG_DEPRECATED != 0)?;
+    let conditions = if flags & CACHE_FLAG_CONDITIONS != 0 {
+        let entries = next_list(true)?;
+        Some(
+            entries
+                .iter()
+                .filter_map(|entry| entry.split_once('='))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    } else {
+        None
+    };
+    let options = next_list(flags & CACHE_FLAG_OPTIONS != 0)?;
+    Ok(CacheLists {
+        overlay_inputs,
+        deprecated,
+        conditions,
+        options,
+        data_offset: offset,
+    })
+}
+
+/// The lists of the cache at `cache`, read without loading its payload.
+fn read_cache_file_lists(cache: &Path, flags: u32) -> Option<CacheLists> {
+    let file = fs::File::open(cache).ok()?;
+    let mapped = unsafe { Mmap::map(&file) }.ok()?;
+    read_cache_lists(&mapped, flags).ok()
+}
+
+/// The flags and source hash recorded in a cache's header, if it is a
+/// current-version cache.
+fn read_cache_header(cache: &Path) -> Option<(u32, u64)> {
+    let mut header = [0u8; CACHE_HEADER_LEN];
+    fs::File::open(cache).ok()?.read_exact(&mut header).ok()?;
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if &header[..8] != CACHE_MAGIC || version != CACHE_VERSION {
+        return None;
+    }
+    Some((
+        u32::from_le_bytes(header[12..16].try_into().unwrap()),
+        u64::from_le_bytes(header[16..24].try_into().unwrap()),
+    ))
+}
+
+/// How `load` decides whether an existing cache is stale.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Validate {
+    /// Source modified after the cache.
+    Mtime,
+    /// Source content hash differs from the one stored in the cache.
+    Hash,
+    /// Never; only a missing cache is rebuilt.
+    None,
+}
+
+impl Validate {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "mtime" => Ok(Validate::Mtime),
+            "hash" => Ok(Validate::Hash),
+            "none" => Ok(Validate::None),
+            _ => Err(PyValueError::new_err(format!(
+                "validate must be 'mtime', 'hash' or 'none', not '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+fn split_cache_bytes(mmap: &[u8]) -> std::result::Result<(usize, u32, &[u8]), SnapconfigError> {
     if mmap.is_empty() {
         return Err(SnapconfigError::InvalidCache(
             "Cache file is empty".to_string(),
@@ -51,31 +324,320 @@ fn split_cache_bytes(mmap: &Mmap) -> std::result::Result<(usize, &[u8]), Snapcon
 
     if &mmap[..8] != CACHE_MAGIC {
         return Err(SnapconfigError::InvalidCache(
-            "Cache header magic mismatch".to_string(),
+            "Not a snapconfig cache (header magic mismatch); recompile it from the source"
+                .to_string(),
         ));
     }
 
     let version = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
     if version != CACHE_VERSION {
         return Err(SnapconfigError::InvalidCache(format!(
-            "Unsupported cache version: {}",
-            version
+            "Unsupported cache version: {} (this build reads version {}); recompile it from the source",
+            version, CACHE_VERSION
         )));
     }
 
-    let payload = &mmap[CACHE_HEADER_LEN..];
+    let flags = u32::from_le_bytes(mmap[12..16].try_into().unwrap());
+
+    let data_offset = read_cache_lists(mmap, flags)?.data_offset;
+    let payload = mmap.get(data_offset..).unwrap_or_default();
     if payload.is_empty() {
         return Err(SnapconfigError::InvalidCache(
             "Cache payload is empty".to_string(),
         ));
     }
 
-    Ok((CACHE_HEADER_LEN, payload))
+    Ok((data_offset, flags, payload))
 }
 
 #[pyfunction]
-#[pyo3(signature = (source_path, cache_path=None))]
-fn compile(source_path: &str, cache_path: Option<&str>) -> PyResult<String> {
+#[pyo3(signature = (
+    source_path,
+    cache_path=None,
+    preserve_order=false,
+    trim_keys=true,
+    strict_keys=false,
+    strip_comments=false,
+    allow_nan=false,
+    eval_conditions=false,
+    context=None,
+    compress=false,
+    dedup=false,
+    strict=false,
+    reject_duplicate_keys=None,
+    reject_non_finite=None,
+    reject_overflow=None,
+    reject_unknown_format=None,
+    emit_warnings=false,
+    cache_dir=None,
+    toml_merge_key=None,
+    key_transform=None,
+    mmap_source=false,
+    csv_delimiter=",",
+    csv_has_header=true,
+    max_keys=None,
+    max_array_len=None,
+    deprecated=None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn compile(
+    py: Python<'_>,
+    source_path: &str,
+    cache_path: Option<&str>,
+    preserve_order: bool,
+    trim_keys: bool,
+    strict_keys: bool,
+    strip_comments: bool,
+    allow_nan: bool,
+    eval_conditions: bool,
+    context: Option<HashMap<String, String>>,
+    compress: bool,
+    dedup: bool,
+    strict: bool,
+    reject_duplicate_keys: Option<bool>,
+    reject_non_finite: Option<bool>,
+    reject_overflow: Option<bool>,
+    reject_unknown_format: Option<bool>,
+    emit_warnings: bool,
+    cache_dir: Option<&str>,
+    toml_merge_key: Option<String>,
+    key_transform: Option<Bound<'_, PyAny>>,
+    mmap_source: bool,
+    csv_delimiter: &str,
+    csv_has_header: bool,
+    max_keys: Option<usize>,
+    max_array_len: Option<usize>,
+    deprecated: Option<Vec<String>>,
+) -> PyResult<String> {
+    let deprecated = deprecated.unwrap_or_default();
+    if let Some(entry) = deprecated
+        .iter()
+        .find(|entry| entry.is_empty() || entry.starts_with('=') || entry.contains('\n'))
+    {
+        return Err(PyValueError::new_err(format!(
+            "Invalid deprecated entry {:?}; expected 'old.path' or 'old.path=new.path'",
+            entry
+        )));
+    }
+    if let Some((key, value)) = context
+        .iter()
+        .flatten()
+        .find(|(key, value)| key.is_empty() || key.contains(['=', '\n']) || value.contains('\n'))
+    {
+        return Err(PyValueError::new_err(format!(
+            "Invalid context entry {:?}: {:?}; keys must be non-empty without '=' and neither may contain a newline",
+            key, value
+        )));
+    }
+    if let Some(key) = toml_merge_key.as_ref().filter(|key| key.contains('\n')) {
+        return Err(PyValueError::new_err(format!(
+            "Invalid toml_merge_key {:?}; it may not contain a newline",
+            key
+        )));
+    }
+    let opts = ParseOptions {
+        preserve_order,
+        trim_keys,
+        strict_keys,
+        strip_comments,
+        allow_nan,
+        conditions: eval_conditions.then(|| context.unwrap_or_default()),
+        dedup,
+        emit_warnings,
+        toml_merge_key,
+        csv_delimiter: csv_delimiter_byte(csv_delimiter)?,
+        csv_has_header,
+        max_keys,
+        max_array_len,
+        deprecated,
+        ..strict_options(
+            strict,
+            reject_duplicate_keys,
+            reject_non_finite,
+            reject_overflow,
+            reject_unknown_format,
+        )
+    };
+    if let Some(dir) = cache_dir {
+        fs::create_dir_all(dir)?;
+    }
+    let cache_path = resolve_cache_path(source_path, cache_path, cache_dir)?;
+    let compiled = compile_with(
+        source_path,
+        cache_path.as_deref(),
+        &opts,
+        compress,
+        key_transform.as_ref(),
+        mmap_source,
+    );
+    emit_parse_warnings(py)?;
+    compiled
+}
+
+/// Compile every file in `dir` whose name matches `pattern` (a `*`/`?`
+/// glob; by default any file with a recognised config extension) to its
+/// `{file}.snapconfig`, in parallel and without holding the GIL. Returns the
+/// cache paths in file name order. A file that fails doesn't stop the
+/// others: once they are written, ValueError lists every failure.
+#[pyfunction]
+#[pyo3(signature = (dir, pattern=None))]
+fn compile_dir(py: Python<'_>, dir: &str, pattern: Option<&str>) -> PyResult<Vec<String>> {
+    let mut sources: Vec<String> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let wanted = match pattern {
+            Some(pattern) => config::glob_match(pattern, &name),
+            None => parsers::Format::from_path(&path).is_some(),
+        };
+        if wanted && path.is_file() && !name.ends_with(".snapconfig") {
+            sources.push(path.to_string_lossy().into_owned());
+        }
+    }
+    sources.sort();
+
+    let opts = ParseOptions::default();
+    let results: Vec<PyResult<String>> = py.allow_threads(|| {
+        sources
+            .par_iter()
+            .map(|source| compile_with(source, None, &opts, false, None, false))
+            .collect()
+    });
+
+    let mut caches = Vec::with_capacity(results.len());
+    let mut failures = Vec::new();
+    for (source, result) in sources.iter().zip(results) {
+        match result {
+            Ok(cache) => caches.push(cache),
+            Err(err) => failures.push(format!("  {}: {}", source, err)),
+        }
+    }
+    if !failures.is_empty() {
+        return Err(PyValueError::new_err(format!(
+            "Failed to compile {} of {} files:\n{}",
+            failures.len(),
+            sources.len(),
+            failures.join("\n")
+        )));
+    }
+    Ok(caches)
+}
+
+/// Parse each of `paths` (formats may differ) and deep-merge them in order
+/// into one cache, later files winning; nested objects merge key by key and
+/// anything else is replaced. The cache defaults to the first path's
+/// (`{paths[0]}.snapconfig`), so `load(paths[0])` picks it up, and it
+/// records every input: `load` rebuilds it when any of them changes.
+#[pyfunction]
+#[pyo3(signature = (paths, cache_path=None, preserve_order=false))]
+fn compile_overlay(
+    paths: Vec<String>,
+    cache_path: Option<&str>,
+    preserve_order: bool,
+) -> PyResult<String> {
+    let Some(primary) = paths.first() else {
+        return Err(PyValueError::new_err(
+            "compile_overlay() needs at least one path",
+        ));
+    };
+    let output_path = cache_path
+        .map(String::from)
+        .unwrap_or_else(|| format!("{}.snapconfig", primary));
+    let opts = ParseOptions {
+        preserve_order,
+        ..ParseOptions::default()
+    };
+    // Absolute, so a later load() from another directory finds them.
+    let inputs = paths
+        .iter()
+        .map(|path| Ok(std::path::absolute(path)?.to_string_lossy().into_owned()))
+        .collect::<Result<Vec<_>>>()?;
+    let (bytes, hash) = compile_overlay_bytes(&inputs, &opts)?;
+    write_cache_with_inputs(Path::new(&output_path), &bytes, &opts, hash, false, &inputs)?;
+    Ok(output_path)
+}
+
+/// An explicit `cache_path` wins; otherwise, with `cache_dir`, the cache
+/// goes in that directory under the source's file name
+/// plus a hash of its absolute path, so same-named sources in different
+/// directories get separate caches. `None` means `{source}.snapconfig`.
+fn resolve_cache_path(
+    source_path: &str,
+    cache_path: Option<&str>,
+    cache_dir: Option<&str>,
+) -> Result<Option<String>> {
+    if let Some(cache_path) = cache_path {
+        return Ok(Some(cache_path.to_string()));
+    }
+    let Some(cache_dir) = cache_dir else {
+        return Ok(None);
+    };
+    let source = Path::new(source_path);
+    let absolute = std::path::absolute(source)?;
+    let name = source
+        .file_name()
+        .map_or_else(|| "config".into(), |name| name.to_string_lossy());
+    let hash = source_hash(absolute.to_string_lossy().as_bytes());
+    let file = format!("{}-{:016x}.snapconfig", name, hash);
+    Ok(Some(
+        Path::new(cache_dir)
+            .join(file)
+            .to_string_lossy()
+            .into_owned(),
+    ))
+}
+
+/// Raises each warning recorded during parsing through Python's
+/// `warnings.warn`, as a `SnapconfigWarning`.
+fn emit_parse_warnings(py: Python<'_>) -> PyResult<()> {
+    let category = py.get_type_bound::<SnapconfigWarning>();
+    for message in parsers::take_warnings() {
+        PyErr::warn_bound(py, &category, &message, 1)?;
+    }
+    Ok(())
+}
+
+/// The single ASCII character `delimiter` as the byte the CSV reader takes.
+fn csv_delimiter_byte(delimiter: &str) -> PyResult<u8> {
+    match delimiter.as_bytes() {
+        [byte] if *byte != b'\n' => Ok(*byte),
+        _ => Err(PyValueError::new_err(format!(
+            "csv_delimiter must be one ASCII character other than a newline, got {:?}",
+            delimiter
+        ))),
+    }
+}
+
+/// Default options with the `strict` preset applied, then any individually
+/// given `reject_*` flags on top.
+fn strict_options(
+    strict: bool,
+    reject_duplicate_keys: Option<bool>,
+    reject_non_finite: Option<bool>,
+    reject_overflow: Option<bool>,
+    reject_unknown_format: Option<bool>,
+) -> ParseOptions {
+    let preset = ParseOptions::default().with_strict(strict);
+    ParseOptions {
+        reject_duplicate_keys: reject_duplicate_keys.unwrap_or(preset.reject_duplicate_keys),
+        reject_non_finite: reject_non_finite.unwrap_or(preset.reject_non_finite),
+        reject_overflow: reject_overflow.unwrap_or(preset.reject_overflow),
+        reject_unknown_format: reject_unknown_format.unwrap_or(preset.reject_unknown_format),
+        ..preset
+    }
+}
+
+fn compile_with(
+    source_path: &str,
+    cache_path: Option<&str>,
+    opts: &ParseOptions,
+    compress: bool,
+    key_transform: Option<&Bound<'_, PyAny>>,
+    mmap_source: bool,
+) -> PyResult<String> {
     let source = Path::new(source_path);
     if !source.exists() {
         return Err(SnapconfigError::FileNotFound(source_path.to_string()).into());
@@ -85,39 +647,429 @@ fn compile(source_path: &str, cache_path: Option<&str>) -> PyResult<String> {
         .map(PathBuf::from)
         .unwrap_or_else(|| PathBuf::from(format!("{}.snapconfig", source_path)));
 
-    let content = fs::read_to_string(source)?;
-    let flat_value = parsers::parse_content(&content, source)?;
+    let (mut flat_value, hash) = parse_source(source, opts, mmap_source)?;
+    let opts = &ParseOptions {
+        key_transformed: key_transform.is_some(),
+        ..opts.clone()
+    };
+    if let Some(transform) = key_transform {
+        flat_value.try_map_keys(
+            |key| transform.call1((key,))?.extract::<String>(),
+            !opts.preserve_order,
+            |key| {
+                SnapconfigError::DuplicateKey(format!(
+                    "key_transform maps two keys of one object to '{}'",
+                    key
+                ))
+                .into()
+            },
+        )?;
+    }
+    let bytes = rkyv::to_bytes::<_, 65536>(&flat_value)
+        .map_err(|e| SnapconfigError::Serialize(e.to_string()))?;
+    write_cache(&output_path, &bytes, opts, hash, compress)?;
+
+    Ok(output_path.to_string_lossy().into_owned())
+}
+
+/// Parse `source` and serialize it into the archived cache payload. Also
+/// returns the source's content hash.
+fn compile_bytes(source: &Path, opts: &ParseOptions) -> Result<(AlignedVec, u64)> {
+    let (flat_value, hash) = parse_source(source, opts, false)?;
+    let bytes = rkyv::to_bytes::<_, 65536>(&flat_value)
+        .map_err(|e| SnapconfigError::Serialize(e.to_string()))?;
+    Ok((bytes, hash))
+}
 
+/// `compile_bytes` for an overlay of `inputs`.
+fn compile_overlay_bytes(inputs: &[String], opts: &ParseOptions) -> Result<(AlignedVec, u64)> {
+    let (flat_value, hash) = parse_overlay(inputs, opts)?;
     let bytes = rkyv::to_bytes::<_, 65536>(&flat_value)
         .map_err(|e| SnapconfigError::Serialize(e.to_string()))?;
+    Ok((bytes, hash))
+}
+
+/// An overlay input as a path, or `FileNotFound` naming it if it is gone.
+fn overlay_input(input: &str) -> Result<&Path> {
+    let path = Path::new(input);
+    if !path.exists() {
+        return Err(SnapconfigError::FileNotFound(input.to_string()));
+    }
+    Ok(path)
+}
+
+/// The hash `parse_overlay` records for `inputs`, without parsing them.
+fn overlay_hash(inputs: &[String]) -> Result<u64> {
+    let mut hashes = Vec::with_capacity(inputs.len() * 8);
+    for input in inputs {
+        hashes.extend_from_slice(&source_hash(&fs::read(overlay_input(input)?)?).to_le_bytes());
+    }
+    Ok(source_hash(&hashes))
+}
+
+/// Parse each of `inputs` and deep-merge them in order, later files winning.
+/// The hash covers every input's content.
+fn parse_overlay(inputs: &[String], opts: &ParseOptions) -> Result<(FlatValue, u64)> {
+    let mut merged: Option<FlatValue> = None;
+    let mut hashes = Vec::with_capacity(inputs.len() * 8);
+    for input in inputs {
+        let (flat, hash) = parse_source(overlay_input(input)?, opts, false)?;
+        hashes.extend_from_slice(&hash.to_le_bytes());
+        merged = Some(match merged {
+            Some(base) => base.merged(&flat, false, !opts.preserve_order),
+            None => flat,
+        });
+    }
+    let merged = merged.expect("overlays have at least one input");
+    let merged = if opts.dedup && inputs.len() > 1 {
+        merged.dedup_scalars()
+    } else {
+        merged
+    };
+    Ok((merged, source_hash(&hashes)))
+}
+
+/// Parse `source`, returning the tree and the source's content hash.
+/// With `mmap_source`, the file is mapped and parsed in place instead of
+/// being read into a `String`.
+fn parse_source(source: &Path, opts: &ParseOptions, mmap_source: bool) -> Result<(FlatValue, u64)> {
+    if mmap_source {
+        let file = fs::File::open(source)?;
+        // Zero-length files can't be mapped; they take the normal path.
+        if file.metadata()?.len() > 0 {
+            let mapped = unsafe { Mmap::map(&file)? };
+            let content = std::str::from_utf8(&mapped)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            let flat_value = parsers::parse_content_opts(content, source, opts)?;
+            return Ok((flat_value, source_hash(&mapped)));
+        }
+    }
+    let content = fs::read_to_string(source)?;
+    let flat_value = parsers::parse_content_opts(&content, source, opts)?;
+    Ok((flat_value, source_hash(content.as_bytes())))
+}
+
+fn write_cache(
+    output_path: &Path,
+    bytes: &[u8],
+    opts: &ParseOptions,
+    source_hash: u64,
+    compress: bool,
+) -> Result<()> {
+    write_cache_with_inputs(output_path, bytes, opts, source_hash, compress, &[])
+}
 
+/// `write_cache`, recording `overlay_inputs` (when there are any) so `load`
+/// can check and rebuild every file the cache was merged from.
+fn write_cache_with_inputs(
+    output_path: &Path,
+    bytes: &[u8],
+    opts: &ParseOptions,
+    source_hash: u64,
+    compress: bool,
+    overlay_inputs: &[String],
+) -> Result<()> {
+    let mut flags = cache_flags(opts, compress);
+    if !overlay_inputs.is_empty() {
+        flags |= CACHE_FLAG_OVERLAY;
+    }
+    let mut header = cache_header(flags, source_hash).to_vec();
+    if !overlay_inputs.is_empty() {
+        header.extend_from_slice(&list_block(overlay_inputs));
+    }
+    if !opts.deprecated.is_empty() {
+        header.extend_from_slice(&list_block(&opts.deprecated));
+    }
+    if let Some(context) = &opts.conditions {
+        let mut entries: Vec<String> = context
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        entries.sort();
+        header.extend_from_slice(&list_block(&entries));
+    }
+    let options = recorded_options(opts);
+    if !options.is_empty() {
+        header.extend_from_slice(&list_block(&options));
+    }
+    if compress {
+        let compressed = zstd::bulk::compress(bytes, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        write_cache_file(output_path, &header, &compressed)
+    } else {
+        write_cache_file(output_path, &header, bytes)
+    }
+}
+
+/// Atomically replace `output_path` with `header` followed by `payload`.
+fn write_cache_file(output_path: &Path, header: &[u8], payload: &[u8]) -> Result<()> {
     let parent = output_path.parent().unwrap_or_else(|| Path::new("."));
     let mut tmp = Builder::new()
         .prefix("snapconfig-")
         .suffix(".tmp")
         .tempfile_in(parent)?;
-    tmp.as_file_mut().write_all(&cache_header())?;
-    tmp.as_file_mut().write_all(&bytes)?;
+    tmp.as_file_mut().write_all(header)?;
+    tmp.as_file_mut().write_all(payload)?;
     tmp.as_file_mut().sync_all()?;
-    tmp.persist(&output_path)
+    tmp.persist(output_path)
         .map_err(|e| SnapconfigError::Io(e.error))?;
 
-    Ok(output_path.to_string_lossy().into_owned())
+    Ok(())
+}
+
+/// Keep an existing cache's payload but record a new source hash.
+fn restamp_cache(cache: &Path, source_hash: u64) -> Result<()> {
+    let mut bytes = fs::read(cache)?;
+    if bytes.len() < CACHE_HEADER_LEN {
+        return Err(SnapconfigError::InvalidCache(
+            "Cache header is missing or truncated".to_string(),
+        ));
+    }
+    bytes[16..24].copy_from_slice(&source_hash.to_le_bytes());
+    let (header, payload) = bytes.split_at(CACHE_HEADER_LEN);
+    write_cache_file(cache, header, payload)
+}
+
+/// Compile `(name, content, format)` items lazily. The returned iterator
+/// yields `(name, cache_bytes)`, or `(name, exception)` for items that fail.
+#[pyfunction]
+fn compile_stream(sources: &Bound<'_, PyAny>) -> PyResult<CompileStream> {
+    Ok(CompileStream {
+        sources: sources.iter()?.unbind(),
+    })
+}
+
+#[pyclass]
+struct CompileStream {
+    sources: Py<PyIterator>,
+}
+
+#[pymethods]
+impl CompileStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let Some(item) = self.sources.bind(py).clone().next() else {
+            return Ok(None);
+        };
+        let (name, content, format): (PyObject, String, String) = item?.extract()?;
+        let result = match compile_content(&content, &format) {
+            Ok(bytes) => PyBytes::new_bound(py, &bytes).into_any().unbind(),
+            Err(err) => err.into_value(py).into_any(),
+        };
+        Ok(Some((name, result).into_py(py)))
+    }
+}
+
+/// Full cache file contents (header + payload) for in-memory source text.
+fn compile_content(content: &str, format: &str) -> PyResult<Vec<u8>> {
+    let opts = ParseOptions::default();
+    let flat_value = parse_named_format(content, format, &opts)?;
+    let payload = rkyv::to_bytes::<_, 65536>(&flat_value)
+        .map_err(|e| SnapconfigError::Serialize(e.to_string()))?;
+
+    let mut bytes = Vec::with_capacity(CACHE_HEADER_LEN + payload.len());
+    bytes.extend_from_slice(&cache_header(
+        cache_flags(&opts, false),
+        source_hash(content.as_bytes()),
+    ));
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
 }
 
 /// Load config file with automatic caching.
+/// With `watch_paths`, a changed source only refreshes the cache when a value
+/// at one of those dotted paths changed; edits elsewhere are ignored.
+/// `validate` picks the staleness check: "mtime" (default), "hash" (compare
+/// the source's content hash, ignoring mtimes) or "none".
+/// With `env_override`, scalars whose env name (`env_prefix` plus the
+/// upper-cased path joined by `env_separator`) is set in `os.environ` take
+/// that value. The result depends on the environment, so it is built in
+/// memory and the cache is neither read nor written; passing cache or
+/// staleness options (`cache_path`, `cache_dir`, `force_recompile`,
+/// `watch_paths`, a non-default `validate`) alongside it raises ValueError.
+/// `env` roots the result at that top-level section (say "production")
+/// instead of the whole document. `defaults` is a dict deep-merged under
+/// the result, which wins on conflicts; the merged config is built in
+/// memory and never written to the cache.
 #[pyfunction]
-#[pyo3(signature = (path, cache_path=None, force_recompile=false))]
-fn load(path: &str, cache_path: Option<&str>, force_recompile: bool) -> PyResult<SnapConfig> {
+#[pyo3(signature = (
+    path,
+    cache_path=None,
+    force_recompile=false,
+    preserve_order=false,
+    watch_paths=None,
+    validate="mtime",
+    env_override=false,
+    env_prefix="",
+    env_separator="_",
+    cache_dir=None,
+    env=None,
+    defaults=None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn load(
+    py: Python<'_>,
+    path: &str,
+    cache_path: Option<&str>,
+    force_recompile: bool,
+    preserve_order: bool,
+    watch_paths: Option<Vec<String>>,
+    validate: &str,
+    env_override: bool,
+    env_prefix: &str,
+    env_separator: &str,
+    cache_dir: Option<&str>,
+    env: Option<&str>,
+    defaults: Option<Bound<'_, PyAny>>,
+) -> PyResult<SnapConfig> {
+    let opts = ParseOptions {
+        preserve_order,
+        ..ParseOptions::default()
+    };
+    let config = if env_override {
+        let ignored: Vec<&str> = [
+            ("cache_path", cache_path.is_some()),
+            ("cache_dir", cache_dir.is_some()),
+            ("force_recompile", force_recompile),
+            ("watch_paths", watch_paths.is_some()),
+            ("validate", validate != "mtime"),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect();
+        if !ignored.is_empty() {
+            return Err(PyValueError::new_err(format!(
+                "env_override bypasses the cache, so {} cannot be used with it",
+                ignored.join(", ")
+            )));
+        }
+        let source = Path::new(path);
+        if !source.exists() {
+            return Err(SnapconfigError::FileNotFound(path.to_string()).into());
+        }
+        let mut flat = parsers::parse_content_opts(&fs::read_to_string(source)?, source, &opts)?;
+        let environ: HashMap<String, String> = py
+            .import_bound("os")?
+            .getattr("environ")?
+            .call_method0("copy")?
+            .extract()?;
+        parsers::apply_env_overrides(&mut flat, &environ, env_prefix, env_separator, &opts)?;
+        in_memory_config(&flat, opts.preserve_order, Some(path.to_string()))?
+    } else {
+        if let Some(dir) = cache_dir {
+            fs::create_dir_all(dir)?;
+        }
+        let cache_path = resolve_cache_path(path, cache_path, cache_dir)?;
+        load_with(
+            path,
+            cache_path.as_deref(),
+            force_recompile,
+            &opts,
+            watch_paths.as_deref(),
+            Validate::parse(validate)?,
+        )?
+    };
+    let config = match env {
+        Some(name) => config.select_env(name)?,
+        None => config,
+    };
+    let Some(defaults) = defaults else {
+        return Ok(config);
+    };
+    let preserve_order = config.preserve_order();
+    let merged = config::python_to_flat_value(&defaults, !preserve_order)?.merged(
+        &config.to_flat_value(),
+        false,
+        !preserve_order,
+    );
+    Ok(
+        in_memory_config(&merged, preserve_order, Some(path.to_string()))?
+            .with_deprecated(config.deprecated().clone()),
+    )
+}
+
+fn load_with(
+    path: &str,
+    cache_path: Option<&str>,
+    force_recompile: bool,
+    opts: &ParseOptions,
+    watch_paths: Option<&[String]>,
+    validate: Validate,
+) -> PyResult<SnapConfig> {
     let source = Path::new(path);
     let cache = cache_path
         .map(String::from)
         .unwrap_or_else(|| format!("{}.snapconfig", path));
     let cache_file = Path::new(&cache);
+    let existing_header = read_cache_header(cache_file);
+    // Rebuilds keep an existing cache compressed and deduplicated, along
+    // with the deprecated paths, condition context and compile-only parse
+    // options it was compiled with.
+    let existing_flags = existing_header.map_or(0, |(flags, _)| flags);
+    let compress = existing_flags & CACHE_FLAG_ZSTD != 0;
+    let list_flags =
+        CACHE_FLAG_OVERLAY | CACHE_FLAG_DEPRECATED | CACHE_FLAG_CONDITIONS | CACHE_FLAG_OPTIONS;
+    let existing_lists = if existing_flags & list_flags != 0 {
+        read_cache_file_lists(cache_file, existing_flags)
+    } else {
+        None
+    };
+    let mut opts = ParseOptions {
+        dedup: opts.dedup || existing_flags & CACHE_FLAG_DEDUP != 0,
+        deprecated: existing_lists
+            .as_ref()
+            .map(|lists| lists.deprecated.clone())
+            .unwrap_or_default(),
+        conditions: existing_lists
+            .as_ref()
+            .and_then(|lists| lists.conditions.clone()),
+        ..opts.clone()
+    };
+    if let Some(lists) = &existing_lists {
+        apply_recorded_options(&mut opts, &lists.options);
+    }
+    let opts = &opts;
+
+    // A cache built with another key order, interpolation or empty-value
+    // setting holds different values.
+    let flag_changed = |flag: u32, wanted: bool| {
+        existing_header.is_some() && (existing_flags & flag != 0) != wanted
+    };
+    let options_changed = flag_changed(CACHE_FLAG_PRESERVE_ORDER, opts.preserve_order)
+        || flag_changed(CACHE_FLAG_INTERPOLATE, opts.interpolate)
+        || flag_changed(CACHE_FLAG_EMPTY_AS_NULL, opts.empty_as_null);
+    // An overlay cache is checked against, and rebuilt from, all its inputs.
+    let overlay_inputs = existing_lists
+        .map(|lists| lists.overlay_inputs)
+        .unwrap_or_default();
 
     let needs_compile = force_recompile
         || !cache_file.exists()
-        || (source.exists() && is_source_newer(source, cache_file)?);
+        || (source.exists() && options_changed)
+        || (source.exists()
+            && match validate {
+                Validate::Mtime if overlay_inputs.is_empty() => {
+                    is_source_newer(source, cache_file)?
+                }
+                Validate::Mtime => {
+                    let mut newer = false;
+                    for input in &overlay_inputs {
+                        newer |= is_source_newer(overlay_input(input)?, cache_file)?;
+                    }
+                    newer
+                }
+                Validate::Hash => {
+                    let current = if overlay_inputs.is_empty() {
+                        source_hash(&fs::read(source)?)
+                    } else {
+                        overlay_hash(&overlay_inputs)?
+                    };
+                    existing_header.map(|(_, hash)| hash) != Some(current)
+                }
+                Validate::None => false,
+            });
 
     if needs_compile {
         if !source.exists() {
@@ -125,10 +1077,82 @@ fn load(path: &str, cache_path: Option<&str>, force_recompile: bool) -> PyResult
                 SnapconfigError::FileNotFound(format!("{} (and no cache exists)", path)).into(),
             );
         }
-        compile(path, Some(&cache))?;
+        if existing_flags & CACHE_FLAG_KEY_TRANSFORM != 0 {
+            return Err(PyValueError::new_err(format!(
+                "{} was compiled with a key_transform, which load cannot apply; \
+                 recompile it with snapconfig.compile(..., key_transform=...)",
+                cache
+            )));
+        }
+        let (bytes, hash) = compile_cache_bytes(source, &overlay_inputs, opts)?;
+        let unchanged = match watch_paths {
+            Some(paths) if !force_recompile && !options_changed && cache_file.exists() => {
+                watched_values_unchanged(&cache, &bytes, paths)
+            }
+            _ => false,
+        };
+        if unchanged {
+            // Only unwatched values changed: keep the cache but mark it fresh.
+            restamp_cache(cache_file, hash)?;
+            let stamp = source.metadata()?.modified()?.max(SystemTime::now());
+            fs::File::options()
+                .write(true)
+                .open(cache_file)?
+                .set_modified(stamp)?;
+        } else {
+            write_cache_with_inputs(cache_file, &bytes, opts, hash, compress, &overlay_inputs)?;
+        }
     }
 
-    load_compiled(&cache, if source.exists() { Some(path) } else { None })
+    let source_path = if source.exists() { Some(path) } else { None };
+    match open_cache(&cache, source_path, false) {
+        // A corrupt cache is only fatal when there is no source to rebuild it from.
+        Err(SnapconfigError::InvalidCache(_)) if source_path.is_some() => {
+            let _ = fs::remove_file(cache_file);
+            let (bytes, hash) = compile_cache_bytes(source, &overlay_inputs, opts)?;
+            write_cache_with_inputs(cache_file, &bytes, opts, hash, compress, &overlay_inputs)?;
+            Ok(open_cache(&cache, source_path, false)?)
+        }
+        result => Ok(result?),
+    }
+}
+
+/// `compile_bytes` for `source`, or for the overlay of `overlay_inputs`
+/// when the cache recorded any.
+fn compile_cache_bytes(
+    source: &Path,
+    overlay_inputs: &[String],
+    opts: &ParseOptions,
+) -> Result<(AlignedVec, u64)> {
+    if overlay_inputs.is_empty() {
+        compile_bytes(source, opts)
+    } else {
+        compile_overlay_bytes(overlay_inputs, opts)
+    }
+}
+
+/// True when every watched path resolves to the same value (or is missing)
+/// in both the existing cache and the freshly compiled payload.
+fn watched_values_unchanged(cache: &str, fresh: &[u8], paths: &[String]) -> bool {
+    let Ok(cached_config) = open_cache(cache, None, false) else {
+        return false;
+    };
+    let cached = cached_config.archived();
+    let fresh = unsafe { rkyv::archived_root::<FlatValue>(fresh) };
+    let (Some(cached_root), Some(fresh_root)) = (cached.root.as_ref(), fresh.root.as_ref()) else {
+        return false;
+    };
+
+    paths.iter().all(|path| {
+        match (
+            config::lookup_path(&cached.nodes, *cached_root, path),
+            config::lookup_path(&fresh.nodes, *fresh_root, path),
+        ) {
+            (None, None) => true,
+            (Some(a), Some(b)) => config::same_value(&cached.nodes, a, &fresh.nodes, b),
+            _ => false,
+        }
+    })
 }
 
 fn is_source_newer(source: &Path, cache: &Path) -> PyResult<bool> {
@@ -138,13 +1162,63 @@ fn is_source_newer(source: &Path, cache: &Path) -> PyResult<bool> {
 }
 
 /// Load directly from compiled .snapconfig cache file (skips freshness check).
+/// `writable=True` maps the cache read-write so `set_scalar` can patch it.
 #[pyfunction]
-#[pyo3(signature = (cache_path, source_path=None))]
-fn load_compiled(cache_path: &str, source_path: Option<&str>) -> PyResult<SnapConfig> {
-    let file = fs::File::open(cache_path)?;
-    let mmap = unsafe { Mmap::map(&file)? };
+#[pyo3(signature = (cache_path, source_path=None, writable=false))]
+fn load_compiled(
+    cache_path: &str,
+    source_path: Option<&str>,
+    writable: bool,
+) -> PyResult<SnapConfig> {
+    Ok(open_cache(cache_path, source_path, writable)?)
+}
 
-    let (data_offset, payload) = split_cache_bytes(&mmap)?;
+fn open_cache(cache_path: &str, source_path: Option<&str>, writable: bool) -> Result<SnapConfig> {
+    let mmap = if writable {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(cache_path)?;
+        Backing::MmapMut(unsafe { MmapMut::map_mut(&file)? })
+    } else {
+        let mut file = fs::File::open(cache_path)?;
+        let len = file.metadata()?.len();
+        if len < SMALL_CACHE_LEN {
+            let mut bytes = AlignedVec::with_capacity(len as usize);
+            bytes.extend_from_reader(&mut file)?;
+            Backing::Owned(bytes)
+        } else {
+            Backing::Mmap(unsafe { Mmap::map(&file)? })
+        }
+    };
+
+    let (data_offset, flags, payload) = split_cache_bytes(&mmap)?;
+    let source_hash = u64::from_le_bytes(mmap[16..24].try_into().unwrap());
+    let deprecated = if flags & CACHE_FLAG_DEPRECATED != 0 {
+        parse_deprecated(&read_cache_lists(&mmap, flags)?.deprecated)
+    } else {
+        HashMap::new()
+    };
+    if writable && flags & CACHE_FLAG_DEDUP != 0 {
+        return Err(SnapconfigError::InvalidCache(
+            "Deduplicated caches cannot be opened writable".to_string(),
+        ));
+    }
+    let (mmap, data_offset) = if flags & CACHE_FLAG_ZSTD != 0 {
+        if writable {
+            return Err(SnapconfigError::InvalidCache(
+                "Compressed caches cannot be opened writable".to_string(),
+            ));
+        }
+        let decompressed = zstd::stream::decode_all(payload)
+            .map_err(|e| SnapconfigError::InvalidCache(format!("Decompression failed: {}", e)))?;
+        let mut aligned = AlignedVec::with_capacity(decompressed.len());
+        aligned.extend_from_slice(&decompressed);
+        (Backing::Owned(aligned), 0)
+    } else {
+        (mmap, data_offset)
+    };
+    let payload = &mmap[data_offset..];
 
     rkyv::check_archived_root::<FlatValue>(payload)
         .map_err(|e| SnapconfigError::InvalidCache(format!("Validation failed: {}", e)))?;
@@ -158,46 +1232,425 @@ fn load_compiled(cache_path: &str, source_path: Option<&str>) -> PyResult<SnapCo
     if (root_idx as usize) >= archived.nodes.len() {
         return Err(SnapconfigError::InvalidCache(
             "Cache root node index is out of bounds".to_string(),
-        )
-        .into());
+        ));
     }
 
     Ok(SnapConfig::new(
         mmap,
         data_offset,
         root_idx,
+        flags & CACHE_FLAG_PRESERVE_ORDER != 0,
         cache_path.to_string(),
         source_path.map(String::from),
+    )
+    .with_deprecated(deprecated)
+    .with_source_hash(source_hash))
+}
+
+/// Deprecated path -> suggested replacement, from `old` / `old=new` entries.
+fn parse_deprecated(entries: &[String]) -> HashMap<String, Option<String>> {
+    entries
+        .iter()
+        .map(|entry| match entry.split_once('=') {
+            Some((old, new)) => (old.to_string(), Some(new.to_string())),
+            None => (entry.clone(), None),
+        })
+        .collect()
+}
+
+/// Parse one entry of a `.tar.gz` bundle. Nothing is cached: the config is
+/// held in memory and its `cache_path` is empty. `format` defaults to the
+/// entry's extension.
+#[cfg(feature = "archive")]
+#[pyfunction]
+#[pyo3(signature = (tar_path, entry_name, format=None))]
+fn load_from_tar(tar_path: &str, entry_name: &str, format: Option<&str>) -> PyResult<SnapConfig> {
+    let file = fs::File::open(tar_path)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let wanted = Path::new(entry_name.trim_start_matches("./"));
+
+    let mut content = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path.strip_prefix(".").unwrap_or(&path) == wanted {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            content = Some(text);
+            break;
+        }
+    }
+    let Some(content) = content else {
+        return Err(
+            SnapconfigError::FileNotFound(format!("{} in {}", entry_name, tar_path)).into(),
+        );
+    };
+
+    let opts = ParseOptions::default();
+    let flat = match format {
+        Some(format) => parse_named_format(&content, format, &opts)?,
+        None => parsers::parse_content_opts(&content, wanted, &opts)?,
+    };
+    Ok(in_memory_config(
+        &flat,
+        false,
+        Some(format!("{}/{}", tar_path, entry_name)),
+    )?)
+}
+
+/// Parse whatever `fileobj.read()` returns (`bytes` or `str`) as `format`.
+/// Like `load_from_tar`, nothing is cached: the config is held in memory
+/// and its `cache_path` is empty. Use `.to_dict()` for a plain dict.
+#[pyfunction]
+#[pyo3(signature = (fileobj, format, preserve_order=false))]
+fn load_fileobj(
+    fileobj: &Bound<'_, PyAny>,
+    format: &str,
+    preserve_order: bool,
+) -> PyResult<SnapConfig> {
+    let data = fileobj.call_method0("read")?;
+    let content = if let Ok(bytes) = data.downcast::<PyBytes>() {
+        let bytes = bytes.as_bytes();
+        let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+        std::str::from_utf8(bytes)
+            .map_err(|e| PyValueError::new_err(format!("Config is not valid UTF-8: {}", e)))?
+            .to_string()
+    } else if let Ok(text) = data.extract::<String>() {
+        text
+    } else {
+        return Err(PyTypeError::new_err(format!(
+            "read() returned {}, expected bytes or str",
+            data.get_type().name()?
+        )));
+    };
+
+    let opts = ParseOptions {
+        preserve_order,
+        ..ParseOptions::default()
+    };
+    let flat = parse_named_format(&content, format, &opts)?;
+    Ok(in_memory_config(&flat, preserve_order, None)?)
+}
+
+/// Serialize `flat` into an owned buffer and wrap it without a cache file.
+fn in_memory_config(
+    flat: &FlatValue,
+    preserve_order: bool,
+    source_path: Option<String>,
+) -> Result<SnapConfig> {
+    let root_idx = flat
+        .root()
+        .ok_or_else(|| SnapconfigError::Serialize("Parsed config has no root".to_string()))?;
+    let bytes =
+        rkyv::to_bytes::<_, 65536>(flat).map_err(|e| SnapconfigError::Serialize(e.to_string()))?;
+
+    Ok(SnapConfig::new(
+        Backing::Owned(bytes),
+        0,
+        root_idx,
+        preserve_order,
+        String::new(),
+        source_path,
     ))
 }
 
+/// Deep-merge `configs` left to right into a new in-memory config. Later
+/// configs win on conflicts and nested objects merge recursively; arrays
+/// are replaced, or concatenated with `concat_arrays=True`.
+#[pyfunction]
+#[pyo3(signature = (configs, concat_arrays=false))]
+fn merge(configs: Vec<PyRef<'_, SnapConfig>>, concat_arrays: bool) -> PyResult<SnapConfig> {
+    let Some((first, rest)) = configs.split_first() else {
+        return Err(PyValueError::new_err("merge() needs at least one config"));
+    };
+    // Document order only survives when every input kept it.
+    let preserve_order = configs.iter().all(|config| config.preserve_order());
+    let mut flat = first.to_flat_value();
+    for config in rest {
+        flat = flat.merged(&config.to_flat_value(), concat_arrays, !preserve_order);
+    }
+    Ok(in_memory_config(&flat, preserve_order, None)?)
+}
+
+/// Collect every `filename` from `start_dir` up towards the filesystem root
+/// and deep-merge them root-first, so deeper directories override shallower
+/// ones, the way `.editorconfig` files resolve. The walk stops at the first
+/// directory holding `marker` (`.git` by default; `None` walks to the root),
+/// after taking that directory's file. The result lives in memory; nothing
+/// is cached.
+#[pyfunction]
+#[pyo3(signature = (start_dir, filename, marker=Some(".git"), preserve_order=false))]
+fn load_layered(
+    start_dir: &str,
+    filename: &str,
+    marker: Option<&str>,
+    preserve_order: bool,
+) -> PyResult<SnapConfig> {
+    let start = fs::canonicalize(start_dir)?;
+    let mut layers = Vec::new();
+    for dir in start.ancestors() {
+        let candidate = dir.join(filename);
+        if candidate.is_file() {
+            layers.push(candidate.to_string_lossy().into_owned());
+        }
+        if marker.is_some_and(|marker| dir.join(marker).exists()) {
+            break;
+        }
+    }
+    if layers.is_empty() {
+        return Err(SnapconfigError::FileNotFound(format!(
+            "{} in {} or its parents",
+            filename,
+            start.display()
+        ))
+        .into());
+    }
+    layers.reverse();
+    let opts = ParseOptions {
+        preserve_order,
+        ..ParseOptions::default()
+    };
+    let (flat, _) = parse_overlay(&layers, &opts)?;
+    Ok(in_memory_config(&flat, preserve_order, layers.pop())?)
+}
+
+/// Load `config_path` and apply the TOML schema at `schema_path`: missing
+/// paths get their declared default and string scalars are coerced to the
+/// declared type. The result lives in memory; nothing is cached.
+#[pyfunction]
+fn load_typed(config_path: &str, schema_path: &str) -> PyResult<SnapConfig> {
+    let source = Path::new(config_path);
+    if !source.exists() {
+        return Err(SnapconfigError::FileNotFound(config_path.to_string()).into());
+    }
+    let mut flat = parsers::parse_content(&fs::read_to_string(source)?, source)?;
+    let schema: toml::Table =
+        toml::from_str(&fs::read_to_string(schema_path)?).map_err(SnapconfigError::from)?;
+    schema::apply_schema(&mut flat, &schema)?;
+    Ok(in_memory_config(
+        &flat,
+        false,
+        Some(config_path.to_string()),
+    )?)
+}
+
 /// Parse content from string without caching.
 #[pyfunction]
-#[pyo3(signature = (content, format="json"))]
-fn loads(py: Python<'_>, content: &str, format: &str) -> PyResult<PyObject> {
-    let flat_value = match format.to_lowercase().as_str() {
-        "json" => parsers::parse_json(content)?,
-        "yaml" | "yml" => parsers::parse_yaml(content)?,
-        "toml" => parsers::parse_toml(content)?,
-        "ini" | "cfg" => parsers::parse_ini(content)?,
-        "env" => parsers::parse_env(content),
-        _ => return Err(PyValueError::new_err(format!("Unknown format: {}", format))),
+#[pyo3(signature = (
+    content,
+    format="json",
+    preserve_order=false,
+    trim_keys=true,
+    strict_keys=false,
+    strip_comments=false,
+    allow_nan=false,
+    strict=false,
+    reject_duplicate_keys=None,
+    reject_non_finite=None,
+    reject_overflow=None,
+    multi=false,
+    emit_warnings=false,
+    toml_merge_key=None,
+    csv_delimiter=",",
+    csv_has_header=true,
+    max_keys=None,
+    max_array_len=None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn loads(
+    py: Python<'_>,
+    content: &str,
+    format: &str,
+    preserve_order: bool,
+    trim_keys: bool,
+    strict_keys: bool,
+    strip_comments: bool,
+    allow_nan: bool,
+    strict: bool,
+    reject_duplicate_keys: Option<bool>,
+    reject_non_finite: Option<bool>,
+    reject_overflow: Option<bool>,
+    multi: bool,
+    emit_warnings: bool,
+    toml_merge_key: Option<String>,
+    csv_delimiter: &str,
+    csv_has_header: bool,
+    max_keys: Option<usize>,
+    max_array_len: Option<usize>,
+) -> PyResult<PyObject> {
+    // An unknown `format` name is always an error here.
+    let opts = ParseOptions {
+        preserve_order,
+        trim_keys,
+        strict_keys,
+        strip_comments,
+        allow_nan,
+        emit_warnings,
+        toml_merge_key,
+        csv_delimiter: csv_delimiter_byte(csv_delimiter)?,
+        csv_has_header,
+        max_keys,
+        max_array_len,
+        ..strict_options(
+            strict,
+            reject_duplicate_keys,
+            reject_non_finite,
+            reject_overflow,
+            None,
+        )
     };
+    let parsed = if multi {
+        if !matches!(format.to_lowercase().as_str(), "yaml" | "yml") {
+            return Err(PyValueError::new_err(format!(
+                "multi=True is only supported for YAML, not {}",
+                format
+            )));
+        }
+        parsers::parse_yaml_multi_opts(content, &opts)
+            .map_err(|err| parsers::locate_error(err, content, None))
+            .and_then(|flat| parsers::check_non_finite(&flat, &opts).map(|_| flat))
+            .map_err(PyErr::from)
+    } else {
+        parse_named_format(content, format, &opts)
+    };
+    emit_parse_warnings(py)?;
+    config::flat_value_to_python(py, &parsed?)
+}
 
-    config::flat_value_to_python(py, &flat_value)
+/// Like `loads`, for a `bytes` buffer, taking the same options. A leading
+/// UTF-8 BOM is skipped.
+#[pyfunction]
+#[pyo3(signature = (
+    data,
+    format="json",
+    preserve_order=false,
+    trim_keys=true,
+    strict_keys=false,
+    strip_comments=false,
+    allow_nan=false,
+    strict=false,
+    reject_duplicate_keys=None,
+    reject_non_finite=None,
+    reject_overflow=None,
+    multi=false,
+    emit_warnings=false,
+    toml_merge_key=None,
+    csv_delimiter=",",
+    csv_has_header=true,
+    max_keys=None,
+    max_array_len=None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn loads_bytes(
+    py: Python<'_>,
+    data: &[u8],
+    format: &str,
+    preserve_order: bool,
+    trim_keys: bool,
+    strict_keys: bool,
+    strip_comments: bool,
+    allow_nan: bool,
+    strict: bool,
+    reject_duplicate_keys: Option<bool>,
+    reject_non_finite: Option<bool>,
+    reject_overflow: Option<bool>,
+    multi: bool,
+    emit_warnings: bool,
+    toml_merge_key: Option<String>,
+    csv_delimiter: &str,
+    csv_has_header: bool,
+    max_keys: Option<usize>,
+    max_array_len: Option<usize>,
+) -> PyResult<PyObject> {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let content = std::str::from_utf8(data)
+        .map_err(|e| PyValueError::new_err(format!("Config is not valid UTF-8: {}", e)))?;
+    loads(
+        py,
+        content,
+        format,
+        preserve_order,
+        trim_keys,
+        strict_keys,
+        strip_comments,
+        allow_nan,
+        strict,
+        reject_duplicate_keys,
+        reject_non_finite,
+        reject_overflow,
+        multi,
+        emit_warnings,
+        toml_merge_key,
+        csv_delimiter,
+        csv_has_header,
+        max_keys,
+        max_array_len,
+    )
+}
+
+fn parse_named_format(content: &str, format: &str, opts: &ParseOptions) -> PyResult<FlatValue> {
+    let flat = match format.to_lowercase().as_str() {
+        "json" => parsers::parse_json_opts(content, opts),
+        "json5" => parsers::parse_json5_opts(content, opts),
+        "yaml" | "yml" => parsers::parse_yaml_opts(content, opts),
+        "toml" => parsers::parse_toml_opts(content, opts),
+        "ini" | "cfg" => parsers::parse_ini_opts(content, opts),
+        "env" => parsers::parse_env_opts(content, opts),
+        "properties" => parsers::parse_properties_opts(content, opts),
+        "xml" => parsers::parse_xml_opts(content, opts),
+        "ron" => parsers::parse_ron_opts(content, opts),
+        "csv" => parsers::parse_csv_opts(content, opts),
+        _ => return Err(PyValueError::new_err(format!("Unknown format: {}", format))),
+    }
+    .map_err(|err| parsers::locate_error(err, content, None))?;
+    parsers::check_non_finite(&flat, opts)?;
+    Ok(flat)
 }
 
 #[pyfunction]
-#[pyo3(signature = (path=".env", cache_path=None, force_recompile=false))]
-fn load_env(path: &str, cache_path: Option<&str>, force_recompile: bool) -> PyResult<SnapConfig> {
-    load(path, cache_path, force_recompile)
+#[pyo3(signature = (
+    path=".env",
+    cache_path=None,
+    force_recompile=false,
+    empty_as_null=false,
+    interpolate=false,
+))]
+fn load_env(
+    path: &str,
+    cache_path: Option<&str>,
+    force_recompile: bool,
+    empty_as_null: bool,
+    interpolate: bool,
+) -> PyResult<SnapConfig> {
+    let opts = ParseOptions {
+        empty_as_null,
+        interpolate,
+        ..ParseOptions::default()
+    };
+    load_with(
+        path,
+        cache_path,
+        force_recompile,
+        &opts,
+        None,
+        Validate::Mtime,
+    )
 }
 
-/// Load .env file and populate os.environ.
+/// Load .env file and populate os.environ. With `empty_as_null=True`, keys
+/// with empty values are left unset (or removed when overriding).
+/// `interpolate=True` expands `${VAR}` / `$VAR` from keys defined earlier in
+/// the file; unknown names expand to "" and single-quoted values stay literal.
 #[pyfunction]
-#[pyo3(signature = (path=".env", override_existing=false))]
-fn load_dotenv(py: Python<'_>, path: &str, override_existing: bool) -> PyResult<usize> {
-    let config = load_env(path, None, false)?;
+#[pyo3(signature = (path=".env", override_existing=false, empty_as_null=false, interpolate=false))]
+fn load_dotenv(
+    py: Python<'_>,
+    path: &str,
+    override_existing: bool,
+    empty_as_null: bool,
+    interpolate: bool,
+) -> PyResult<usize> {
+    let config = load_env(path, None, false, empty_as_null, interpolate)?;
     let os = py.import_bound("os")?;
     let environ = os.getattr("environ")?;
 
@@ -229,14 +1682,16 @@ fn load_dotenv(py: Python<'_>, path: &str, override_existing: bool) -> PyResult<
                 continue;
             }
 
+            if empty_as_null && matches!(value_node, value::ArchivedValueNode::Null) {
+                if exists {
+                    environ.del_item(key)?;
+                }
+                continue;
+            }
+
             // Convert value to string for os.environ
-            let value_str = match value_node {
-                value::ArchivedValueNode::String(s) => s.as_str().to_string(),
-                value::ArchivedValueNode::Int(i) => i.to_string(),
-                value::ArchivedValueNode::Float(f) => f.to_string(),
-                value::ArchivedValueNode::Bool(b) => if *b { "true" } else { "false" }.to_string(),
-                value::ArchivedValueNode::Null => String::new(),
-                _ => continue,
+            let Some(value_str) = config::scalar_to_string(value_node) else {
+                continue;
             };
 
             environ.set_item(key, value_str)?;
@@ -248,53 +1703,149 @@ fn load_dotenv(py: Python<'_>, path: &str, override_existing: bool) -> PyResult<
 }
 
 #[pyfunction]
-fn parse_env(py: Python<'_>, content: &str) -> PyResult<PyObject> {
-    let flat = parsers::parse_env(content);
+#[pyo3(signature = (content, sections=false, empty_as_null=false, interpolate=false))]
+fn parse_env(
+    py: Python<'_>,
+    content: &str,
+    sections: bool,
+    empty_as_null: bool,
+    interpolate: bool,
+) -> PyResult<PyObject> {
+    let opts = ParseOptions {
+        sections,
+        empty_as_null,
+        interpolate,
+        ..ParseOptions::default()
+    };
+    let flat = parsers::parse_env_opts(content, &opts)?;
     config::flat_value_to_python(py, &flat)
 }
 
-#[pyfunction]
-fn cache_info(source_path: &str) -> PyResult<HashMap<String, PyObject>> {
-    Python::with_gil(|py| {
-        let mut info = HashMap::new();
+/// Filesystem facts behind `cache_info`, gathered without holding the GIL.
+struct CacheStatus {
+    source_exists: bool,
+    cache_exists: bool,
+    cache_path: String,
+    source_size: Option<u64>,
+    cache_size: Option<u64>,
+    cache_fresh: Option<bool>,
+    /// Node count and root type of the cache, when it opens and validates.
+    contents: Option<(usize, &'static str)>,
+    format: Option<Format>,
+}
+
+impl CacheStatus {
+    fn stat(source_path: &str) -> Self {
         let source = Path::new(source_path);
         let cache_path = format!("{}.snapconfig", source_path);
         let cache = Path::new(&cache_path);
+        let source_meta = source.metadata().ok();
+        let cache_meta = cache.metadata().ok();
 
-        info.insert("source_exists".to_string(), source.exists().to_object(py));
-        info.insert("cache_exists".to_string(), cache.exists().to_object(py));
-        info.insert("cache_path".to_string(), cache_path.clone().to_object(py));
+        // An overlay cache is fresh only if no input, the source included,
+        // changed after it or went missing.
+        let inputs = read_cache_header(cache)
+            .filter(|(flags, _)| flags & CACHE_FLAG_OVERLAY != 0)
+            .and_then(|(flags, _)| read_cache_file_lists(cache, flags))
+            .map(|lists| lists.overlay_inputs)
+            .unwrap_or_else(|| vec![source_path.to_string()]);
+        let cache_fresh = match (&source_meta, &cache_meta) {
+            (Some(_), Some(cache_meta)) => cache_meta.modified().ok().map(|cache_mod| {
+                inputs.iter().all(|input| {
+                    Path::new(input)
+                        .metadata()
+                        .and_then(|meta| meta.modified())
+                        .is_ok_and(|modified| modified <= cache_mod)
+                })
+            }),
+            _ => None,
+        };
+        let contents = cache_meta
+            .as_ref()
+            .and_then(|_| open_cache(&cache_path, None, false).ok())
+            .map(|config| {
+                let archived = config.archived();
+                let root_type = match archived
+                    .root
+                    .as_ref()
+                    .map(|&idx| &archived.nodes[idx as usize])
+                {
+                    Some(value::ArchivedValueNode::Object(_)) => "object",
+                    Some(value::ArchivedValueNode::Array(_)) => "array",
+                    _ => "scalar",
+                };
+                (archived.nodes.len(), root_type)
+            });
 
-        if source.exists() {
-            if let Ok(meta) = source.metadata() {
-                info.insert("source_size".to_string(), (meta.len() as i64).to_object(py));
-            }
+        Self {
+            source_exists: source.exists(),
+            cache_exists: cache.exists(),
+            source_size: source_meta.map(|m| m.len()),
+            cache_size: cache_meta.map(|m| m.len()),
+            cache_fresh,
+            contents,
+            format: Format::from_path(source),
+            cache_path,
         }
+    }
 
-        if cache.exists() {
-            if let Ok(meta) = cache.metadata() {
-                info.insert("cache_size".to_string(), (meta.len() as i64).to_object(py));
-            }
-            if source.exists() {
-                if let (Ok(source_mod), Ok(cache_mod)) = (
-                    source.metadata().and_then(|m| m.modified()),
-                    cache.metadata().and_then(|m| m.modified()),
-                ) {
-                    info.insert(
-                        "cache_fresh".to_string(),
-                        (cache_mod >= source_mod).to_object(py),
-                    );
-                }
-            }
+    fn into_info(self, py: Python<'_>) -> HashMap<String, PyObject> {
+        let mut info = HashMap::new();
+        info.insert(
+            "source_exists".to_string(),
+            self.source_exists.to_object(py),
+        );
+        info.insert("cache_exists".to_string(), self.cache_exists.to_object(py));
+        info.insert("cache_path".to_string(), self.cache_path.to_object(py));
+        if let Some(size) = self.source_size {
+            info.insert("source_size".to_string(), (size as i64).to_object(py));
+        }
+        if let Some(size) = self.cache_size {
+            info.insert("cache_size".to_string(), (size as i64).to_object(py));
+        }
+        if let Some(fresh) = self.cache_fresh {
+            info.insert("cache_fresh".to_string(), fresh.to_object(py));
         }
+        let (node_count, root_type) = self.contents.unzip();
+        info.insert("node_count".to_string(), node_count.to_object(py));
+        info.insert("root_type".to_string(), root_type.to_object(py));
+        info.insert(
+            "format".to_string(),
+            self.format.map(Format::name).to_object(py),
+        );
+        info
+    }
+}
 
-        Ok(info)
-    })
+#[pyfunction]
+fn cache_info(py: Python<'_>, source_path: &str) -> PyResult<HashMap<String, PyObject>> {
+    Ok(CacheStatus::stat(source_path).into_info(py))
+}
+
+/// `cache_info` for many sources at once, keyed by source path.
+#[pyfunction]
+fn cache_info_many(
+    py: Python<'_>,
+    source_paths: Vec<String>,
+) -> PyResult<HashMap<String, HashMap<String, PyObject>>> {
+    let statuses: Vec<CacheStatus> = py.allow_threads(|| {
+        source_paths
+            .iter()
+            .map(|path| CacheStatus::stat(path))
+            .collect()
+    });
+    Ok(source_paths
+        .into_iter()
+        .zip(statuses)
+        .map(|(path, status)| (path, status.into_info(py)))
+        .collect())
 }
 
 #[pyfunction]
-fn clear_cache(source_path: &str) -> PyResult<bool> {
-    let cache_path = format!("{}.snapconfig", source_path);
+#[pyo3(signature = (source_path, cache_dir=None))]
+fn clear_cache(source_path: &str, cache_dir: Option<&str>) -> PyResult<bool> {
+    let cache_path = resolve_cache_path(source_path, None, cache_dir)?
+        .unwrap_or_else(|| format!("{}.snapconfig", source_path));
     let cache = Path::new(&cache_path);
 
     if cache.exists() {
@@ -308,14 +1859,102 @@ fn clear_cache(source_path: &str) -> PyResult<bool> {
 #[pymodule]
 fn snapconfig(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<SnapConfig>()?;
+    m.add_class::<config::Secret>()?;
+    m.add_class::<config::LazyDict>()?;
+    m.add_class::<config::EnvironMap>()?;
+    // Registered so isinstance(x, collections.abc.Mapping) holds.
+    let mapping = m.py().import_bound("collections.abc")?.getattr("Mapping")?;
+    mapping.call_method1("register", (m.py().get_type_bound::<config::LazyDict>(),))?;
+    mapping.call_method1("register", (m.py().get_type_bound::<config::EnvironMap>(),))?;
+    m.add(
+        "SnapconfigWarning",
+        m.py().get_type_bound::<SnapconfigWarning>(),
+    )?;
     m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(compile_stream, m)?)?;
+    m.add_function(wrap_pyfunction!(compile_overlay, m)?)?;
+    m.add_function(wrap_pyfunction!(compile_dir, m)?)?;
     m.add_function(wrap_pyfunction!(load, m)?)?;
     m.add_function(wrap_pyfunction!(load_compiled, m)?)?;
+    m.add_function(wrap_pyfunction!(load_typed, m)?)?;
+    m.add_function(wrap_pyfunction!(merge, m)?)?;
+    m.add_function(wrap_pyfunction!(load_layered, m)?)?;
     m.add_function(wrap_pyfunction!(loads, m)?)?;
+    m.add_function(wrap_pyfunction!(loads_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(load_fileobj, m)?)?;
+    #[cfg(feature = "archive")]
+    m.add_function(wrap_pyfunction!(load_from_tar, m)?)?;
     m.add_function(wrap_pyfunction!(load_env, m)?)?;
     m.add_function(wrap_pyfunction!(load_dotenv, m)?)?;
     m.add_function(wrap_pyfunction!(parse_env, m)?)?;
     m.add_function(wrap_pyfunction!(cache_info, m)?)?;
+    m.add_function(wrap_pyfunction!(cache_info_many, m)?)?;
     m.add_function(wrap_pyfunction!(clear_cache, m)?)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_json(dir: &Path, name: &str, content: &str) -> String {
+        let source = dir.join(name);
+        fs::write(&source, content).unwrap();
+        let opts = ParseOptions::default();
+        let (bytes, hash) = compile_bytes(&source, &opts).unwrap();
+        let cache = dir.join(format!("{}.snapconfig", name));
+        write_cache(&cache, &bytes, &opts, hash, false).unwrap();
+        cache.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_open_cache_reads_small_and_maps_large() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let small = compile_json(dir.path(), "small.json", r#"{"port": 8080}"#);
+        assert!(fs::metadata(&small).unwrap().len() < SMALL_CACHE_LEN);
+        let config = open_cache(&small, None, false).unwrap();
+        assert!(!config.is_mapped());
+        assert_eq!(config.to_flat_value().nodes[0], ValueNode::Int(8080));
+
+        let entries: Vec<String> = (0..500).map(|i| format!(r#""key{}": {}"#, i, i)).collect();
+        let large = compile_json(
+            dir.path(),
+            "large.json",
+            &format!("{{{}}}", entries.join(",")),
+        );
+        assert!(fs::metadata(&large).unwrap().len() >= SMALL_CACHE_LEN);
+        let config = open_cache(&large, None, false).unwrap();
+        assert!(config.is_mapped());
+        assert_eq!(config.to_flat_value().nodes.len(), 501);
+    }
+
+    #[test]
+    fn test_cache_lists_keep_payload_aligned() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("config.json");
+        fs::write(&source, r#"{"host": "db", "port": 5432}"#).unwrap();
+        let opts = ParseOptions {
+            deprecated: vec!["host=database.host".to_string()],
+            ..ParseOptions::default()
+        };
+        let (bytes, hash) = compile_bytes(&source, &opts).unwrap();
+        let cache = dir.path().join("config.json.snapconfig");
+        let inputs = vec!["a.json".to_string(), "b.yaml".to_string()];
+        write_cache_with_inputs(&cache, &bytes, &opts, hash, false, &inputs).unwrap();
+
+        let raw = fs::read(&cache).unwrap();
+        let flags = u32::from_le_bytes(raw[12..16].try_into().unwrap());
+        let lists = read_cache_lists(&raw, flags).unwrap();
+        assert_eq!(lists.overlay_inputs, inputs);
+        assert_eq!(lists.deprecated, opts.deprecated);
+        assert_eq!(lists.data_offset % 16, 0);
+
+        let config = open_cache(cache.to_str().unwrap(), None, false).unwrap();
+        assert_eq!(
+            config.deprecated().get("host"),
+            Some(&Some("database.host".to_string()))
+        );
+        assert_eq!(config.to_flat_value().nodes.len(), 3);
+    }
+}
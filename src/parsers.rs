@@ -1,16 +1,326 @@
 //! Format parsers for snapconfig.
 
+use crate::conditions::apply_conditions;
 use crate::error::{Result, SnapconfigError};
-use crate::value::{FlatValue, ValueIdx, ValueNode};
-use ini::Ini;
+use crate::value::{DatetimeKind, FlatValue, ValueIdx, ValueNode};
+use ini::{Ini, ParseOption};
+use serde::de::DeserializeSeed;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
 use std::path::Path;
 
-fn sort_pairs(pairs: &mut Vec<(String, ValueIdx)>) {
+/// Options controlling how source documents are flattened.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Keep object keys in document order instead of sorting them.
+    pub preserve_order: bool,
+    /// Strip stray leading whitespace from INI/env keys.
+    pub trim_keys: bool,
+    /// Reject INI/env keys that carry stray leading whitespace.
+    pub strict_keys: bool,
+    /// Remove `//` and `/* */` comments from JSON before parsing.
+    pub strip_comments: bool,
+    /// Read bare `NaN`, `Infinity` and `-Infinity` in JSON as floats, as
+    /// JSON5 and Python's `json` module do.
+    pub allow_nan: bool,
+    /// Nest env keys under `[name]` header lines, INI-style.
+    pub sections: bool,
+    /// Read unquoted empty env values (`KEY=`) as null instead of "".
+    pub empty_as_null: bool,
+    /// Expand `${VAR}` / `$VAR` in env values from keys defined earlier.
+    pub interpolate: bool,
+    /// When set, objects with a `when` predicate are kept or dropped by
+    /// evaluating it against this context.
+    pub conditions: Option<HashMap<String, String>>,
+    /// Store equal scalar values once and share their index.
+    pub dedup: bool,
+    /// Fail on repeated keys in one JSON/INI/env/properties object instead
+    /// of letting the last one win.
+    pub reject_duplicate_keys: bool,
+    /// Fail on `inf`/`nan` floats.
+    pub reject_non_finite: bool,
+    /// Fail on INI/env/properties integers too wide for 64 bits instead of
+    /// reading them as floats.
+    pub reject_overflow: bool,
+    /// Fail on unrecognised file extensions instead of parsing them as env.
+    pub reject_unknown_format: bool,
+    /// Record soft issues (merged duplicate keys, non-finite floats) for
+    /// `take_warnings` instead of passing over them silently.
+    pub emit_warnings: bool,
+    /// Merge TOML array-of-tables entries that share a value for this
+    /// field into one entry.
+    pub toml_merge_key: Option<String>,
+    /// Field separator for CSV.
+    pub csv_delimiter: u8,
+    /// Read the first CSV row as column names (rows become objects) rather
+    /// than as data (rows become arrays).
+    pub csv_has_header: bool,
+    /// Fail when any one object has more keys than this.
+    pub max_keys: Option<usize>,
+    /// Fail when any one array has more elements than this.
+    pub max_array_len: Option<usize>,
+    /// Paths recorded in the cache as deprecated, each `old.path` or
+    /// `old.path=new.path` naming a replacement. Not used by the parsers.
+    pub deprecated: Vec<String>,
+    /// Keys were rewritten by `compile`'s `key_transform`, which the cache
+    /// records but cannot store. Not used by the parsers.
+    pub key_transformed: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            preserve_order: false,
+            trim_keys: true,
+            strict_keys: false,
+            strip_comments: false,
+            allow_nan: false,
+            sections: false,
+            empty_as_null: false,
+            interpolate: false,
+            conditions: None,
+            dedup: false,
+            reject_duplicate_keys: false,
+            reject_non_finite: false,
+            reject_overflow: false,
+            reject_unknown_format: false,
+            emit_warnings: false,
+            toml_merge_key: None,
+            csv_delimiter: b',',
+            csv_has_header: true,
+            max_keys: None,
+            max_array_len: None,
+            deprecated: Vec::new(),
+            key_transformed: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Sets every `reject_*` check at once; the "fail loud" preset.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.reject_duplicate_keys = strict;
+        self.reject_non_finite = strict;
+        self.reject_overflow = strict;
+        self.reject_unknown_format = strict;
+        self
+    }
+}
+
+fn sort_pairs(pairs: &mut [(String, ValueIdx)]) {
     pairs.sort_by(|a, b| a.0.cmp(&b.0));
 }
 
-fn parse_scalar_value(flat: &mut FlatValue, value: &str) -> ValueIdx {
-    if value.is_empty() {
+/// Sorts by key and collapses repeated keys, last value winning. Cheaper
+/// than `dedup_pairs` for the many small objects of large documents.
+fn sort_dedup_pairs(mut pairs: Vec<(String, ValueIdx)>) -> Vec<(String, ValueIdx)> {
+    // The sort is stable, so each run of equal keys stays in file order.
+    sort_pairs(&mut pairs);
+    let mut out: Vec<(String, ValueIdx)> = Vec::with_capacity(pairs.len());
+    for pair in pairs {
+        match out.last_mut() {
+            Some(last) if last.0 == pair.0 => *last = pair,
+            _ => out.push(pair),
+        }
+    }
+    out
+}
+
+/// Collapses repeated keys (last value wins) while keeping first-seen order.
+fn dedup_pairs(pairs: Vec<(String, ValueIdx)>) -> Vec<(String, ValueIdx)> {
+    let mut seen: HashMap<String, usize> = HashMap::with_capacity(pairs.len());
+    let mut out: Vec<(String, ValueIdx)> = Vec::with_capacity(pairs.len());
+    for (key, idx) in pairs {
+        match seen.get(&key) {
+            Some(&pos) => out[pos].1 = idx,
+            None => {
+                seen.insert(key.clone(), out.len());
+                out.push((key, idx));
+            }
+        }
+    }
+    out
+}
+
+fn check_duplicate_keys(pairs: &[(String, ValueIdx)], opts: &ParseOptions) -> Result<()> {
+    if !opts.reject_duplicate_keys && !opts.emit_warnings {
+        return Ok(());
+    }
+    let mut seen = std::collections::HashSet::with_capacity(pairs.len());
+    let mut reported = std::collections::HashSet::new();
+    for (key, _) in pairs {
+        if seen.insert(key.as_str()) {
+            continue;
+        }
+        if opts.reject_duplicate_keys {
+            return Err(SnapconfigError::DuplicateKey(format!("'{}'", key)));
+        }
+        if reported.insert(key.as_str()) {
+            warn(opts, format!("Duplicate key '{}' was merged", key));
+        }
+    }
+    Ok(())
+}
+
+thread_local! {
+    static WARNINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Records `message` when `opts.emit_warnings` is set.
+fn warn(opts: &ParseOptions, message: String) {
+    if opts.emit_warnings {
+        WARNINGS.with(|warnings| warnings.borrow_mut().push(message));
+    }
+}
+
+/// Drains the warnings recorded on this thread since the last call.
+pub fn take_warnings() -> Vec<String> {
+    WARNINGS.with(|warnings| warnings.take())
+}
+
+/// Fails on the first `inf`/`nan` float when `opts.reject_non_finite` is set,
+/// naming its dotted path; otherwise warns about each one.
+pub fn check_non_finite(flat: &FlatValue, opts: &ParseOptions) -> Result<()> {
+    fn walk(
+        flat: &FlatValue,
+        idx: ValueIdx,
+        path: &mut Vec<String>,
+        opts: &ParseOptions,
+    ) -> Result<()> {
+        match &flat.nodes[idx as usize] {
+            ValueNode::Float(f) if !f.is_finite() => {
+                let detail = format!("'{}' is {}", path.join("."), f);
+                if opts.reject_non_finite {
+                    return Err(SnapconfigError::NonFiniteFloat(detail));
+                }
+                warn(opts, format!("Non-finite float: {}", detail));
+                Ok(())
+            }
+            ValueNode::Array(indices) => indices.iter().enumerate().try_for_each(|(i, &child)| {
+                path.push(i.to_string());
+                walk(flat, child, path, opts)?;
+                path.pop();
+                Ok(())
+            }),
+            ValueNode::Object(pairs) => pairs.iter().try_for_each(|(key, child)| {
+                path.push(key.clone());
+                walk(flat, *child, path, opts)?;
+                path.pop();
+                Ok(())
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    match flat.root() {
+        Some(root) if opts.reject_non_finite || opts.emit_warnings => {
+            walk(flat, root, &mut Vec::new(), opts)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fails when an object of `len` keys is over `opts.max_keys`. Builders
+/// call this as each object is finished; the error starts with an empty
+/// path, which `within` fills in as it leaves the enclosing containers.
+fn check_keys(len: usize, opts: &ParseOptions) -> Result<()> {
+    check_limit(len, opts.max_keys, "keys", "max_keys")
+}
+
+/// `check_keys` for an array of `len` elements and `opts.max_array_len`.
+fn check_elements(len: usize, opts: &ParseOptions) -> Result<()> {
+    check_limit(len, opts.max_array_len, "elements", "max_array_len")
+}
+
+fn check_limit(
+    len: usize,
+    max: Option<usize>,
+    what: &'static str,
+    option: &'static str,
+) -> Result<()> {
+    match max {
+        Some(max) if len > max => Err(SnapconfigError::LimitExceeded {
+            path: Vec::new(),
+            len,
+            what,
+            option,
+            max,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Prefixes the path of a limit error raised below `segment` (a key or
+/// array index). Other errors pass through.
+fn within(mut err: SnapconfigError, segment: &str) -> SnapconfigError {
+    if let SnapconfigError::LimitExceeded { path, .. } = &mut err {
+        path.insert(0, segment.to_string());
+    }
+    err
+}
+
+/// Turns keys that occur more than once into one array of their values, in
+/// file order, at the key's first position.
+fn group_repeated_keys(
+    flat: &mut FlatValue,
+    pairs: Vec<(String, ValueIdx)>,
+    opts: &ParseOptions,
+) -> Result<Vec<(String, ValueIdx)>> {
+    let mut positions: HashMap<String, usize> = HashMap::with_capacity(pairs.len());
+    let mut grouped: Vec<(String, Vec<ValueIdx>)> = Vec::with_capacity(pairs.len());
+    for (key, idx) in pairs {
+        match positions.get(&key) {
+            Some(&pos) => grouped[pos].1.push(idx),
+            None => {
+                positions.insert(key.clone(), grouped.len());
+                grouped.push((key, vec![idx]));
+            }
+        }
+    }
+    grouped
+        .into_iter()
+        .map(|(key, mut indices)| {
+            let idx = if indices.len() == 1 {
+                indices.pop().unwrap()
+            } else {
+                check_elements(indices.len(), opts).map_err(|e| within(e, &key))?;
+                flat.add_node(ValueNode::Array(indices))
+            };
+            Ok((key, idx))
+        })
+        .collect()
+}
+
+/// Normalizes a raw INI/env key. Whitespace before the separator is always
+/// padding; leading whitespace is trimmed, kept, or rejected per `opts`.
+fn normalize_key(raw: &str, opts: &ParseOptions) -> Result<String> {
+    let key = raw.trim_end();
+    if key.len() == key.trim_start().len() {
+        return Ok(key.to_string());
+    }
+    if opts.strict_keys {
+        return Err(SnapconfigError::InvalidKey(format!(
+            "'{}' has leading whitespace",
+            key
+        )));
+    }
+    if opts.trim_keys {
+        Ok(key.trim_start().to_string())
+    } else {
+        Ok(key.to_string())
+    }
+}
+
+fn parse_scalar_value(flat: &mut FlatValue, value: &str, opts: &ParseOptions) -> Result<ValueIdx> {
+    if opts.reject_overflow && looks_like_integer(value) && value.parse::<i64>().is_err() {
+        return Err(SnapconfigError::NumberOverflow(format!(
+            "{} does not fit in a 64-bit integer",
+            value
+        )));
+    }
+    Ok(if value.is_empty() {
         flat.add_node(ValueNode::String(String::new()))
     } else if value.eq_ignore_ascii_case("true") {
         flat.add_node(ValueNode::Bool(true))
@@ -27,81 +337,586 @@ fn parse_scalar_value(flat: &mut FlatValue, value: &str) -> ValueIdx {
         flat.add_node(ValueNode::Float(f))
     } else {
         flat.add_node(ValueNode::String(value.to_string()))
+    })
+}
+
+/// Replaces scalar values whose env name is set in `env`. A key's env name is
+/// `prefix` plus its upper-cased path segments joined by `separator`, so
+/// `database.host` is `APP_DATABASE_HOST` for prefix "APP_" and separator
+/// "_". Values are read like env-file values: "true" becomes a bool.
+pub fn apply_env_overrides(
+    flat: &mut FlatValue,
+    env: &HashMap<String, String>,
+    prefix: &str,
+    separator: &str,
+    opts: &ParseOptions,
+) -> Result<()> {
+    fn walk(
+        flat: &mut FlatValue,
+        idx: ValueIdx,
+        name_prefix: &str,
+        env: &HashMap<String, String>,
+        separator: &str,
+        opts: &ParseOptions,
+    ) -> Result<()> {
+        let ValueNode::Object(pairs) = &flat.nodes[idx as usize] else {
+            return Ok(());
+        };
+        for (pos, (key, child)) in pairs.clone().into_iter().enumerate() {
+            let name = format!("{}{}", name_prefix, key.to_uppercase());
+            match &flat.nodes[child as usize] {
+                ValueNode::Object(_) => walk(
+                    flat,
+                    child,
+                    &format!("{}{}", name, separator),
+                    env,
+                    separator,
+                    opts,
+                )?,
+                ValueNode::Array(_) => {}
+                _ => {
+                    if let Some(value) = env.get(&name) {
+                        // Repoint the key rather than overwrite the node, which
+                        // may be shared after dedup.
+                        let replacement = parse_scalar_value(flat, value, opts)?;
+                        if let ValueNode::Object(pairs) = &mut flat.nodes[idx as usize] {
+                            pairs[pos].1 = replacement;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    match flat.root() {
+        Some(root) => walk(flat, root, prefix, env, separator, opts),
+        None => Ok(()),
     }
 }
+
+fn looks_like_integer(value: &str) -> bool {
+    let digits = value.strip_prefix(['-', '+']).unwrap_or(value);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
 pub fn parse_json(content: &str) -> Result<FlatValue> {
-    let mut bytes = content.as_bytes().to_vec();
-    let parsed = simd_json::to_owned_value(&mut bytes)?;
-    Ok(from_simd_json(parsed))
+    parse_json_opts(content, &ParseOptions::default())
+}
+
+/// Blanks out `//` and `/* */` comments outside string literals. Comments
+/// become spaces (newlines are kept) so parser offsets still line up.
+fn strip_json_comments(content: &str) -> Vec<u8> {
+    let src = content.as_bytes();
+    let mut out = Vec::with_capacity(src.len());
+    let mut i = 0;
+    let mut in_string = false;
+
+    while i < src.len() {
+        let b = src[i];
+        if in_string {
+            out.push(b);
+            if b == b'\\' && i + 1 < src.len() {
+                out.push(src[i + 1]);
+                i += 1;
+            } else if b == b'"' {
+                in_string = false;
+            }
+            i += 1;
+        } else if b == b'"' {
+            in_string = true;
+            out.push(b);
+            i += 1;
+        } else if b == b'/' && src.get(i + 1) == Some(&b'/') {
+            while i < src.len() && src[i] != b'\n' {
+                out.push(b' ');
+                i += 1;
+            }
+        } else if b == b'/' && src.get(i + 1) == Some(&b'*') {
+            out.extend_from_slice(b"  ");
+            i += 2;
+            while i < src.len() && !(src[i] == b'*' && src.get(i + 1) == Some(&b'/')) {
+                out.push(if src[i] == b'\n' { b'\n' } else { b' ' });
+                i += 1;
+            }
+            if i < src.len() {
+                out.extend_from_slice(b"  ");
+                i += 2;
+            }
+        } else {
+            out.push(b);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Rewrites bare `NaN`/`Infinity` literals (optionally signed) outside
+/// string literals to `null`, which serde_json accepts. Returns the new text
+/// and what every `null` in it stands for, in document order: `None` for
+/// the ones that were there already. `None` overall when nothing changed.
+fn replace_non_finite_literals(src: &[u8]) -> Option<(Vec<u8>, Vec<Option<f64>>)> {
+    let mut out = Vec::with_capacity(src.len());
+    let mut nulls = Vec::new();
+    let mut replaced = false;
+    let mut i = 0;
+    let mut in_string = false;
+
+    while i < src.len() {
+        let b = src[i];
+        if in_string {
+            out.push(b);
+            if b == b'\\' && i + 1 < src.len() {
+                out.push(src[i + 1]);
+                i += 1;
+            } else if b == b'"' {
+                in_string = false;
+            }
+            i += 1;
+        } else if b == b'"' {
+            in_string = true;
+            out.push(b);
+            i += 1;
+        } else if b.is_ascii_alphabetic()
+            || (matches!(b, b'-' | b'+') && src.get(i + 1).is_some_and(u8::is_ascii_alphabetic))
+        {
+            let start = i;
+            i += 1;
+            while i < src.len() && src[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let value = match &src[start..i] {
+                b"NaN" | b"-NaN" | b"+NaN" => f64::NAN,
+                b"Infinity" | b"+Infinity" => f64::INFINITY,
+                b"-Infinity" => f64::NEG_INFINITY,
+                token => {
+                    if token == b"null" {
+                        nulls.push(None);
+                    }
+                    out.extend_from_slice(token);
+                    continue;
+                }
+            };
+            nulls.push(Some(value));
+            out.extend_from_slice(b"null");
+            replaced = true;
+        } else {
+            out.push(b);
+            i += 1;
+        }
+    }
+    replaced.then_some((out, nulls))
+}
+
+pub fn parse_json_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    let mut bytes = if opts.strip_comments {
+        Cow::Owned(strip_json_comments(content))
+    } else {
+        Cow::Borrowed(content.as_bytes())
+    };
+    let mut non_finite = Vec::new();
+    if opts.allow_nan {
+        if let Some((rewritten, nulls)) = replace_non_finite_literals(&bytes) {
+            bytes = Cow::Owned(rewritten);
+            non_finite = nulls;
+        }
+    }
+    // Nodes are built as the parser reads them, so nothing but the input and
+    // the FlatValue is held at once. Keys arrive in document order,
+    // duplicates included.
+    let mut builder = JsonBuilder {
+        flat: FlatValue::new(),
+        opts,
+        error: None,
+        depth: 0,
+    };
+    let mut de = serde_json::Deserializer::from_slice(&bytes);
+    // serde_json's own limit of 128 levels is lower than real configs need;
+    // the builder enforces `MAX_JSON_DEPTH` instead.
+    de.disable_recursion_limit();
+    let root_idx = JsonSeed(&mut builder)
+        .deserialize(&mut de)
+        .and_then(|root_idx| de.end().map(|()| root_idx))
+        .map_err(|e| {
+            builder
+                .error
+                .take()
+                .unwrap_or(SnapconfigError::JsonParse(e))
+        })?;
+    let mut flat = builder.flat;
+    flat.set_root(root_idx);
+    if !non_finite.is_empty() {
+        // Leaves are added in document order, so the Null nodes line up
+        // with the `null` literals recorded while rewriting.
+        let mut values = non_finite.into_iter();
+        for node in flat.nodes.iter_mut() {
+            if *node == ValueNode::Null {
+                if let Some(f) = values.next().flatten() {
+                    *node = ValueNode::Float(f);
+                }
+            }
+        }
+    }
+    Ok(flat)
+}
+
+/// Deepest JSON nesting accepted. Each level recurses through serde_json
+/// and `JsonSeed`, so without a cap hostile input overflows the stack.
+const MAX_JSON_DEPTH: usize = 512;
+
+/// State shared by the `JsonSeed`s of one parse. `error` keeps a
+/// snapconfig error raised mid-parse, which serde can only carry as text.
+/// `depth` counts the arrays and objects currently open.
+struct JsonBuilder<'a> {
+    flat: FlatValue,
+    opts: &'a ParseOptions,
+    error: Option<SnapconfigError>,
+    depth: usize,
+}
+
+impl JsonBuilder<'_> {
+    /// Opens one more array or object, failing past `MAX_JSON_DEPTH`.
+    fn enter<E: serde::de::Error>(&mut self) -> std::result::Result<(), E> {
+        self.depth += 1;
+        if self.depth > MAX_JSON_DEPTH {
+            return Err(E::custom(format!(
+                "recursion limit exceeded (more than {} levels)",
+                MAX_JSON_DEPTH
+            )));
+        }
+        Ok(())
+    }
+
+    /// Keeps `err` for `parse_json_opts` and hands serde its text.
+    fn fail<E: serde::de::Error>(&mut self, err: SnapconfigError) -> E {
+        let message = err.to_string();
+        self.error = Some(err);
+        E::custom(message)
+    }
+
+    /// Passes on an error from below `segment`, adding the segment to a
+    /// kept limit error's path.
+    fn within<E>(&mut self, e: E, segment: &str) -> E {
+        self.error = self.error.take().map(|err| within(err, segment));
+        e
+    }
+}
+
+/// Deserializes one JSON value straight into `FlatValue` nodes, returning
+/// its index.
+struct JsonSeed<'a, 'b>(&'a mut JsonBuilder<'b>);
+
+impl<'de> serde::de::DeserializeSeed<'de> for JsonSeed<'_, '_> {
+    type Value = ValueIdx;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<ValueIdx, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for JsonSeed<'_, '_> {
+    type Value = ValueIdx;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> std::result::Result<ValueIdx, E> {
+        Ok(self.0.flat.add_node(ValueNode::Bool(b)))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> std::result::Result<ValueIdx, E> {
+        Ok(self.0.flat.add_node(ValueNode::Int(i)))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> std::result::Result<ValueIdx, E> {
+        Ok(self.0.flat.add_node(match i64::try_from(u) {
+            Ok(i) => ValueNode::Int(i),
+            Err(_) => ValueNode::UInt(u),
+        }))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> std::result::Result<ValueIdx, E> {
+        Ok(self.0.flat.add_node(ValueNode::Float(f)))
+    }
+
+    fn visit_str<E>(self, s: &str) -> std::result::Result<ValueIdx, E> {
+        Ok(self.0.flat.add_node(ValueNode::String(s.to_string())))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<ValueIdx, E> {
+        Ok(self.0.flat.add_node(ValueNode::Null))
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<ValueIdx, A::Error> {
+        self.0.enter()?;
+        let mut indices = Vec::new();
+        loop {
+            match seq.next_element_seed(JsonSeed(&mut *self.0)) {
+                Ok(Some(idx)) => indices.push(idx),
+                Ok(None) => break,
+                Err(e) => return Err(self.0.within(e, &indices.len().to_string())),
+            }
+        }
+        if let Err(err) = check_elements(indices.len(), self.0.opts) {
+            return Err(self.0.fail(err));
+        }
+        self.0.depth -= 1;
+        Ok(self.0.flat.add_node(ValueNode::Array(indices)))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<ValueIdx, A::Error> {
+        self.0.enter()?;
+        let mut pairs = Vec::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let idx = map
+                .next_value_seed(JsonSeed(&mut *self.0))
+                .map_err(|e| self.0.within(e, &key))?;
+            pairs.push((key, idx));
+        }
+        let opts = self.0.opts;
+        if let Err(err) = check_duplicate_keys(&pairs, opts) {
+            return Err(self.0.fail(err));
+        }
+        let pairs = if opts.preserve_order {
+            dedup_pairs(pairs)
+        } else {
+            sort_dedup_pairs(pairs)
+        };
+        if let Err(err) = check_keys(pairs.len(), opts) {
+            return Err(self.0.fail(err));
+        }
+        self.0.depth -= 1;
+        Ok(self.0.flat.add_node(ValueNode::Object(pairs)))
+    }
+}
+
+/// Parse JSON5 (comments, trailing commas, unquoted keys) into the same
+/// tree `parse_json` produces.
+pub fn parse_json5(content: &str) -> Result<FlatValue> {
+    parse_json5_opts(content, &ParseOptions::default())
 }
 
-pub fn from_simd_json(value: simd_json::OwnedValue) -> FlatValue {
+pub fn parse_json5_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    let parsed: serde_json::Value =
+        json5::from_str(content).map_err(|e| SnapconfigError::Json5Parse(e.to_string()))?;
     let mut flat = FlatValue::new();
-    let root_idx = add_simd_json_value(&mut flat, value);
+    let root_idx = add_json5_value(&mut flat, parsed, opts)?;
     flat.set_root(root_idx);
-    flat
+    Ok(flat)
 }
 
-fn add_simd_json_value(flat: &mut FlatValue, value: simd_json::OwnedValue) -> ValueIdx {
-    use simd_json::prelude::*;
+fn add_json5_value(
+    flat: &mut FlatValue,
+    value: serde_json::Value,
+    opts: &ParseOptions,
+) -> Result<ValueIdx> {
+    use serde_json::Value;
 
-    if value.is_null() {
-        flat.add_node(ValueNode::Null)
-    } else if let Some(b) = value.as_bool() {
-        flat.add_node(ValueNode::Bool(b))
-    } else if let Some(i) = value.as_i64() {
-        flat.add_node(ValueNode::Int(i))
-    } else if let Some(f) = value.as_f64() {
-        flat.add_node(ValueNode::Float(f))
-    } else if let Some(s) = value.as_str() {
-        flat.add_node(ValueNode::String(s.to_string()))
-    } else if value.is_array() {
-        if let Some(arr) = value.into_array() {
+    Ok(match value {
+        Value::Null => flat.add_node(ValueNode::Null),
+        Value::Bool(b) => flat.add_node(ValueNode::Bool(b)),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                flat.add_node(ValueNode::Int(i))
+            } else if let Some(u) = n.as_u64() {
+                flat.add_node(ValueNode::UInt(u))
+            } else if let Some(f) = n.as_f64() {
+                flat.add_node(ValueNode::Float(f))
+            } else {
+                flat.add_node(ValueNode::Null)
+            }
+        }
+        Value::String(s) => flat.add_node(ValueNode::String(s)),
+        Value::Array(arr) => {
+            check_elements(arr.len(), opts)?;
             let indices: Vec<ValueIdx> = arr
                 .into_iter()
-                .map(|v| add_simd_json_value(flat, v))
-                .collect();
+                .enumerate()
+                .map(|(i, v)| add_json5_value(flat, v, opts).map_err(|e| within(e, &i.to_string())))
+                .collect::<Result<_>>()?;
             flat.add_node(ValueNode::Array(indices))
-        } else {
-            flat.add_node(ValueNode::Null)
         }
-    } else if value.is_object() {
-        if let Some(obj) = value.into_object() {
+        Value::Object(obj) => {
+            check_keys(obj.len(), opts)?;
             let mut pairs: Vec<(String, ValueIdx)> = obj
                 .into_iter()
-                .map(|(k, v)| (k.to_string(), add_simd_json_value(flat, v)))
-                .collect();
-            sort_pairs(&mut pairs);
+                .map(|(k, v)| {
+                    let idx = add_json5_value(flat, v, opts).map_err(|e| within(e, &k))?;
+                    Ok((k, idx))
+                })
+                .collect::<Result<_>>()?;
+            if !opts.preserve_order {
+                sort_pairs(&mut pairs);
+            }
             flat.add_node(ValueNode::Object(pairs))
-        } else {
-            flat.add_node(ValueNode::Null)
         }
-    } else {
-        flat.add_node(ValueNode::Null)
-    }
+    })
 }
 
 pub fn from_yaml(value: serde_yaml::Value) -> FlatValue {
+    from_yaml_opts(value, &ParseOptions::default()).expect("no limits are set by default")
+}
+
+fn from_yaml_opts(value: serde_yaml::Value, opts: &ParseOptions) -> Result<FlatValue> {
     let mut flat = FlatValue::new();
-    let root_idx = add_yaml_value(&mut flat, value);
+    let root_idx = add_yaml_value(&mut flat, value, opts)?;
     flat.set_root(root_idx);
-    flat
+    Ok(flat)
 }
 
 pub fn parse_yaml(content: &str) -> Result<FlatValue> {
-    let parsed: serde_yaml::Value = serde_yaml::from_str(content)?;
-    Ok(from_yaml(parsed))
+    parse_yaml_opts(content, &ParseOptions::default())
+}
+
+pub fn parse_yaml_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    let parsed: serde_yaml::Value = serde_yaml::from_str(&tag_yaml_big_ints(content))?;
+    from_yaml_opts(parsed, opts)
+}
+
+/// Parses a `---` separated YAML stream. A single document yields its root
+/// as `parse_yaml` would; several yield an array of document roots.
+pub fn parse_yaml_multi(content: &str) -> Result<FlatValue> {
+    parse_yaml_multi_opts(content, &ParseOptions::default())
+}
+
+pub fn parse_yaml_multi_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    use serde::Deserialize;
+
+    let content = tag_yaml_big_ints(content);
+    let mut docs = serde_yaml::Deserializer::from_str(&content)
+        .map(serde_yaml::Value::deserialize)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if docs.len() <= 1 {
+        return from_yaml_opts(docs.pop().unwrap_or(serde_yaml::Value::Null), opts);
+    }
+
+    check_elements(docs.len(), opts)?;
+    let mut flat = FlatValue::new();
+    let items = docs
+        .into_iter()
+        .enumerate()
+        .map(|(i, doc)| add_yaml_value(&mut flat, doc, opts).map_err(|e| within(e, &i.to_string())))
+        .collect::<Result<_>>()?;
+    let root_idx = flat.add_node(ValueNode::Array(items));
+    flat.set_root(root_idx);
+    Ok(flat)
+}
+
+/// Tag `tag_yaml_big_ints` puts on integers beyond 64 bits. serde_yaml
+/// rejects those up to 128 bits and rounds longer ones to floats.
+const YAML_BIG_INT_TAG: &str = "!snapconfig/bigint";
+
+/// Rewrites each plain integer scalar too big for 64 bits as a quoted string
+/// tagged `YAML_BIG_INT_TAG`, so `add_yaml_value` sees its exact digits.
+/// Documents without a run of 19 digits are returned as they are.
+fn tag_yaml_big_ints(content: &str) -> Cow<'_, str> {
+    let has_long_run = content
+        .as_bytes()
+        .split(|b| !b.is_ascii_digit())
+        .any(|run| run.len() >= 19);
+    if !has_long_run {
+        return Cow::Borrowed(content);
+    }
+    let spans = yaml_big_int_spans(content);
+    if spans.is_empty() {
+        return Cow::Borrowed(content);
+    }
+
+    let mut out = String::with_capacity(content.len() + spans.len() * 40);
+    let mut copied = 0;
+    for span in spans {
+        out.push_str(&content[copied..span.start]);
+        out.push_str(&format!(
+            "!<{}> '{}'",
+            YAML_BIG_INT_TAG,
+            &content[span.clone()]
+        ));
+        copied = span.end;
+    }
+    out.push_str(&content[copied..]);
+    Cow::Owned(out)
+}
+
+/// Byte ranges of the untagged plain scalars in `content` that are integers
+/// outside the 64-bit range. Positions come from libyaml's events, so digits
+/// in quoted or block scalars and comments are never matched. A syntax error
+/// just ends the scan; serde_yaml reports it when it parses the document.
+fn yaml_big_int_spans(content: &str) -> Vec<Range<usize>> {
+    use std::mem::MaybeUninit;
+    use unsafe_libyaml::*;
+
+    let is_big_int = |text: &str| {
+        let digits = text.strip_prefix(['-', '+']).unwrap_or(text);
+        !digits.is_empty()
+            && digits.bytes().all(|b| b.is_ascii_digit())
+            && !(digits.len() > 1 && digits.starts_with('0'))
+            && text.parse::<i64>().is_err()
+            && text.parse::<u64>().is_err()
+    };
+
+    let mut spans = Vec::new();
+    // SAFETY: the parser reads `content`, which outlives it, and every event
+    // is deleted before the next one is parsed into the same slot.
+    unsafe {
+        let mut parser = MaybeUninit::<yaml_parser_t>::uninit();
+        if yaml_parser_initialize(parser.as_mut_ptr()).fail {
+            return spans;
+        }
+        let parser = parser.as_mut_ptr();
+        yaml_parser_set_input_string(parser, content.as_ptr(), content.len() as u64);
+        let mut event = MaybeUninit::<yaml_event_t>::uninit();
+        while yaml_parser_parse(parser, event.as_mut_ptr()).ok {
+            let event = event.as_mut_ptr();
+            let done = (*event).type_ == YAML_STREAM_END_EVENT;
+            if (*event).type_ == YAML_SCALAR_EVENT {
+                let scalar = &(*event).data.scalar;
+                let value = std::slice::from_raw_parts(scalar.value, scalar.length as usize);
+                // A plain scalar's value is its source text, ending at end_mark.
+                let end = (*event).end_mark.index as usize;
+                if scalar.style == YAML_PLAIN_SCALAR_STYLE
+                    && scalar.tag.is_null()
+                    && end >= value.len()
+                    && content.as_bytes().get(end - value.len()..end) == Some(value)
+                    && std::str::from_utf8(value).is_ok_and(is_big_int)
+                {
+                    spans.push(end - value.len()..end);
+                }
+            }
+            yaml_event_delete(event);
+            if done {
+                break;
+            }
+        }
+        yaml_parser_delete(parser);
+    }
+    spans
 }
 
-fn add_yaml_value(flat: &mut FlatValue, value: serde_yaml::Value) -> ValueIdx {
+fn add_yaml_value(
+    flat: &mut FlatValue,
+    value: serde_yaml::Value,
+    opts: &ParseOptions,
+) -> Result<ValueIdx> {
     use serde_yaml::Value;
 
-    match value {
+    Ok(match value {
         Value::Null => flat.add_node(ValueNode::Null),
         Value::Bool(b) => flat.add_node(ValueNode::Bool(b)),
         Value::Number(n) => {
             if let Some(i) = n.as_i64() {
                 flat.add_node(ValueNode::Int(i))
+            } else if let Some(u) = n.as_u64() {
+                flat.add_node(ValueNode::UInt(u))
             } else if let Some(f) = n.as_f64() {
                 flat.add_node(ValueNode::Float(f))
             } else {
@@ -110,65 +925,187 @@ fn add_yaml_value(flat: &mut FlatValue, value: serde_yaml::Value) -> ValueIdx {
         }
         Value::String(s) => flat.add_node(ValueNode::String(s)),
         Value::Sequence(arr) => {
-            let indices: Vec<ValueIdx> = arr.into_iter().map(|v| add_yaml_value(flat, v)).collect();
+            check_elements(arr.len(), opts)?;
+            let indices: Vec<ValueIdx> = arr
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| add_yaml_value(flat, v, opts).map_err(|e| within(e, &i.to_string())))
+                .collect::<Result<_>>()?;
             flat.add_node(ValueNode::Array(indices))
         }
         Value::Mapping(obj) => {
-            let mut pairs: Vec<(String, ValueIdx)> = obj
+            let mut pairs: Vec<(String, ValueIdx)> = apply_yaml_merge(obj)
                 .into_iter()
                 .filter_map(|(k, v)| {
                     let key = match k {
                         Value::String(s) => s,
                         _ => k.as_str()?.to_string(),
                     };
-                    Some((key, add_yaml_value(flat, v)))
+                    let idx = add_yaml_value(flat, v, opts).map_err(|e| within(e, &key));
+                    Some(idx.map(|idx| (key, idx)))
                 })
-                .collect();
-            sort_pairs(&mut pairs);
+                .collect::<Result<_>>()?;
+            check_keys(pairs.len(), opts)?;
+            if !opts.preserve_order {
+                sort_pairs(&mut pairs);
+            }
             flat.add_node(ValueNode::Object(pairs))
         }
-        Value::Tagged(tagged) => add_yaml_value(flat, tagged.value),
+        Value::Tagged(tagged) if tagged.tag == YAML_BIG_INT_TAG => match tagged.value {
+            Value::String(digits) => flat.add_node(ValueNode::BigInt(
+                digits.strip_prefix('+').unwrap_or(&digits).to_string(),
+            )),
+            other => add_yaml_value(flat, other, opts)?,
+        },
+        Value::Tagged(tagged) => add_yaml_value(flat, tagged.value, opts)?,
+    })
+}
+
+/// Expands a `<<` merge key: its mapping, or each mapping of a sequence in
+/// turn, fills in keys the parent doesn't set itself. As in YAML, explicit
+/// keys win and earlier sources win over later ones. Sources are expanded
+/// first, so chained merges work. A `<<` holding anything else stays a
+/// plain key.
+fn apply_yaml_merge(mut mapping: serde_yaml::Mapping) -> serde_yaml::Mapping {
+    use serde_yaml::Value;
+
+    let sources = match mapping.remove("<<") {
+        None => return mapping,
+        Some(Value::Mapping(source)) => vec![source],
+        Some(Value::Sequence(items)) if items.iter().all(Value::is_mapping) => items
+            .into_iter()
+            .filter_map(|item| match item {
+                Value::Mapping(source) => Some(source),
+                _ => None,
+            })
+            .collect(),
+        Some(other) => {
+            mapping.insert(Value::String("<<".to_string()), other);
+            return mapping;
+        }
+    };
+
+    for source in sources {
+        for (key, value) in apply_yaml_merge(source) {
+            mapping.entry(key).or_insert(value);
+        }
     }
+    mapping
 }
 
 pub fn from_toml(value: toml::Value) -> FlatValue {
+    from_toml_opts(value, &ParseOptions::default()).expect("no limits are set by default")
+}
+
+fn from_toml_opts(value: toml::Value, opts: &ParseOptions) -> Result<FlatValue> {
     let mut flat = FlatValue::new();
-    let root_idx = add_toml_value(&mut flat, value);
+    let root_idx = add_toml_value(&mut flat, value, opts)?;
     flat.set_root(root_idx);
-    flat
+    Ok(flat)
 }
 
 pub fn parse_toml(content: &str) -> Result<FlatValue> {
-    let parsed: toml::Value = toml::from_str(content)?;
-    Ok(from_toml(parsed))
+    parse_toml_opts(content, &ParseOptions::default())
 }
 
-fn add_toml_value(flat: &mut FlatValue, value: toml::Value) -> ValueIdx {
+pub fn parse_toml_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    let mut parsed: toml::Value = toml::from_str(content)?;
+    if let Some(key) = &opts.toml_merge_key {
+        merge_keyed_tables(&mut parsed, key);
+    }
+    from_toml_opts(parsed, opts)
+}
+
+/// Folds array entries that are tables with the same `key` value into the
+/// first of them, later entries' fields winning. Entries without `key` are
+/// kept as they are. Arrays at any depth are folded.
+fn merge_keyed_tables(value: &mut toml::Value, key: &str) {
     use toml::Value;
 
     match value {
+        Value::Table(table) => {
+            for (_, child) in table.iter_mut() {
+                merge_keyed_tables(child, key);
+            }
+        }
+        Value::Array(items) => {
+            let mut merged: Vec<Value> = Vec::with_capacity(items.len());
+            for mut item in items.drain(..) {
+                merge_keyed_tables(&mut item, key);
+                let target = item.get(key).and_then(|id| {
+                    merged
+                        .iter_mut()
+                        .find(|entry| entry.is_table() && entry.get(key) == Some(id))
+                });
+                match (target, item) {
+                    (Some(Value::Table(target)), Value::Table(fields)) => target.extend(fields),
+                    (_, item) => merged.push(item),
+                }
+            }
+            *items = merged;
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn add_toml_value(
+    flat: &mut FlatValue,
+    value: toml::Value,
+    opts: &ParseOptions,
+) -> Result<ValueIdx> {
+    use toml::Value;
+
+    Ok(match value {
         Value::String(s) => flat.add_node(ValueNode::String(s)),
         Value::Integer(i) => flat.add_node(ValueNode::Int(i)),
         Value::Float(f) => flat.add_node(ValueNode::Float(f)),
         Value::Boolean(b) => flat.add_node(ValueNode::Bool(b)),
-        Value::Datetime(dt) => flat.add_node(ValueNode::String(dt.to_string())),
+        Value::Datetime(dt) => {
+            let kind = match (dt.date.is_some(), dt.time.is_some(), dt.offset.is_some()) {
+                (true, true, true) => DatetimeKind::OffsetDateTime,
+                (true, true, false) => DatetimeKind::LocalDateTime,
+                (true, false, _) => DatetimeKind::LocalDate,
+                (false, _, _) => DatetimeKind::LocalTime,
+            };
+            flat.add_node(ValueNode::Datetime(kind, dt.to_string()))
+        }
         Value::Array(arr) => {
-            let indices: Vec<ValueIdx> = arr.into_iter().map(|v| add_toml_value(flat, v)).collect();
+            check_elements(arr.len(), opts)?;
+            let indices: Vec<ValueIdx> = arr
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| add_toml_value(flat, v, opts).map_err(|e| within(e, &i.to_string())))
+                .collect::<Result<_>>()?;
             flat.add_node(ValueNode::Array(indices))
         }
         Value::Table(table) => {
+            check_keys(table.len(), opts)?;
             let mut pairs: Vec<(String, ValueIdx)> = table
                 .into_iter()
-                .map(|(k, v)| (k, add_toml_value(flat, v)))
-                .collect();
-            sort_pairs(&mut pairs);
+                .map(|(k, v)| {
+                    let idx = add_toml_value(flat, v, opts).map_err(|e| within(e, &k))?;
+                    Ok((k, idx))
+                })
+                .collect::<Result<_>>()?;
+            if !opts.preserve_order {
+                sort_pairs(&mut pairs);
+            }
             flat.add_node(ValueNode::Object(pairs))
         }
-    }
+    })
 }
 
 pub fn parse_ini(content: &str) -> Result<FlatValue> {
-    let ini = Ini::load_from_str(content).map_err(|e| SnapconfigError::IniParse(e.to_string()))?;
+    parse_ini_opts(content, &ParseOptions::default())
+}
+
+pub fn parse_ini_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    let ini_opts = ParseOption {
+        enabled_preserve_key_leading_whitespace: !opts.trim_keys || opts.strict_keys,
+        ..ParseOption::default()
+    };
+    let ini = Ini::load_from_str_opt(content, ini_opts)
+        .map_err(|e| SnapconfigError::IniParse(e.to_string()))?;
 
     let mut flat = FlatValue::new();
     let mut sections: Vec<(String, ValueIdx)> = Vec::new();
@@ -177,43 +1114,92 @@ pub fn parse_ini(content: &str) -> Result<FlatValue> {
         let section_name = section.unwrap_or("default").to_string();
         let mut pairs: Vec<(String, ValueIdx)> = Vec::new();
 
+        // rust-ini yields every occurrence of a repeated key.
         for (key, value) in props.iter() {
-            let value_idx = parse_scalar_value(&mut flat, value);
-            pairs.push((key.to_string(), value_idx));
+            let key = normalize_key(key, opts)?;
+            let value_idx = parse_scalar_value(&mut flat, value, opts)?;
+            pairs.push((key, value_idx));
         }
 
-        sort_pairs(&mut pairs);
+        check_duplicate_keys(&pairs, opts)?;
+        let mut pairs = group_repeated_keys(&mut flat, pairs, opts)
+            .and_then(|pairs| check_keys(pairs.len(), opts).map(|()| pairs))
+            .map_err(|e| within(e, &section_name))?;
+        if !opts.preserve_order {
+            sort_pairs(&mut pairs);
+        }
         let section_idx = flat.add_node(ValueNode::Object(pairs));
         sections.push((section_name, section_idx));
     }
 
-    sort_pairs(&mut sections);
+    check_keys(sections.len(), opts)?;
+    if !opts.preserve_order {
+        sort_pairs(&mut sections);
+    }
     let root_idx = flat.add_node(ValueNode::Object(sections));
     flat.set_root(root_idx);
     Ok(flat)
 }
 
 pub fn parse_env(content: &str) -> FlatValue {
+    parse_env_opts(content, &ParseOptions::default()).expect("lenient env parsing cannot fail")
+}
+
+pub fn parse_env_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
     let mut flat = FlatValue::new();
     let mut pairs: Vec<(String, ValueIdx)> = Vec::new();
+    // Keys seen under each `[name]` header, in header order.
+    let mut sections: Vec<(String, Vec<(String, ValueIdx)>)> = Vec::new();
+    let mut current: Option<usize> = None;
+    // Expanded values so far, for `interpolate`.
+    let mut vars: HashMap<String, String> = HashMap::new();
 
-    for line in content.lines() {
-        let mut line = line.trim();
+    let mut lines = content.lines();
+    while let Some(raw_line) = lines.next() {
+        let raw_line = raw_line.trim_end();
+        let mut line = raw_line.trim_start();
 
         // Skip empty lines and comments
         if line.is_empty() || line.starts_with('#') {
             continue;
         }
 
-        // Handle 'export ' prefix (shell-compatible .env files)
+        if opts.sections && line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim();
+            current = Some(match sections.iter().position(|(s, _)| s == name) {
+                Some(pos) => pos,
+                None => {
+                    sections.push((name.to_string(), Vec::new()));
+                    sections.len() - 1
+                }
+            });
+            continue;
+        }
+
+        // Handle 'export ' prefix (shell-compatible .env files); otherwise the
+        // line's own indentation belongs to the key.
         if let Some(stripped) = line.strip_prefix("export ") {
             line = stripped;
+        } else {
+            line = raw_line;
         }
 
         // Parse KEY=VALUE
-        if let Some(eq_pos) = line.find('=') {
-            let key = line[..eq_pos].trim().to_string();
-            let mut value = line[eq_pos + 1..].trim().to_string();
+        if let Some((key, value, quoted)) = split_env_assignment(line) {
+            let key = if quoted {
+                key
+            } else {
+                normalize_key(&key, opts)?
+            };
+            let mut value = value.trim().to_string();
+            if let Some(joined) = take_multiline_value(&value, &mut lines) {
+                value = joined;
+            }
+            // `KEY=` is empty; `KEY=""` is an explicit empty string.
+            let unset = value.is_empty();
+            let single_quoted =
+                value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'');
+            let double_quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
 
             // Remove surrounding quotes if present
             if ((value.starts_with('"') && value.ends_with('"'))
@@ -222,25 +1208,714 @@ pub fn parse_env(content: &str) -> FlatValue {
             {
                 value = value[1..value.len() - 1].to_string();
             }
+            if double_quoted {
+                value = unescape_double_quoted(&value);
+            }
 
-            let value_idx = parse_scalar_value(&mut flat, &value);
-            pairs.push((key, value_idx));
+            if opts.interpolate {
+                if !single_quoted {
+                    value = interpolate_env(&value, &vars);
+                }
+                vars.insert(key.clone(), value.clone());
+            }
+
+            let value_idx = if unset && opts.empty_as_null {
+                flat.add_node(ValueNode::Null)
+            } else {
+                parse_scalar_value(&mut flat, &value, opts)?
+            };
+            match current {
+                Some(pos) => sections[pos].1.push((key, value_idx)),
+                None => pairs.push((key, value_idx)),
+            }
+        }
+    }
+
+    for (name, section_pairs) in sections {
+        check_duplicate_keys(&section_pairs, opts)?;
+        let section_pairs = order_env_pairs(section_pairs, opts);
+        check_keys(section_pairs.len(), opts).map_err(|e| within(e, &name))?;
+        let section_idx = flat.add_node(ValueNode::Object(section_pairs));
+        pairs.push((name, section_idx));
+    }
+
+    check_duplicate_keys(&pairs, opts)?;
+    let pairs = order_env_pairs(pairs, opts);
+    check_keys(pairs.len(), opts)?;
+    let root_idx = flat.add_node(ValueNode::Object(pairs));
+    flat.set_root(root_idx);
+    Ok(flat)
+}
+
+/// Splits `KEY=VALUE` at the first `=` outside quotes and not escaped as
+/// `\=`. A quoted key (`"A=B"=1`) is taken literally, with escapes resolved
+/// inside double quotes; the flag says so, since such keys skip
+/// `normalize_key`. Returns `None` for lines without an assignment.
+fn split_env_assignment(line: &str) -> Option<(String, &str, bool)> {
+    let body = line.trim_start();
+    if let Some(quote) = body.chars().next().filter(|&c| c == '"' || c == '\'') {
+        if let Some(end) = find_closing_quote(&body[1..], quote) {
+            let inner = &body[1..1 + end];
+            if let Some(value) = body[end + 2..].trim_start().strip_prefix('=') {
+                let key = if quote == '"' {
+                    unescape_double_quoted(inner)
+                } else {
+                    inner.to_string()
+                };
+                return Some((key, value, true));
+            }
         }
     }
 
+    let mut key = String::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|&(_, next)| next == '=') => {
+                key.push('=');
+                chars.next();
+            }
+            '=' => return Some((key, &line[i + 1..], false)),
+            c => key.push(c),
+        }
+    }
+    None
+}
+
+/// Sorts env keys, or with `preserve_order` keeps file order. Ordered
+/// objects are searched linearly, which would find the first of repeated
+/// keys, so they are collapsed there (last value wins).
+fn order_env_pairs(
+    mut pairs: Vec<(String, ValueIdx)>,
+    opts: &ParseOptions,
+) -> Vec<(String, ValueIdx)> {
+    if opts.preserve_order {
+        return dedup_pairs(pairs);
+    }
     sort_pairs(&mut pairs);
+    pairs
+}
+
+/// Byte offset of the first unescaped `quote` in `text`. Backslash escapes
+/// only count inside double quotes.
+fn find_closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quote == '"' {
+            escaped = true;
+        } else if c == quote {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// When `first` opens a quote it doesn't close, joins the following lines
+/// (newlines kept) through the one holding the closing quote and advances
+/// `lines` past it. Anything after that quote is dropped. An unterminated
+/// quote consumes nothing, leaving `first` to be read as-is.
+fn take_multiline_value(first: &str, lines: &mut std::str::Lines<'_>) -> Option<String> {
+    let quote = first.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    if find_closing_quote(&first[1..], quote).is_some() {
+        return None;
+    }
+
+    let mut ahead = lines.clone();
+    let mut joined = first.to_string();
+    for next in ahead.by_ref() {
+        joined.push('\n');
+        if let Some(end) = find_closing_quote(next, quote) {
+            joined.push_str(&next[..end + quote.len_utf8()]);
+            *lines = ahead;
+            return Some(joined);
+        }
+        joined.push_str(next);
+    }
+    None
+}
+
+/// Resolves `\n`, `\r`, `\t`, `\"` and `\\` inside a double-quoted env value.
+/// Other escapes (notably `\$`, which interpolation handles) are kept.
+fn unescape_double_quoted(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Expands `${NAME}` and `$NAME` from `vars`, docker-compose style: unknown
+/// names become "" and `\$` is a literal dollar sign.
+fn interpolate_env(value: &str, vars: &HashMap<String, String>) -> String {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(pos) = rest.find(['$', '\\']) {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+        if let Some(after) = tail.strip_prefix("\\$") {
+            out.push('$');
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix('\\') {
+            out.push('\\');
+            rest = after;
+        } else if let Some((name, after)) = tail
+            .strip_prefix("${")
+            .and_then(|braced| braced.split_once('}'))
+        {
+            out.push_str(vars.get(name).map_or("", String::as_str));
+            rest = after;
+        } else {
+            let after = &tail[1..];
+            let len = after
+                .find(|c: char| !is_name_char(c))
+                .unwrap_or(after.len());
+            if len == 0 || after.starts_with(|c: char| c.is_ascii_digit()) {
+                out.push('$');
+                rest = after;
+            } else {
+                out.push_str(vars.get(&after[..len]).map_or("", String::as_str));
+                rest = &after[len..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parse an XML document. The root element becomes the single top-level
+/// key. Elements become objects, repeated siblings arrays, attributes keys
+/// prefixed with `@`, and text a `#text` key; an element with only text
+/// becomes that scalar (typed like INI values), and an empty one null.
+pub fn parse_xml(content: &str) -> Result<FlatValue> {
+    parse_xml_opts(content, &ParseOptions::default())
+}
+
+pub fn parse_xml_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    use quick_xml::events::Event;
+
+    struct Element {
+        name: String,
+        pairs: Vec<(String, ValueIdx)>,
+        text: String,
+    }
+
+    let mut reader = quick_xml::Reader::from_str(content);
+    reader.config_mut().expand_empty_elements = true;
+    let xml_error = |reader: &quick_xml::Reader<&[u8]>, message: String| {
+        let (line, column) = line_column(content, reader.error_position() as usize);
+        SnapconfigError::XmlParse(format!("{} at line {} column {}", message, line, column))
+    };
+
+    let mut flat = FlatValue::new();
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root: Option<(String, ValueIdx)> = None;
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| xml_error(&reader, e.to_string()))?;
+        match event {
+            Event::Start(start) => {
+                let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+                let mut pairs = Vec::new();
+                for attr in start.attributes() {
+                    let attr = attr.map_err(|e| xml_error(&reader, e.to_string()))?;
+                    let key = format!("@{}", String::from_utf8_lossy(attr.key.as_ref()));
+                    let value = attr
+                        .unescape_value()
+                        .map_err(|e| xml_error(&reader, e.to_string()))?;
+                    pairs.push((key, parse_scalar_value(&mut flat, &value, opts)?));
+                }
+                stack.push(Element {
+                    name,
+                    pairs,
+                    text: String::new(),
+                });
+            }
+            Event::Text(text) => {
+                if let Some(element) = stack.last_mut() {
+                    let text = text
+                        .unescape()
+                        .map_err(|e| xml_error(&reader, e.to_string()))?;
+                    element.text.push_str(&text);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&String::from_utf8_lossy(&data));
+                }
+            }
+            Event::End(_) => {
+                let element = stack.pop().expect("end tags are matched by the reader");
+                let text = element.text.trim();
+                let idx = if element.pairs.is_empty() {
+                    if text.is_empty() {
+                        flat.add_node(ValueNode::Null)
+                    } else {
+                        parse_scalar_value(&mut flat, text, opts)?
+                    }
+                } else {
+                    let text_keys = usize::from(!text.is_empty());
+                    let grouped =
+                        group_repeated_keys(&mut flat, element.pairs, opts).and_then(|pairs| {
+                            check_keys(pairs.len() + text_keys, opts).map(|()| pairs)
+                        });
+                    let mut pairs = grouped.map_err(|e| {
+                        // The element's path: its ancestors' names, then its own.
+                        stack
+                            .iter()
+                            .map(|parent| parent.name.as_str())
+                            .chain([element.name.as_str()])
+                            .rev()
+                            .fold(e, within)
+                    })?;
+                    if !text.is_empty() {
+                        let text_idx = parse_scalar_value(&mut flat, text, opts)?;
+                        pairs.push(("#text".to_string(), text_idx));
+                    }
+                    if !opts.preserve_order {
+                        sort_pairs(&mut pairs);
+                    }
+                    flat.add_node(ValueNode::Object(pairs))
+                };
+                match stack.last_mut() {
+                    Some(parent) => parent.pairs.push((element.name, idx)),
+                    None if root.is_some() => {
+                        return Err(xml_error(&reader, "more than one root element".to_string()))
+                    }
+                    None => root = Some((element.name, idx)),
+                }
+            }
+            Event::Eof => break,
+            Event::Comment(_) | Event::Decl(_) | Event::PI(_) | Event::DocType(_) => {}
+            Event::Empty(_) => unreachable!("empty elements are expanded"),
+        }
+    }
+
+    let Some((name, idx)) = root else {
+        return Err(SnapconfigError::XmlParse(
+            "document has no root element".to_string(),
+        ));
+    };
+    check_keys(1, opts)?;
+    let root_idx = flat.add_node(ValueNode::Object(vec![(name, idx)]));
+    flat.set_root(root_idx);
+    Ok(flat)
+}
+
+/// Parse RON. Maps and structs become objects, tuples and sequences become
+/// arrays, and `()`, unit structs and `None` become null; `Some(x)` is just
+/// `x`. A named value below the root is read as an enum variant and tagged
+/// with its name (`Tcp(port: 80)` as `{"Tcp": {"port": 80}}`); the root's
+/// name is its struct name and is dropped. Unit variants are bare names,
+/// which RON cannot tell apart from unit structs, so they read as null.
+pub fn parse_ron(content: &str) -> Result<FlatValue> {
+    parse_ron_opts(content, &ParseOptions::default())
+}
+
+pub fn parse_ron_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    let parsed: ron::Value = ron::from_str(&tag_ron_variants(content)).map_err(|e| {
+        // Report the position in the document as written, not in the rewrite.
+        let e = ron::from_str::<ron::Value>(content).err().unwrap_or(e);
+        SnapconfigError::RonParse(e.to_string())
+    })?;
+    let mut flat = FlatValue::new();
+    let root_idx = add_ron_value(&mut flat, parsed, opts)?;
+    flat.set_root(root_idx);
+    Ok(flat)
+}
+
+/// Rewrites each named value below the root, `Tcp(port: 80)`, as a one-key
+/// map, `{"Tcp": (port: 80)}`, because the `ron` crate drops names when it
+/// reads untyped values. `Some(..)` is left alone. Documents without named
+/// values, or with an unclosed one, are returned as they are.
+fn tag_ron_variants(content: &str) -> Cow<'_, str> {
+    let bytes = content.as_bytes();
+    let is_ident_start = |b: u8| b.is_ascii_alphabetic() || b == b'_';
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    // Name ranges of the variants found, with the index of their `)`.
+    let mut variants: Vec<(Range<usize>, Option<usize>)> = Vec::new();
+    // One entry per open bracket: the variant a `(` belongs to, if any.
+    let mut open: Vec<Option<usize>> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // Block comments nest in RON.
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i..].starts_with(b"/*") {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            b'#' if bytes.get(i + 1) == Some(&b'!') => {
+                // `#![enable(..)]` attributes.
+                while i < bytes.len() && bytes[i] != b']' {
+                    i += 1;
+                }
+            }
+            b'(' | b'[' | b'{' => {
+                open.push(None);
+                i += 1;
+            }
+            b')' | b']' | b'}' => {
+                if let Some(Some(variant)) = open.pop() {
+                    variants[variant].1 = Some(i);
+                }
+                i += 1;
+            }
+            b'r' if matches!(bytes.get(i + 1), Some(b'"' | b'#')) && {
+                let hashes = bytes[i + 1..].iter().take_while(|&&b| b == b'#').count();
+                bytes.get(i + 1 + hashes) == Some(&b'"')
+            } =>
+            {
+                // Raw string: r"..", r#".."#, and so on.
+                let hashes = bytes[i + 1..].iter().take_while(|&&b| b == b'#').count();
+                let close = [&b"\""[..], &bytes[i + 1..i + 1 + hashes]].concat();
+                i += hashes + 2;
+                while i < bytes.len() && !bytes[i..].starts_with(&close) {
+                    i += 1;
+                }
+                i += close.len();
+            }
+            b if is_ident_start(b) => {
+                // `r#name` is a raw identifier for `name`.
+                let raw = b == b'r'
+                    && bytes.get(i + 1) == Some(&b'#')
+                    && bytes.get(i + 2).is_some_and(|&b| is_ident_start(b));
+                let start = if raw { i + 2 } else { i };
+                let mut end = start;
+                while end < bytes.len() && is_ident(bytes[end]) {
+                    end += 1;
+                }
+                let mut next = end;
+                while next < bytes.len() && bytes[next].is_ascii_whitespace() {
+                    next += 1;
+                }
+                // At depth 0 the name belongs to the root struct.
+                if bytes.get(next) == Some(&b'(')
+                    && !open.is_empty()
+                    && &content[start..end] != "Some"
+                {
+                    variants.push((i..end, None));
+                    open.push(Some(variants.len() - 1));
+                    i = next + 1;
+                } else {
+                    i = end;
+                }
+            }
+            b if b.is_ascii_digit() => {
+                // Numbers such as 0x1F or 1e5 must not read as names.
+                while i < bytes.len() && (is_ident(bytes[i]) || bytes[i] == b'.') {
+                    i += 1;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    if variants.is_empty() || variants.iter().any(|(_, close)| close.is_none()) {
+        return Cow::Borrowed(content);
+    }
+
+    let mut edits: Vec<(Range<usize>, String)> = Vec::with_capacity(variants.len() * 2);
+    for (name, close) in variants {
+        let close = close.unwrap_or_default() + 1;
+        let ident = content[name.clone()].trim_start_matches("r#");
+        edits.push((name, format!("{{{:?}:", ident)));
+        edits.push((close..close, "}".to_string()));
+    }
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut out = String::with_capacity(content.len() + edits.len() * 8);
+    let mut copied = 0;
+    for (range, text) in edits {
+        out.push_str(&content[copied..range.start]);
+        out.push_str(&text);
+        copied = range.end;
+    }
+    out.push_str(&content[copied..]);
+    Cow::Owned(out)
+}
+
+fn add_ron_value(flat: &mut FlatValue, value: ron::Value, opts: &ParseOptions) -> Result<ValueIdx> {
+    use ron::value::Number;
+    use ron::Value;
+
+    let node = match value {
+        Value::Bool(b) => ValueNode::Bool(b),
+        Value::Char(c) => ValueNode::String(c.to_string()),
+        Value::String(s) => ValueNode::String(s),
+        Value::Number(Number::Integer(i)) => ValueNode::Int(i),
+        Value::Number(Number::Float(f)) => ValueNode::Float(f.get()),
+        Value::Unit | Value::Option(None) => ValueNode::Null,
+        Value::Option(Some(inner)) => return add_ron_value(flat, *inner, opts),
+        Value::Seq(items) => {
+            check_elements(items.len(), opts)?;
+            ValueNode::Array(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        add_ron_value(flat, item, opts).map_err(|e| within(e, &i.to_string()))
+                    })
+                    .collect::<Result<_>>()?,
+            )
+        }
+        Value::Map(map) => {
+            check_keys(map.len(), opts)?;
+            let mut pairs = map
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Value::String(s) => s,
+                        Value::Char(c) => c.to_string(),
+                        Value::Bool(b) => b.to_string(),
+                        Value::Number(Number::Integer(i)) => i.to_string(),
+                        Value::Number(Number::Float(f)) => f.get().to_string(),
+                        other => {
+                            return Err(SnapconfigError::RonParse(format!(
+                                "map keys must be scalars, not {:?}",
+                                other
+                            )))
+                        }
+                    };
+                    let idx = add_ron_value(flat, value, opts).map_err(|e| within(e, &key))?;
+                    Ok((key, idx))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            if !opts.preserve_order {
+                sort_pairs(&mut pairs);
+            }
+            ValueNode::Object(pairs)
+        }
+    };
+    Ok(flat.add_node(node))
+}
+
+/// Parse a Java `.properties` file into a flat object keyed by the full
+/// (possibly dotted) property name.
+pub fn parse_properties(content: &str) -> Result<FlatValue> {
+    parse_properties_opts(content, &ParseOptions::default())
+}
+
+pub fn parse_properties_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    let mut flat = FlatValue::new();
+    let mut pairs: Vec<(String, ValueIdx)> = Vec::new();
+    let mut lines = content.lines();
+
+    while let Some(line) = lines.next() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+
+        // Join backslash continuations; leading whitespace on the next line is dropped.
+        let mut logical = line.to_string();
+        while ends_with_continuation(&logical) {
+            logical.pop();
+            match lines.next() {
+                Some(next) => logical.push_str(next.trim_start()),
+                None => break,
+            }
+        }
+
+        // The key ends at the first unescaped '=', ':' or whitespace.
+        let mut key_end = logical.len();
+        let mut escaped = false;
+        for (i, c) in logical.char_indices() {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '=' || c == ':' || c.is_whitespace() {
+                key_end = i;
+                break;
+            }
+        }
+        let mut rest = logical[key_end..].trim_start();
+        if let Some(stripped) = rest.strip_prefix(['=', ':']) {
+            rest = stripped.trim_start();
+        }
+
+        let key = unescape_properties(&logical[..key_end])?;
+        let value = unescape_properties(rest)?;
+        let value_idx = parse_scalar_value(&mut flat, &value, opts)?;
+        pairs.push((key, value_idx));
+    }
+
+    check_duplicate_keys(&pairs, opts)?;
+    let mut pairs = dedup_pairs(pairs);
+    check_keys(pairs.len(), opts)?;
+    if !opts.preserve_order {
+        sort_pairs(&mut pairs);
+    }
     let root_idx = flat.add_node(ValueNode::Object(pairs));
     flat.set_root(root_idx);
-    flat
+    Ok(flat)
+}
+
+fn ends_with_continuation(line: &str) -> bool {
+    line.bytes().rev().take_while(|&b| b == b'\\').count() % 2 == 1
+}
+
+/// Resolves `\t`, `\n`, `\r`, `\f`, `\uXXXX` (including surrogate pairs) and
+/// `\x` -> `x` escapes.
+fn unescape_properties(raw: &str) -> Result<String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut units: Vec<u16> = Vec::new();
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            flush_utf16(&mut units, &mut out)?;
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let unit = u16::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 4);
+                let unit = unit.ok_or_else(|| {
+                    SnapconfigError::PropertiesParse(format!("Malformed \\u escape in {:?}", raw))
+                })?;
+                units.push(unit);
+                continue;
+            }
+            Some(other) => {
+                flush_utf16(&mut units, &mut out)?;
+                out.push(match other {
+                    't' => '\t',
+                    'n' => '\n',
+                    'r' => '\r',
+                    'f' => '\x0c',
+                    c => c,
+                });
+            }
+            None => flush_utf16(&mut units, &mut out)?,
+        }
+    }
+    flush_utf16(&mut units, &mut out)?;
+    Ok(out)
+}
+
+fn flush_utf16(units: &mut Vec<u16>, out: &mut String) -> Result<()> {
+    if units.is_empty() {
+        return Ok(());
+    }
+    let decoded = String::from_utf16(units)
+        .map_err(|_| SnapconfigError::PropertiesParse("Unpaired surrogate in \\u escape".into()))?;
+    out.push_str(&decoded);
+    units.clear();
+    Ok(())
+}
+
+/// Parse CSV into an array with one entry per row. With a header row
+/// (the default) each row is an object keyed by column name; without one,
+/// an array of cells. Cells are typed like INI/env values.
+pub fn parse_csv(content: &str) -> Result<FlatValue> {
+    parse_csv_opts(content, &ParseOptions::default())
+}
+
+pub fn parse_csv_opts(content: &str, opts: &ParseOptions) -> Result<FlatValue> {
+    let csv_err = |e: csv::Error| SnapconfigError::CsvParse(e.to_string());
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(opts.csv_delimiter)
+        .has_headers(opts.csv_has_header)
+        .from_reader(content.as_bytes());
+    let headers: Option<Vec<String>> = if opts.csv_has_header {
+        let headers = reader.headers().map_err(csv_err)?;
+        Some(headers.iter().map(|h| h.trim().to_string()).collect())
+    } else {
+        None
+    };
+
+    let mut flat = FlatValue::new();
+    let mut rows: Vec<ValueIdx> = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_err)?;
+        let cells = record
+            .iter()
+            .map(|cell| parse_scalar_value(&mut flat, cell, opts))
+            .collect::<Result<Vec<_>>>()?;
+        let row_idx = match &headers {
+            Some(headers) => {
+                let pairs: Vec<(String, ValueIdx)> = headers.iter().cloned().zip(cells).collect();
+                check_duplicate_keys(&pairs, opts)?;
+                let mut pairs = dedup_pairs(pairs);
+                check_keys(pairs.len(), opts).map_err(|e| within(e, &rows.len().to_string()))?;
+                if !opts.preserve_order {
+                    sort_pairs(&mut pairs);
+                }
+                flat.add_node(ValueNode::Object(pairs))
+            }
+            None => {
+                check_elements(cells.len(), opts)
+                    .map_err(|e| within(e, &rows.len().to_string()))?;
+                flat.add_node(ValueNode::Array(cells))
+            }
+        };
+        rows.push(row_idx);
+    }
+    check_elements(rows.len(), opts)?;
+    let root_idx = flat.add_node(ValueNode::Array(rows));
+    flat.set_root(root_idx);
+    Ok(flat)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Format {
     Json,
+    Json5,
     Yaml,
     Toml,
     Ini,
     Env,
+    Properties,
+    Xml,
+    Ron,
+    Csv,
 }
 
 impl Format {
@@ -249,6 +1924,8 @@ impl Format {
 
         if path_str.ends_with(".json") {
             Some(Format::Json)
+        } else if path_str.ends_with(".json5") {
+            Some(Format::Json5)
         } else if path_str.ends_with(".yaml") || path_str.ends_with(".yml") {
             Some(Format::Yaml)
         } else if path_str.ends_with(".toml") {
@@ -258,22 +1935,128 @@ impl Format {
             || path_str.ends_with(".conf")
         {
             Some(Format::Ini)
+        } else if path_str.ends_with(".properties") {
+            Some(Format::Properties)
+        } else if path_str.ends_with(".xml") {
+            Some(Format::Xml)
+        } else if path_str.ends_with(".ron") {
+            Some(Format::Ron)
+        } else if path_str.ends_with(".csv") {
+            Some(Format::Csv)
         } else if path_str.ends_with(".env") || path_str.contains(".env.") {
             Some(Format::Env)
         } else {
             None
         }
     }
+
+    /// The name `loads(format=...)` takes for this format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Json5 => "json5",
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+            Format::Ini => "ini",
+            Format::Env => "env",
+            Format::Properties => "properties",
+            Format::Xml => "xml",
+            Format::Ron => "ron",
+            Format::Csv => "csv",
+        }
+    }
 }
 
 pub fn parse_content(content: &str, path: &Path) -> Result<FlatValue> {
-    match Format::from_path(path).unwrap_or(Format::Env) {
-        Format::Json => parse_json(content),
-        Format::Yaml => parse_yaml(content),
-        Format::Toml => parse_toml(content),
-        Format::Ini => parse_ini(content),
-        Format::Env => Ok(parse_env(content)),
+    parse_content_opts(content, path, &ParseOptions::default())
+}
+
+pub fn parse_content_opts(content: &str, path: &Path, opts: &ParseOptions) -> Result<FlatValue> {
+    let format = match Format::from_path(path) {
+        Some(format) => format,
+        None if opts.reject_unknown_format => {
+            return Err(SnapconfigError::UnknownFormat(path.display().to_string()))
+        }
+        None => Format::Env,
+    };
+    let flat = match format {
+        Format::Json => parse_json_opts(content, opts),
+        Format::Json5 => parse_json5_opts(content, opts),
+        Format::Yaml => parse_yaml_opts(content, opts),
+        Format::Toml => parse_toml_opts(content, opts),
+        Format::Ini => parse_ini_opts(content, opts),
+        Format::Env => parse_env_opts(content, opts),
+        Format::Properties => parse_properties_opts(content, opts),
+        Format::Xml => parse_xml_opts(content, opts),
+        Format::Ron => parse_ron_opts(content, opts),
+        Format::Csv => parse_csv_opts(content, opts),
     }
+    .map_err(|err| locate_error(err, content, Some(path)))?;
+    check_non_finite(&flat, opts)?;
+    let flat = match &opts.conditions {
+        Some(context) => apply_conditions(&flat, context)?,
+        None => flat,
+    };
+    Ok(if opts.dedup {
+        flat.dedup_scalars()
+    } else {
+        flat
+    })
+}
+
+/// Prefixes a JSON/YAML/TOML parse error with `file:line:column` (file is
+/// `<string>` without a `source`); other errors are returned unchanged.
+pub fn locate_error(err: SnapconfigError, content: &str, source: Option<&Path>) -> SnapconfigError {
+    let (line, column, message) = match &err {
+        SnapconfigError::JsonParse(e) => {
+            // serde_json appends the position itself; it moves to the prefix.
+            let message = err.to_string().replacen(
+                &format!(" at line {} column {}", e.line(), e.column()),
+                "",
+                1,
+            );
+            (e.line(), e.column(), message)
+        }
+        SnapconfigError::YamlParse(e) => {
+            let Some(location) = e.location() else {
+                return err;
+            };
+            let (line, column) = (location.line(), location.column());
+            // serde_yaml appends the position itself; it moves to the prefix.
+            let message =
+                err.to_string()
+                    .replacen(&format!(" at line {} column {}", line, column), "", 1);
+            (line, column, message)
+        }
+        SnapconfigError::TomlParse(e) => {
+            let Some(span) = e.span() else {
+                return err;
+            };
+            let (line, column) = line_column(content, span.start);
+            (
+                line,
+                column,
+                format!("TOML parse error: {}", e.message().trim_end()),
+            )
+        }
+        _ => return err,
+    };
+    let file = source.map_or_else(|| "<string>".to_string(), |path| path.display().to_string());
+    SnapconfigError::Located(format!("{}:{}:{}: {}", file, line, column, message))
+}
+
+/// One-based line and column (in characters) of byte `offset` in `content`.
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let mut offset = offset.min(content.len());
+    while !content.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &content[..offset];
+    let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
 }
 
 #[cfg(test)]
@@ -292,18 +2075,514 @@ mod tests {
         assert_eq!(flat.len(), 4); // int, 3 objects
     }
 
+    #[test]
+    fn test_parse_json_duplicate_keys_last_wins() {
+        let flat = parse_json(r#"{"b": 1, "a": 2, "b": 3, "b": 4}"#).unwrap();
+        let ValueNode::Object(pairs) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        let keys: Vec<&str> = pairs.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["a", "b"]);
+        assert_eq!(flat.nodes[pairs[1].1 as usize], ValueNode::Int(4));
+    }
+
+    #[test]
+    fn test_parse_json_nesting_beyond_128_levels() {
+        let nested = |levels: usize| format!("{}{}", "[".repeat(levels), "]".repeat(levels));
+        assert_eq!(
+            parse_json(&nested(MAX_JSON_DEPTH)).unwrap().len(),
+            MAX_JSON_DEPTH
+        );
+
+        let err = parse_json(&nested(100_000)).unwrap_err();
+        assert!(
+            err.to_string().contains("recursion limit exceeded"),
+            "{}",
+            err
+        );
+        let objects =
+            |levels: usize| format!("{}1{}", "{\"a\":".repeat(levels), "}".repeat(levels));
+        assert!(parse_json(&objects(MAX_JSON_DEPTH)).is_ok());
+        let err = parse_json(&objects(MAX_JSON_DEPTH + 1)).unwrap_err();
+        assert!(
+            err.to_string().contains("recursion limit exceeded"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_parse_json_preserve_order() {
+        let opts = ParseOptions {
+            preserve_order: true,
+            ..ParseOptions::default()
+        };
+        let flat = parse_json_opts(r#"{"z": 1, "a": 2, "m": 3, "a": 4}"#, &opts).unwrap();
+        let root_idx = flat.root().expect("expected root");
+        if let ValueNode::Object(pairs) = &flat.nodes[root_idx as usize] {
+            let keys: Vec<&str> = pairs.iter().map(|(k, _)| k.as_str()).collect();
+            assert_eq!(keys, vec!["z", "a", "m"]);
+            assert_eq!(flat.nodes[pairs[1].1 as usize], ValueNode::Int(4));
+        } else {
+            panic!("Expected Object");
+        }
+    }
+
+    #[test]
+    fn test_preserve_order_other_formats() {
+        let opts = ParseOptions {
+            preserve_order: true,
+            ..ParseOptions::default()
+        };
+        let top_keys = |flat: FlatValue| -> Vec<String> {
+            match &flat.nodes[flat.root().unwrap() as usize] {
+                ValueNode::Object(pairs) => pairs.iter().map(|(k, _)| k.clone()).collect(),
+                _ => panic!("expected object root"),
+            }
+        };
+        assert_eq!(
+            top_keys(parse_yaml_opts("z: 1\na: 2\n", &opts).unwrap()),
+            ["z", "a"]
+        );
+        assert_eq!(
+            top_keys(parse_toml_opts("z = 1\na = 2\n", &opts).unwrap()),
+            ["z", "a"]
+        );
+        assert_eq!(
+            top_keys(parse_env_opts("Z=1\nA=2\nZ=3\n", &opts).unwrap()),
+            ["Z", "A"]
+        );
+        assert_eq!(top_keys(parse_toml("z = 1\na = 2\n").unwrap()), ["a", "z"]);
+    }
+
+    #[test]
+    fn test_parse_json_strip_comments() {
+        let opts = ParseOptions {
+            strip_comments: true,
+            ..ParseOptions::default()
+        };
+        let content = r#"{
+            // line comment
+            "url": "http://example.com", /* block
+            comment */ "n": 1
+        }"#;
+        assert!(parse_json(content).is_err());
+        let flat = parse_json_opts(content, &opts).unwrap();
+        let root_idx = flat.root().expect("expected root");
+        if let ValueNode::Object(pairs) = &flat.nodes[root_idx as usize] {
+            assert_eq!(pairs.len(), 2);
+            assert_eq!(pairs[1].0, "url");
+            assert_eq!(
+                flat.nodes[pairs[1].1 as usize],
+                ValueNode::String("http://example.com".to_string())
+            );
+        } else {
+            panic!("Expected Object");
+        }
+    }
+
+    #[test]
+    fn test_parse_json_allow_nan() {
+        let opts = ParseOptions {
+            allow_nan: true,
+            ..ParseOptions::default()
+        };
+        let content = r#"[null, NaN, "NaN", -Infinity, 18446744073709551615, null, Infinity]"#;
+        assert!(parse_json(content).is_err());
+        let flat = parse_json_opts(content, &opts).unwrap();
+        let root_idx = flat.root().expect("expected root");
+        let ValueNode::Array(items) = &flat.nodes[root_idx as usize] else {
+            panic!("Expected Array");
+        };
+        let values: Vec<_> = items.iter().map(|&i| &flat.nodes[i as usize]).collect();
+        assert_eq!(values[0], &ValueNode::Null);
+        assert!(matches!(values[1], ValueNode::Float(f) if f.is_nan()));
+        assert_eq!(values[2], &ValueNode::String("NaN".to_string()));
+        assert_eq!(values[3], &ValueNode::Float(f64::NEG_INFINITY));
+        assert_eq!(values[4], &ValueNode::UInt(u64::MAX));
+        assert_eq!(values[5], &ValueNode::Null);
+        assert_eq!(values[6], &ValueNode::Float(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_parse_json5() {
+        let flat =
+            parse_json5("{\n  // hand-edited\n  name: 'api',\n  ports: [80, 443,],\n}").unwrap();
+        let Some(ValueNode::Object(pairs)) = flat.root().map(|r| &flat.nodes[r as usize]) else {
+            panic!("expected object root");
+        };
+        assert_eq!(pairs[0].0, "name");
+        assert_eq!(pairs[1].0, "ports");
+        assert!(parse_json5("{a: }").is_err());
+    }
+
     #[test]
     fn test_parse_yaml() {
         let flat = parse_yaml("key: value\nnum: 42").unwrap();
         assert_eq!(flat.len(), 3);
     }
 
+    #[test]
+    fn test_parse_yaml_merge_keys() {
+        let content = "\
+base: &base {host: db, port: 1, tls: false}
+extra: &extra {port: 2, pool: 5, tls: true}
+chained: &chained {<<: *base, user: app}
+single: {<<: *base, port: 3}
+multi: {<<: [*base, *extra], name: x}
+deep: {<<: *chained}
+";
+        let flat = parse_yaml(content).unwrap();
+        let object = |idx: ValueIdx| match &flat.nodes[idx as usize] {
+            ValueNode::Object(pairs) => pairs,
+            other => panic!("expected object, got {:?}", other),
+        };
+        let lookup = |path: &str| {
+            path.split('.').fold(flat.root().unwrap(), |idx, part| {
+                object(idx).iter().find(|(k, _)| k == part).expect(path).1
+            })
+        };
+        let get = |path: &str| flat.nodes[lookup(path) as usize].clone();
+        let keys = |path: &str| {
+            object(lookup(path))
+                .iter()
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        // Explicit keys override merged ones.
+        assert_eq!(get("single.port"), ValueNode::Int(3));
+        assert_eq!(get("single.host"), ValueNode::String("db".into()));
+        assert_eq!(keys("single"), ["host", "port", "tls"]);
+        // Earlier sources win over later ones.
+        assert_eq!(get("multi.port"), ValueNode::Int(1));
+        assert_eq!(get("multi.tls"), ValueNode::Bool(false));
+        assert_eq!(get("multi.pool"), ValueNode::Int(5));
+        // Merges inside merge sources are expanded too.
+        assert_eq!(keys("deep"), ["host", "port", "tls", "user"]);
+    }
+
+    #[test]
+    fn test_parse_yaml_u64() {
+        let flat = parse_yaml("big: 18446744073709551615").unwrap();
+        assert_eq!(flat.nodes[0], ValueNode::UInt(u64::MAX));
+    }
+
+    #[test]
+    fn test_parse_yaml_big_ints_keep_digits() {
+        let forty = "1".repeat(40);
+        let content = format!("a: {forty}\nb: &n -18446744073709551616\nc: *n\nd: |\n  {forty}\n");
+        let flat = parse_yaml(&content).unwrap();
+        let values: Vec<&ValueNode> = match &flat.nodes[flat.root().unwrap() as usize] {
+            ValueNode::Object(pairs) => pairs.iter().map(|p| &flat.nodes[p.1 as usize]).collect(),
+            _ => panic!("expected object"),
+        };
+        assert_eq!(values[0], &ValueNode::BigInt(forty.clone()));
+        assert_eq!(
+            values[1],
+            &ValueNode::BigInt("-18446744073709551616".to_string())
+        );
+        assert_eq!(values[2], values[1]);
+        assert_eq!(values[3], &ValueNode::String(format!("{forty}\n")));
+    }
+
+    #[test]
+    fn test_locate_error() {
+        let content = "a: 1\nb: [1, 2\n";
+        let err = parse_content_opts(content, Path::new("app.yaml"), &ParseOptions::default())
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("app.yaml:3:1: YAML parse error: "));
+
+        let err = locate_error(
+            parse_toml("a = 1\nb = =\n").unwrap_err(),
+            "a = 1\nb = =\n",
+            None,
+        );
+        assert!(err
+            .to_string()
+            .starts_with("<string>:2:5: TOML parse error: "));
+
+        assert_eq!(line_column("ab\ncdé\nx", 7), (2, 4));
+    }
+
+    #[test]
+    fn test_parse_ron() {
+        let flat = parse_ron(
+            r#"Config(
+                name: "svc",
+                ports: [80, 443],
+                pair: (1, 2.5),
+                limits: {"cpu": 2, 'x': true},
+                backup: None,
+                replica: Some("r1"),
+                marker: Unit,
+                listener: Tcp(port: 8080),
+            )"#,
+        )
+        .unwrap();
+        let root = flat.root().unwrap() as usize;
+        let ValueNode::Object(pairs) = &flat.nodes[root] else {
+            panic!("expected object root");
+        };
+        let get = |key: &str| {
+            let idx = pairs.iter().find(|(k, _)| k == key).unwrap().1;
+            flat.nodes[idx as usize].clone()
+        };
+        assert_eq!(get("name"), ValueNode::String("svc".into()));
+        assert_eq!(get("backup"), ValueNode::Null);
+        assert_eq!(get("marker"), ValueNode::Null);
+        assert_eq!(get("replica"), ValueNode::String("r1".into()));
+        let ValueNode::Array(pair) = get("pair") else {
+            panic!("expected tuple as array");
+        };
+        assert_eq!(flat.nodes[pair[1] as usize], ValueNode::Float(2.5));
+        let ValueNode::Object(limits) = get("limits") else {
+            panic!("expected map as object");
+        };
+        assert_eq!(limits[0].0, "cpu");
+        assert_eq!(limits[1].0, "x");
+        let ValueNode::Object(listener) = get("listener") else {
+            panic!("expected struct variant as object");
+        };
+        assert_eq!(listener[0].0, "Tcp");
+        let ValueNode::Object(payload) = &flat.nodes[listener[0].1 as usize] else {
+            panic!("expected variant payload as object");
+        };
+        assert_eq!(payload[0].0, "port");
+
+        assert!(matches!(
+            parse_ron("(a: [1, 2"),
+            Err(SnapconfigError::RonParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_tag_ron_variants() {
+        assert_eq!(
+            tag_ron_variants("Config(a: Tcp(port: 1), b: Some(Pair(1, 2)), c: r#type ())"),
+            r#"Config(a: {"Tcp":(port: 1)}, b: Some({"Pair":(1, 2)}), c: {"type": ()})"#
+        );
+        let untouched = r##"(s: "Tcp(1)", r: r#"Udp(")"#, /* X(1) */ n: 0x1F, m: Unit)"##;
+        assert_eq!(tag_ron_variants(untouched), untouched);
+        assert_eq!(tag_ron_variants("(a: Tcp(1"), "(a: Tcp(1");
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let opts = ParseOptions {
+            max_keys: Some(2),
+            max_array_len: Some(3),
+            ..ParseOptions::default()
+        };
+        let path = Path::new("limits.json");
+        assert!(
+            parse_content_opts(r#"{"a": [1, 2, 3], "b": {"c": 1, "d": 2}}"#, path, &opts).is_ok()
+        );
+
+        let err = parse_content_opts(r#"{"a": {"x": [1, 2, 3, 4]}}"#, path, &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Limit exceeded: 'a.x' has 4 elements (max_array_len=3)"
+        );
+        let err = parse_content_opts(r#"{"a": 1, "b": 2, "c": 3}"#, path, &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Limit exceeded: the root has 3 keys (max_keys=2)"
+        );
+
+        let nested = [
+            ("limits.yaml", "a:\n  - x: [1, 2, 3, 4]\n"),
+            ("limits.toml", "[[a]]\nx = [1, 2, 3, 4]\n"),
+            ("limits.json5", "{a: [{x: [1, 2, 3, 4]}]}"),
+            ("limits.ron", "(a: [(x: [1, 2, 3, 4])])"),
+        ];
+        for (name, content) in nested {
+            let err = parse_content_opts(content, Path::new(name), &opts).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Limit exceeded: 'a.0.x' has 4 elements (max_array_len=3)",
+                "{}",
+                name
+            );
+        }
+        let err =
+            parse_content_opts("[s]\na=1\nb=2\nc=3\n", Path::new("limits.ini"), &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Limit exceeded: 's' has 3 keys (max_keys=2)"
+        );
+        let err = parse_content_opts(
+            "<r><s a=\"1\"><b/><c/></s></r>",
+            Path::new("limits.xml"),
+            &opts,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Limit exceeded: 'r.s' has 3 keys (max_keys=2)"
+        );
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let flat = parse_csv("name,port,tls\nweb,80,false\n\"db, main\",5432,true\n").unwrap();
+        let ValueNode::Array(rows) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected array root");
+        };
+        assert_eq!(rows.len(), 2);
+        let ValueNode::Object(row) = &flat.nodes[rows[1] as usize] else {
+            panic!("expected object row");
+        };
+        let keys: Vec<&str> = row.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["name", "port", "tls"]);
+        assert_eq!(
+            flat.nodes[row[0].1 as usize],
+            ValueNode::String("db, main".into())
+        );
+        assert_eq!(flat.nodes[row[1].1 as usize], ValueNode::Int(5432));
+        assert_eq!(flat.nodes[row[2].1 as usize], ValueNode::Bool(true));
+
+        let opts = ParseOptions {
+            csv_delimiter: b';',
+            csv_has_header: false,
+            ..ParseOptions::default()
+        };
+        let flat = parse_csv_opts("a;1\nb;2.5\n", &opts).unwrap();
+        let ValueNode::Array(rows) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected array root");
+        };
+        let ValueNode::Array(cells) = &flat.nodes[rows[1] as usize] else {
+            panic!("expected array row");
+        };
+        assert_eq!(flat.nodes[cells[1] as usize], ValueNode::Float(2.5));
+
+        assert!(matches!(
+            parse_csv("a,b\n1,2,3\n"),
+            Err(SnapconfigError::CsvParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_xml() {
+        let flat = parse_xml(
+            r#"<?xml version="1.0"?>
+<server name="web" port="8080">
+  <!-- hosts -->
+  <host>a</host>
+  <host>b</host>
+  <tls/>
+  <note lang="en">hi &amp; bye</note>
+</server>"#,
+        )
+        .unwrap();
+        let lookup = |path: &str| {
+            let mut idx = flat.root().unwrap();
+            for part in path.split('.') {
+                idx = match &flat.nodes[idx as usize] {
+                    ValueNode::Object(pairs) => pairs.iter().find(|(k, _)| k == part).unwrap().1,
+                    ValueNode::Array(items) => items[part.parse::<usize>().unwrap()],
+                    _ => panic!("cannot descend into {}", part),
+                };
+            }
+            flat.nodes[idx as usize].clone()
+        };
+        assert_eq!(lookup("server.@port"), ValueNode::Int(8080));
+        assert_eq!(lookup("server.host.1"), ValueNode::String("b".into()));
+        assert_eq!(lookup("server.tls"), ValueNode::Null);
+        assert_eq!(
+            lookup("server.note.#text"),
+            ValueNode::String("hi & bye".into())
+        );
+
+        assert!(matches!(
+            parse_xml("<a><b></a>"),
+            Err(SnapconfigError::XmlParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_yaml_multi() {
+        let flat = parse_yaml_multi("kind: A\n---\nkind: B\n").unwrap();
+        let ValueNode::Array(docs) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected array of documents");
+        };
+        assert_eq!(docs.len(), 2);
+
+        let single = parse_yaml_multi("kind: A\n").unwrap();
+        assert!(matches!(
+            single.nodes[single.root().unwrap() as usize],
+            ValueNode::Object(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_toml_merge_key() {
+        let opts = ParseOptions {
+            toml_merge_key: Some("name".to_string()),
+            ..ParseOptions::default()
+        };
+        let flat = parse_toml_opts(
+            "[[host]]\nname = \"a\"\nport = 80\n\
+             [[host]]\nname = \"b\"\n\
+             [[host]]\nname = \"a\"\nport = 8080\ntls = true\n",
+            &opts,
+        )
+        .unwrap();
+        let root = flat.root().unwrap() as usize;
+        let ValueNode::Object(top) = &flat.nodes[root] else {
+            panic!("expected object root");
+        };
+        let ValueNode::Array(hosts) = &flat.nodes[top[0].1 as usize] else {
+            panic!("expected host array");
+        };
+        assert_eq!(hosts.len(), 2);
+        let ValueNode::Object(first) = &flat.nodes[hosts[0] as usize] else {
+            panic!("expected host table");
+        };
+        let fields: Vec<(&str, &ValueNode)> = first
+            .iter()
+            .map(|(k, idx)| (k.as_str(), &flat.nodes[*idx as usize]))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("name", &ValueNode::String("a".into())),
+                ("port", &ValueNode::Int(8080)),
+                ("tls", &ValueNode::Bool(true)),
+            ]
+        );
+
+        // Without the option the entries stay separate.
+        let flat = parse_toml("[[host]]\nname = \"a\"\n[[host]]\nname = \"a\"\n").unwrap();
+        let ValueNode::Object(top) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        assert!(
+            matches!(&flat.nodes[top[0].1 as usize], ValueNode::Array(items) if items.len() == 2)
+        );
+    }
+
     #[test]
     fn test_parse_toml() {
         let flat = parse_toml("[section]\nkey = \"value\"").unwrap();
         assert_eq!(flat.len(), 3); // string, section object, root object
     }
 
+    #[test]
+    fn test_parse_toml_datetimes() {
+        let flat = parse_toml("odt = 1979-05-27T07:32:00Z\nld = 1979-05-27").unwrap();
+        assert!(flat.nodes.contains(&ValueNode::Datetime(
+            DatetimeKind::OffsetDateTime,
+            "1979-05-27T07:32:00Z".into()
+        )));
+        assert!(flat.nodes.contains(&ValueNode::Datetime(
+            DatetimeKind::LocalDate,
+            "1979-05-27".into()
+        )));
+    }
+
     #[test]
     fn test_parse_ini() {
         let flat = parse_ini("[section]\nkey = value").unwrap();
@@ -330,6 +2609,102 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_env_sections() {
+        let content = "ROOT=1\n[db]\nexport HOST=\"localhost\"\nPORT=5432\n[cache]\nTTL=60";
+        let opts = ParseOptions {
+            sections: true,
+            ..ParseOptions::default()
+        };
+        let flat = parse_env_opts(content, &opts).unwrap();
+        let root = &flat.nodes[flat.root().unwrap() as usize];
+        let ValueNode::Object(pairs) = root else {
+            panic!("expected object root");
+        };
+        let keys: Vec<&str> = pairs.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["ROOT", "cache", "db"]);
+        let ValueNode::Object(db) = &flat.nodes[pairs[2].1 as usize] else {
+            panic!("expected db section");
+        };
+        assert_eq!(db[0].0, "HOST");
+        assert_eq!(
+            flat.nodes[db[0].1 as usize],
+            ValueNode::String("localhost".into())
+        );
+        assert_eq!(flat.nodes[db[1].1 as usize], ValueNode::Int(5432));
+
+        // Without the option, header lines are ignored as before.
+        let flat = parse_env(content);
+        let ValueNode::Object(pairs) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        assert_eq!(pairs.len(), 4);
+    }
+
+    #[test]
+    fn test_parse_env_empty_as_null() {
+        let content = "EMPTY=\nQUOTED=\"\"";
+        let flat = parse_env(content);
+        assert_eq!(flat.nodes[0], ValueNode::String(String::new()));
+
+        let opts = ParseOptions {
+            empty_as_null: true,
+            ..ParseOptions::default()
+        };
+        let flat = parse_env_opts(content, &opts).unwrap();
+        assert_eq!(flat.nodes[0], ValueNode::Null);
+        assert_eq!(flat.nodes[1], ValueNode::String(String::new()));
+    }
+
+    #[test]
+    fn test_parse_env_interpolate() {
+        let content = "HOST=db\nPORT=5432\nURL=\"postgres://${HOST}:$PORT/${MISSING}x\"\nRAW='$HOST'\nPRICE=\\$5 $1";
+        let opts = ParseOptions {
+            interpolate: true,
+            ..ParseOptions::default()
+        };
+        let flat = parse_env_opts(content, &opts).unwrap();
+        let ValueNode::Object(pairs) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        let get = |key: &str| {
+            let (_, idx) = pairs.iter().find(|(k, _)| k == key).unwrap();
+            flat.nodes[*idx as usize].clone()
+        };
+        assert_eq!(get("URL"), ValueNode::String("postgres://db:5432/x".into()));
+        assert_eq!(get("RAW"), ValueNode::String("$HOST".into()));
+        assert_eq!(get("PRICE"), ValueNode::String("$5 $1".into()));
+
+        // Off by default: values stay literal.
+        let flat = parse_env("HOST=db\nURL=${HOST}");
+        assert_eq!(flat.nodes[1], ValueNode::String("${HOST}".into()));
+    }
+
+    #[test]
+    fn test_parse_env_multiline() {
+        let content = "KEY=\"-----BEGIN KEY-----\nabc\\\"def\n-----END KEY-----\" # pem\nRAW='line one\n  line \\n two'\nESC=\"a\\nb\"\nOPEN=\"never closed\nAFTER=1\n";
+        let flat = parse_env(content);
+        let ValueNode::Object(pairs) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        let get = |key: &str| {
+            let (_, idx) = pairs.iter().find(|(k, _)| k == key).unwrap();
+            flat.nodes[*idx as usize].clone()
+        };
+        assert_eq!(
+            get("KEY"),
+            ValueNode::String("-----BEGIN KEY-----\nabc\"def\n-----END KEY-----".into())
+        );
+        assert_eq!(
+            get("RAW"),
+            ValueNode::String("line one\n  line \\n two".into())
+        );
+        assert_eq!(get("ESC"), ValueNode::String("a\nb".into()));
+        // An unterminated quote doesn't swallow the rest of the file.
+        assert_eq!(get("OPEN"), ValueNode::String("\"never closed".into()));
+        assert_eq!(get("AFTER"), ValueNode::Int(1));
+    }
+
     #[test]
     fn test_parse_env_quotes() {
         let flat = parse_env("KEY=\"quoted value\"");
@@ -346,12 +2721,157 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_env_separator_in_key() {
+        let content = "\"A=B\"=1\n'C D=' = two\nE\\=F=3\nURL=http://h/?x=1&y=2\n\"G\\\"H\"=4\n";
+        let flat = parse_env(content);
+        let ValueNode::Object(pairs) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        let get = |key: &str| {
+            let (_, idx) = pairs.iter().find(|(k, _)| k == key).unwrap();
+            flat.nodes[*idx as usize].clone()
+        };
+        assert_eq!(get("A=B"), ValueNode::Int(1));
+        assert_eq!(get("C D="), ValueNode::String("two".into()));
+        assert_eq!(get("E=F"), ValueNode::Int(3));
+        assert_eq!(get("URL"), ValueNode::String("http://h/?x=1&y=2".into()));
+        assert_eq!(get("G\"H"), ValueNode::Int(4));
+    }
+
+    #[test]
+    fn test_parse_properties() {
+        let content = "# comment\n! also a comment\na.b.c=1\nname : Jos\\u00e9\nlist = one, \\\n    two\nkey\\ with\\ space value\n";
+        let flat = parse_properties(content).unwrap();
+        let ValueNode::Object(pairs) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        let get = |key: &str| {
+            let (_, idx) = pairs.iter().find(|(k, _)| k == key).unwrap();
+            flat.nodes[*idx as usize].clone()
+        };
+        assert_eq!(get("a.b.c"), ValueNode::Int(1));
+        assert_eq!(get("name"), ValueNode::String("José".into()));
+        assert_eq!(get("list"), ValueNode::String("one, two".into()));
+        assert_eq!(get("key with space"), ValueNode::String("value".into()));
+        assert!(parse_properties("bad=\\u12").is_err());
+    }
+
+    #[test]
+    fn test_spaced_keys_trimmed() {
+        let flat = parse_ini_opts("[s]\n  key = value\n", &ParseOptions::default()).unwrap();
+        let root_idx = flat.root().expect("expected root");
+        let ValueNode::Object(sections) = &flat.nodes[root_idx as usize] else {
+            panic!("Expected Object");
+        };
+        let section = sections.iter().find(|(k, _)| k == "s").unwrap();
+        if let ValueNode::Object(pairs) = &flat.nodes[section.1 as usize] {
+            assert_eq!(pairs[0].0, "key");
+        } else {
+            panic!("Expected Object");
+        }
+
+        let flat = parse_env_opts("  KEY = value", &ParseOptions::default()).unwrap();
+        let root_idx = flat.root().expect("expected root");
+        if let ValueNode::Object(pairs) = &flat.nodes[root_idx as usize] {
+            assert_eq!(pairs[0].0, "KEY");
+        } else {
+            panic!("Expected Object");
+        }
+    }
+
+    #[test]
+    fn test_parse_ini_repeated_keys() {
+        let flat =
+            parse_ini("[upstream]\nserver = a\nport = 80\nserver = b\nserver = 3\n").unwrap();
+        let ValueNode::Object(sections) = &flat.nodes[flat.root().unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        let (_, section_idx) = sections.iter().find(|(k, _)| k == "upstream").unwrap();
+        let ValueNode::Object(pairs) = &flat.nodes[*section_idx as usize] else {
+            panic!("expected section object");
+        };
+        assert_eq!(pairs[0].0, "port");
+        assert_eq!(flat.nodes[pairs[0].1 as usize], ValueNode::Int(80));
+        assert_eq!(pairs[1].0, "server");
+        let ValueNode::Array(servers) = &flat.nodes[pairs[1].1 as usize] else {
+            panic!("expected repeated key to become an array");
+        };
+        let values: Vec<_> = servers
+            .iter()
+            .map(|&i| flat.nodes[i as usize].clone())
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ValueNode::String("a".into()),
+                ValueNode::String("b".into()),
+                ValueNode::Int(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_keys_rejects_spaced_key() {
+        let opts = ParseOptions {
+            strict_keys: true,
+            ..ParseOptions::default()
+        };
+        assert!(matches!(
+            parse_ini_opts("[s]\n  key = value\n", &opts),
+            Err(SnapconfigError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            parse_env_opts("  KEY=value", &opts),
+            Err(SnapconfigError::InvalidKey(_))
+        ));
+        // Padding before the separator is not a stray key character.
+        assert!(parse_ini_opts("[s]\nkey = value\n", &opts).is_ok());
+    }
+
+    #[test]
+    fn test_strict_preset() {
+        let strict = ParseOptions::default().with_strict(true);
+        assert!(matches!(
+            parse_json_opts(r#"{"a": {"b": 1, "b": 2}}"#, &strict),
+            Err(SnapconfigError::DuplicateKey(_))
+        ));
+        assert!(matches!(
+            parse_env_opts("PORT=99999999999999999999", &strict),
+            Err(SnapconfigError::NumberOverflow(_))
+        ));
+        let flat = parse_yaml("limits:\n  max: .inf\n").unwrap();
+        assert!(matches!(
+            check_non_finite(&flat, &strict),
+            Err(SnapconfigError::NonFiniteFloat(msg)) if msg.contains("limits.max")
+        ));
+        assert!(matches!(
+            parse_content_opts("A=1", Path::new("settings.txt"), &strict),
+            Err(SnapconfigError::UnknownFormat(_))
+        ));
+
+        // Through the tape, strict JSON still sorts keys like the default path.
+        let content = r#"{"z": 1, "a": [1.5, "x"]}"#;
+        assert_eq!(
+            parse_json_opts(content, &strict).unwrap().nodes,
+            parse_json(content).unwrap().nodes
+        );
+    }
+
     #[test]
     fn test_format_detection() {
         assert_eq!(
             Format::from_path(Path::new("config.json")),
             Some(Format::Json)
         );
+        assert_eq!(
+            Format::from_path(Path::new("config.json5")),
+            Some(Format::Json5)
+        );
+        assert_eq!(
+            Format::from_path(Path::new("app.properties")),
+            Some(Format::Properties)
+        );
         assert_eq!(
             Format::from_path(Path::new("config.yaml")),
             Some(Format::Yaml)
@@ -0,0 +1,220 @@
+//! Typed schemas: fill in defaults and coerce scalars to declared types.
+//!
+//! A schema is a TOML document whose tables carrying a `type` key describe
+//! the value at their dotted position, e.g.
+//!
+//! ```toml
+//! [database.port]
+//! type = "int"
+//! default = 5432
+//! ```
+
+use crate::error::{Result, SnapconfigError};
+use crate::parsers::{add_toml_value, ParseOptions};
+use crate::value::{FlatValue, ValueIdx, ValueNode};
+
+/// Applies every entry of `schema` to `flat`: missing paths get the entry's
+/// `default` (an error without one), and scalars are coerced to its `type`.
+pub fn apply_schema(flat: &mut FlatValue, schema: &toml::Table) -> Result<()> {
+    let mut entries = Vec::new();
+    collect_entries(schema, "", &mut entries);
+
+    for (path, entry) in entries {
+        let type_name = match entry.get("type") {
+            Some(toml::Value::String(name)) => name.as_str(),
+            _ => {
+                return Err(SnapconfigError::SchemaViolation(format!(
+                    "'{}': type must be a string",
+                    path
+                )))
+            }
+        };
+
+        let idx = match lookup(flat, &path) {
+            Some(idx) => idx,
+            None => match entry.get("default") {
+                Some(default) => insert_default(flat, &path, default.clone())?,
+                None => {
+                    return Err(SnapconfigError::SchemaViolation(format!(
+                        "'{}' is missing and has no default",
+                        path
+                    )))
+                }
+            },
+        };
+
+        let coerced = coerce(&flat.nodes[idx as usize], type_name, &path)?;
+        if let Some(node) = coerced {
+            flat.nodes[idx as usize] = node;
+        }
+    }
+    Ok(())
+}
+
+fn collect_entries<'a>(
+    table: &'a toml::Table,
+    prefix: &str,
+    out: &mut Vec<(String, &'a toml::Table)>,
+) {
+    for (key, value) in table {
+        let toml::Value::Table(child) = value else {
+            continue;
+        };
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        if child.contains_key("type") {
+            out.push((path, child));
+        } else {
+            collect_entries(child, &path, out);
+        }
+    }
+}
+
+fn lookup(flat: &FlatValue, path: &str) -> Option<ValueIdx> {
+    let mut idx = flat.root()?;
+    for part in path.split('.') {
+        idx = match &flat.nodes[idx as usize] {
+            ValueNode::Object(pairs) => pairs.iter().find(|(k, _)| k == part)?.1,
+            ValueNode::Array(items) => *items.get(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(idx)
+}
+
+/// Adds `default` at `path`, creating missing parent objects. Keys are
+/// inserted in sorted position, matching how parsed objects are stored.
+fn insert_default(flat: &mut FlatValue, path: &str, default: toml::Value) -> Result<ValueIdx> {
+    let parts: Vec<&str> = path.split('.').collect();
+    let mut parent = flat
+        .root()
+        .ok_or_else(|| SnapconfigError::SchemaViolation("Config has no root".to_string()))?;
+
+    for (depth, part) in parts.iter().enumerate() {
+        let existing = match &flat.nodes[parent as usize] {
+            ValueNode::Object(pairs) => pairs.iter().find(|(k, _)| k == part).map(|(_, idx)| *idx),
+            _ => {
+                return Err(SnapconfigError::SchemaViolation(format!(
+                    "Cannot add a default for '{}': '{}' is not an object",
+                    path,
+                    parts[..depth].join(".")
+                )))
+            }
+        };
+        if let Some(idx) = existing {
+            parent = idx;
+            continue;
+        }
+
+        let child = if depth + 1 == parts.len() {
+            add_toml_value(flat, default.clone(), &ParseOptions::default())?
+        } else {
+            flat.add_node(ValueNode::Object(Vec::new()))
+        };
+        if let ValueNode::Object(pairs) = &mut flat.nodes[parent as usize] {
+            let pos = pairs.partition_point(|(k, _)| k.as_str() < *part);
+            pairs.insert(pos, (part.to_string(), child));
+        }
+        parent = child;
+    }
+    Ok(parent)
+}
+
+/// Reads `true`/`yes`/`on`/`1` and `false`/`no`/`off`/`0`, ignoring case
+/// and surrounding whitespace.
+pub(crate) fn parse_bool_word(text: &str) -> Option<bool> {
+    match text.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// The node `node` should become to satisfy `type_name`, or None when it
+/// already does.
+fn coerce(node: &ValueNode, type_name: &str, path: &str) -> Result<Option<ValueNode>> {
+    let violation = || {
+        let shown = match node {
+            ValueNode::String(s) => format!(" ({:?})", s),
+            _ => String::new(),
+        };
+        SnapconfigError::SchemaViolation(format!(
+            "'{}' cannot be read as {}{}",
+            path, type_name, shown
+        ))
+    };
+
+    let coerced = match (type_name, node) {
+        ("int", ValueNode::Int(_) | ValueNode::UInt(_) | ValueNode::BigInt(_)) => None,
+        ("int", ValueNode::String(s)) => {
+            Some(ValueNode::Int(s.trim().parse().map_err(|_| violation())?))
+        }
+        ("float", ValueNode::Float(_)) => None,
+        ("float", ValueNode::Int(i)) => Some(ValueNode::Float(*i as f64)),
+        ("float", ValueNode::String(s)) => {
+            Some(ValueNode::Float(s.trim().parse().map_err(|_| violation())?))
+        }
+        ("bool", ValueNode::Bool(_)) => None,
+        ("bool", ValueNode::String(s)) => {
+            Some(ValueNode::Bool(parse_bool_word(s).ok_or_else(violation)?))
+        }
+        ("str" | "string", ValueNode::String(_)) => None,
+        ("str" | "string", ValueNode::Int(i)) => Some(ValueNode::String(i.to_string())),
+        ("str" | "string", ValueNode::UInt(u)) => Some(ValueNode::String(u.to_string())),
+        ("str" | "string", ValueNode::BigInt(digits)) => Some(ValueNode::String(digits.clone())),
+        ("str" | "string", ValueNode::Float(f)) => Some(ValueNode::String(f.to_string())),
+        ("str" | "string", ValueNode::Bool(b)) => Some(ValueNode::String(b.to_string())),
+        ("bytes", ValueNode::Bytes(_)) => None,
+        ("list" | "array", ValueNode::Array(_)) => None,
+        ("dict" | "object", ValueNode::Object(_)) => None,
+        ("any", _) => None,
+        (
+            "int" | "float" | "bool" | "str" | "string" | "bytes" | "list" | "array" | "dict"
+            | "object",
+            _,
+        ) => return Err(violation()),
+        _ => {
+            return Err(SnapconfigError::SchemaViolation(format!(
+                "'{}': unknown type '{}'",
+                path, type_name
+            )))
+        }
+    };
+    Ok(coerced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::parse_json;
+
+    #[test]
+    fn test_apply_schema() {
+        let mut flat = parse_json(r#"{"db": {"port": "5432"}, "debug": "yes"}"#).unwrap();
+        let schema: toml::Table = toml::from_str(
+            "[db.port]\ntype = \"int\"\n[db.host]\ntype = \"str\"\ndefault = \"localhost\"\n\
+             [debug]\ntype = \"bool\"\n[limits.ratio]\ntype = \"float\"\ndefault = 1\n",
+        )
+        .unwrap();
+        apply_schema(&mut flat, &schema).unwrap();
+
+        let get = |path: &str| flat.nodes[lookup(&flat, path).unwrap() as usize].clone();
+        assert_eq!(get("db.port"), ValueNode::Int(5432));
+        assert_eq!(get("db.host"), ValueNode::String("localhost".into()));
+        assert_eq!(get("debug"), ValueNode::Bool(true));
+        assert_eq!(get("limits.ratio"), ValueNode::Float(1.0));
+        let ValueNode::Object(db) = &flat.nodes[lookup(&flat, "db").unwrap() as usize] else {
+            panic!("expected db object");
+        };
+        assert_eq!(db[0].0, "host");
+
+        let schema: toml::Table = toml::from_str("[db.port]\ntype = \"bool\"\n").unwrap();
+        assert!(matches!(
+            apply_schema(&mut flat, &schema),
+            Err(SnapconfigError::SchemaViolation(msg)) if msg.contains("'db.port'")
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 //! Core value types for snapconfig.
 
+use std::collections::HashMap;
+
 use rkyv::bytecheck::CheckBytes;
 use rkyv::{Archive, Deserialize, Serialize};
 
@@ -12,12 +14,65 @@ pub enum ValueNode {
     Null,
     Bool(bool),
     Int(i64),
+    /// Integers above `i64::MAX` that still fit in 64 bits.
+    UInt(u64),
+    /// Integers beyond 64 bits, kept exact as their decimal digits (with a
+    /// leading `-` when negative).
+    BigInt(String),
     Float(f64),
     String(String),
+    /// Raw binary data, read back as Python `bytes`.
+    Bytes(Vec<u8>),
+    /// TOML date/time, kept as its RFC 3339 text.
+    Datetime(DatetimeKind, String),
     Array(Vec<ValueIdx>),
     Object(Vec<(String, ValueIdx)>),
 }
 
+/// Which of TOML's four date/time forms a `ValueNode::Datetime` holds.
+#[derive(Archive, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[archive_attr(derive(Debug, CheckBytes))]
+pub enum DatetimeKind {
+    /// Date and time with a UTC offset (aware `datetime`).
+    OffsetDateTime,
+    /// Date and time without an offset (naive `datetime`).
+    LocalDateTime,
+    LocalDate,
+    LocalTime,
+}
+
+/// Hashable identity of a scalar node. Floats compare by bit pattern so
+/// interning never merges values that read back differently (`0.0`/`-0.0`).
+#[derive(Hash, PartialEq, Eq)]
+enum ScalarKey<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    BigInt(&'a str),
+    Float(u64),
+    String(&'a str),
+    Bytes(&'a [u8]),
+    Datetime(DatetimeKind, &'a str),
+}
+
+impl<'a> ScalarKey<'a> {
+    fn of(node: &'a ValueNode) -> Option<Self> {
+        Some(match node {
+            ValueNode::Null => ScalarKey::Null,
+            ValueNode::Bool(b) => ScalarKey::Bool(*b),
+            ValueNode::Int(i) => ScalarKey::Int(*i),
+            ValueNode::UInt(u) => ScalarKey::UInt(*u),
+            ValueNode::BigInt(digits) => ScalarKey::BigInt(digits),
+            ValueNode::Float(f) => ScalarKey::Float(f.to_bits()),
+            ValueNode::String(s) => ScalarKey::String(s),
+            ValueNode::Bytes(b) => ScalarKey::Bytes(b),
+            ValueNode::Datetime(kind, s) => ScalarKey::Datetime(*kind, s),
+            ValueNode::Array(_) | ValueNode::Object(_) => return None,
+        })
+    }
+}
+
 /// Flat storage for configuration values.
 #[derive(Archive, Deserialize, Serialize, Debug, Clone)]
 #[archive_attr(derive(Debug, CheckBytes))]
@@ -50,6 +105,187 @@ impl FlatValue {
         idx
     }
 
+    /// Rebuilds the tree so that equal scalar nodes share one index.
+    /// Containers are never shared; the values read back are unchanged.
+    pub fn dedup_scalars(&self) -> FlatValue {
+        let mut out = FlatValue::with_capacity(self.len());
+        let mut interned = HashMap::new();
+        if let Some(root) = self.root {
+            let idx = self.copy_dedup(root, &mut interned, &mut out);
+            out.set_root(idx);
+        }
+        out
+    }
+
+    fn copy_dedup<'a>(
+        &'a self,
+        idx: ValueIdx,
+        interned: &mut HashMap<ScalarKey<'a>, ValueIdx>,
+        out: &mut FlatValue,
+    ) -> ValueIdx {
+        let node = &self.nodes[idx as usize];
+        match node {
+            ValueNode::Array(indices) => {
+                let children = indices
+                    .iter()
+                    .map(|&child| self.copy_dedup(child, interned, out))
+                    .collect();
+                out.add_node(ValueNode::Array(children))
+            }
+            ValueNode::Object(pairs) => {
+                let children = pairs
+                    .iter()
+                    .map(|(key, child)| (key.clone(), self.copy_dedup(*child, interned, out)))
+                    .collect();
+                out.add_node(ValueNode::Object(children))
+            }
+            scalar => {
+                let key = ScalarKey::of(scalar).expect("containers are handled above");
+                *interned
+                    .entry(key)
+                    .or_insert_with(|| out.add_node(scalar.clone()))
+            }
+        }
+    }
+
+    /// Deep-merges `overlay` onto this tree into a fresh one. Objects merge
+    /// key by key; anything else in `overlay` replaces what it lands on,
+    /// except that arrays are concatenated with `concat_arrays`. Keys new to
+    /// an object follow the existing ones unless `sort_keys` is set.
+    pub fn merged(&self, overlay: &FlatValue, concat_arrays: bool, sort_keys: bool) -> FlatValue {
+        let mut out = FlatValue::with_capacity(self.len() + overlay.len());
+        let root = match (self.root, overlay.root) {
+            (Some(base), Some(top)) => {
+                Some(self.merge_node(base, overlay, top, concat_arrays, sort_keys, &mut out))
+            }
+            (Some(base), None) => Some(self.copy_node(base, &mut out)),
+            (None, Some(top)) => Some(overlay.copy_node(top, &mut out)),
+            (None, None) => None,
+        };
+        out.root = root;
+        out
+    }
+
+    fn merge_node(
+        &self,
+        idx: ValueIdx,
+        overlay: &FlatValue,
+        overlay_idx: ValueIdx,
+        concat_arrays: bool,
+        sort_keys: bool,
+        out: &mut FlatValue,
+    ) -> ValueIdx {
+        match (
+            &self.nodes[idx as usize],
+            &overlay.nodes[overlay_idx as usize],
+        ) {
+            (ValueNode::Object(base), ValueNode::Object(top)) => {
+                let top_keys: HashMap<&str, ValueIdx> = top
+                    .iter()
+                    .map(|(key, child)| (key.as_str(), *child))
+                    .collect();
+                let mut pairs = Vec::with_capacity(base.len() + top.len());
+                for (key, child) in base {
+                    let merged = match top_keys.get(key.as_str()) {
+                        Some(&top_child) => self.merge_node(
+                            *child,
+                            overlay,
+                            top_child,
+                            concat_arrays,
+                            sort_keys,
+                            out,
+                        ),
+                        None => self.copy_node(*child, out),
+                    };
+                    pairs.push((key.clone(), merged));
+                }
+                for (key, child) in top {
+                    if !base.iter().any(|(existing, _)| existing == key) {
+                        pairs.push((key.clone(), overlay.copy_node(*child, out)));
+                    }
+                }
+                if sort_keys {
+                    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+                }
+                out.add_node(ValueNode::Object(pairs))
+            }
+            (ValueNode::Array(base), ValueNode::Array(top)) if concat_arrays => {
+                let mut items: Vec<ValueIdx> = base
+                    .iter()
+                    .map(|&child| self.copy_node(child, out))
+                    .collect();
+                items.extend(top.iter().map(|&child| overlay.copy_node(child, out)));
+                out.add_node(ValueNode::Array(items))
+            }
+            _ => overlay.copy_node(overlay_idx, out),
+        }
+    }
+
+    /// Rewrites every object key and string value with `f`, re-sorting keys
+    /// when `sort_keys` is set. Fails with the offending key if `f` maps two
+    /// keys of one object onto the same string.
+    pub fn map_strings(
+        &mut self,
+        f: impl Fn(&str) -> String,
+        sort_keys: bool,
+    ) -> std::result::Result<(), String> {
+        for node in &mut self.nodes {
+            if let ValueNode::String(s) = node {
+                *s = f(s);
+            }
+        }
+        self.try_map_keys(|key| Ok(f(key)), sort_keys, |key| key)
+    }
+
+    /// Rewrites every object key with `f`, stopping at its first error, and
+    /// re-sorts keys when `sort_keys` is set. Two keys of one object mapping
+    /// onto the same string fail with `collision(key)`.
+    pub fn try_map_keys<E>(
+        &mut self,
+        mut f: impl FnMut(&str) -> std::result::Result<String, E>,
+        sort_keys: bool,
+        collision: impl Fn(String) -> E,
+    ) -> std::result::Result<(), E> {
+        for node in &mut self.nodes {
+            let ValueNode::Object(pairs) = node else {
+                continue;
+            };
+            for (key, _) in pairs.iter_mut() {
+                *key = f(key)?;
+            }
+            let mut keys: Vec<&str> = pairs.iter().map(|(key, _)| key.as_str()).collect();
+            keys.sort_unstable();
+            if let Some(pair) = keys.windows(2).find(|pair| pair[0] == pair[1]) {
+                return Err(collision(pair[0].to_string()));
+            }
+            if sort_keys {
+                pairs.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the subtree at `idx` into `out`, returning its new index.
+    fn copy_node(&self, idx: ValueIdx, out: &mut FlatValue) -> ValueIdx {
+        match &self.nodes[idx as usize] {
+            ValueNode::Array(indices) => {
+                let children = indices
+                    .iter()
+                    .map(|&child| self.copy_node(child, out))
+                    .collect();
+                out.add_node(ValueNode::Array(children))
+            }
+            ValueNode::Object(pairs) => {
+                let children = pairs
+                    .iter()
+                    .map(|(key, child)| (key.clone(), self.copy_node(*child, out)))
+                    .collect();
+                out.add_node(ValueNode::Object(children))
+            }
+            scalar => out.add_node(scalar.clone()),
+        }
+    }
+
     #[inline]
     pub fn set_root(&mut self, idx: ValueIdx) {
         self.root = Some(idx);
@@ -110,4 +346,135 @@ mod tests {
         assert_eq!(fv.len(), 3);
         assert_eq!(fv.root, Some(2));
     }
+
+    #[test]
+    fn test_merged() {
+        let mut base = FlatValue::new();
+        let host = base.add_node(ValueNode::String("localhost".to_string()));
+        let port = base.add_node(ValueNode::Int(5432));
+        let db = base.add_node(ValueNode::Object(vec![
+            ("host".to_string(), host),
+            ("port".to_string(), port),
+        ]));
+        let one = base.add_node(ValueNode::Int(1));
+        let tags = base.add_node(ValueNode::Array(vec![one]));
+        let root = base.add_node(ValueNode::Object(vec![
+            ("db".to_string(), db),
+            ("tags".to_string(), tags),
+        ]));
+        base.set_root(root);
+
+        let mut overlay = FlatValue::new();
+        let host = overlay.add_node(ValueNode::String("prod".to_string()));
+        let db = overlay.add_node(ValueNode::Object(vec![("host".to_string(), host)]));
+        let two = overlay.add_node(ValueNode::Int(2));
+        let tags = overlay.add_node(ValueNode::Array(vec![two]));
+        let debug = overlay.add_node(ValueNode::Bool(false));
+        let root = overlay.add_node(ValueNode::Object(vec![
+            ("db".to_string(), db),
+            ("debug".to_string(), debug),
+            ("tags".to_string(), tags),
+        ]));
+        overlay.set_root(root);
+
+        let merged = base.merged(&overlay, true, true);
+        let ValueNode::Object(top) = &merged.nodes[merged.root.unwrap() as usize] else {
+            panic!("expected object root");
+        };
+        let keys: Vec<&str> = top.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["db", "debug", "tags"]);
+        let ValueNode::Object(db) = &merged.nodes[top[0].1 as usize] else {
+            panic!("expected db object");
+        };
+        assert_eq!(
+            merged.nodes[db[0].1 as usize],
+            ValueNode::String("prod".to_string())
+        );
+        assert_eq!(merged.nodes[db[1].1 as usize], ValueNode::Int(5432));
+        let ValueNode::Array(items) = &merged.nodes[top[2].1 as usize] else {
+            panic!("expected tags array");
+        };
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn test_map_strings() {
+        let mut fv = FlatValue::new();
+        let text = fv.add_node(ValueNode::String("x".to_string()));
+        let one = fv.add_node(ValueNode::Int(1));
+        let root = fv.add_node(ValueNode::Object(vec![
+            ("b".to_string(), text),
+            ("c".to_string(), one),
+        ]));
+        fv.set_root(root);
+
+        // "c" becomes "a", so the keys need re-sorting.
+        let mut mapped = fv.clone();
+        mapped
+            .map_strings(
+                |s| {
+                    if s == "c" {
+                        "a".into()
+                    } else {
+                        s.to_uppercase()
+                    }
+                },
+                true,
+            )
+            .unwrap();
+        assert_eq!(
+            mapped.nodes[root as usize],
+            ValueNode::Object(vec![("B".to_string(), text), ("a".to_string(), one)])
+        );
+        assert_eq!(
+            mapped.nodes[text as usize],
+            ValueNode::String("X".to_string())
+        );
+
+        assert_eq!(
+            fv.map_strings(|_| "k".to_string(), true),
+            Err("k".to_string())
+        );
+    }
+
+    #[test]
+    fn test_dedup_scalars_shares_equal_scalars() {
+        let mut fv = FlatValue::new();
+        let a = fv.add_node(ValueNode::String("enabled".to_string()));
+        let b = fv.add_node(ValueNode::String("enabled".to_string()));
+        let pos = fv.add_node(ValueNode::Float(0.0));
+        let neg = fv.add_node(ValueNode::Float(-0.0));
+        let root = fv.add_node(ValueNode::Array(vec![a, b, pos, neg]));
+        fv.set_root(root);
+
+        let deduped = fv.dedup_scalars();
+        assert_eq!(deduped.len(), 4);
+        let ValueNode::Array(items) = &deduped.nodes[deduped.root.unwrap() as usize] else {
+            panic!("expected array root");
+        };
+        assert_eq!(items[0], items[1]);
+        assert_ne!(items[2], items[3]);
+        let values: Vec<_> = items.iter().map(|&i| &deduped.nodes[i as usize]).collect();
+        let original: Vec<_> = [a, b, pos, neg]
+            .iter()
+            .map(|&i| &fv.nodes[i as usize])
+            .collect();
+        assert_eq!(values, original);
+    }
+
+    #[test]
+    fn test_bytes_round_trip_through_archive() {
+        let mut fv = FlatValue::new();
+        let blob = fv.add_node(ValueNode::Bytes(vec![0, 159, 146, 150, 255]));
+        let text = fv.add_node(ValueNode::String("blob".to_string()));
+        let root = fv.add_node(ValueNode::Array(vec![blob, text]));
+        fv.set_root(root);
+
+        let bytes = rkyv::to_bytes::<_, 256>(&fv).unwrap();
+        let archived = rkyv::check_archived_root::<FlatValue>(&bytes).unwrap();
+        let ArchivedValueNode::Bytes(data) = &archived.nodes[blob as usize] else {
+            panic!("expected bytes node");
+        };
+        assert_eq!(data.as_slice(), &[0, 159, 146, 150, 255]);
+    }
 }
@@ -1,8 +1,16 @@
 """Test suite for snapconfig."""
 
+import collections.abc
+import dataclasses
+import io
+import ipaddress
 import json
+import math
 import os
+import pickle
+import subprocess
 import tempfile
+import warnings
 import pytest
 import snapconfig
 
@@ -34,6 +42,20 @@ def json_file(temp_dir):
     snapconfig.clear_cache(path)
 
 
+@pytest.fixture
+def load_json(temp_dir):
+    """Writes the given data to config.json and loads it with the given options."""
+    path = os.path.join(temp_dir, "config.json")
+
+    def load(data, **kwargs):
+        with open(path, "w", encoding="utf-8") as f:
+            json.dump(data, f, ensure_ascii=False)
+        return snapconfig.load(path, **kwargs)
+
+    yield load
+    snapconfig.clear_cache(path)
+
+
 @pytest.fixture
 def yaml_file(temp_dir):
     path = os.path.join(temp_dir, "test.yaml")
@@ -200,6 +222,46 @@ class TestYAML:
         config = snapconfig.load(yaml_file)
         assert config["nested"]["deep"]["level"] == 3
 
+    def test_yaml_integer_above_i64(self, temp_dir):
+        path = os.path.join(temp_dir, "big.yaml")
+        with open(path, "w") as f:
+            f.write("counter: 18446744073709551615\n")
+        config = snapconfig.load(path)
+        assert config["counter"] == 2**64 - 1
+        assert isinstance(config["counter"], int)
+        assert config.to_json() == '{"counter":18446744073709551615}'
+
+    def test_yaml_integers_beyond_64_bits(self, temp_dir):
+        big = 12345678901234567890123456789012345678901234567890
+        path = os.path.join(temp_dir, "huge.yaml")
+        with open(path, "w") as f:
+            f.write(
+                "u128: 340282366920938463463374607431768211455\n"
+                "i128: -170141183460469231731687303715884105728\n"
+                f"forty: {10**39}\n"
+                f"fifty: [{big}, -{big}]\n"
+                f"quoted: '{big}'\n"
+            )
+        config = snapconfig.load(path)
+        assert config["u128"] == 2**128 - 1
+        assert config["i128"] == -(2**127)
+        assert config["forty"] == 10**39
+        assert config["fifty"] == [big, -big]
+        assert all(type(v) is int for v in config["fifty"])
+        assert config["quoted"] == str(big)
+        assert json.loads(config.to_json())["fifty"] == [big, -big]
+        snapconfig.clear_cache(path)
+
+
+class TestYamlMergeKeys:
+    def test_merge_anchor(self):
+        content = (
+            "defaults: &defaults\n  image: app:1\n  replicas: 1\n"
+            "web:\n  <<: *defaults\n  replicas: 3\n"
+        )
+        result = snapconfig.loads(content, format="yaml")
+        assert result["web"] == {"image": "app:1", "replicas": 3}
+
 
 class TestTOML:
     def test_load_toml(self, toml_file):
@@ -220,6 +282,65 @@ class TestTOML:
         assert config["features"]["list"] == ["a", "b", "c"]
 
 
+class TestProperties:
+    CONTENT = """# database settings
+db.host=localhost
+db.port: 5432
+! legacy comment
+greeting = hello \\
+    world
+name=Jos\\u00e9
+"""
+
+    def test_load_properties_file(self, temp_dir):
+        path = os.path.join(temp_dir, "app.properties")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        config = snapconfig.load(path)
+        assert config["db.host"] == "localhost"
+        assert config["db.port"] == 5432
+        assert config["greeting"] == "hello world"
+        assert config["name"] == "Jos\u00e9"
+        snapconfig.clear_cache(path)
+
+    def test_loads_properties(self):
+        assert snapconfig.loads("a.b.c=1", format="properties") == {"a.b.c": 1}
+
+
+class TestTomlDatetimes:
+    CONTENT = """
+odt = 1979-05-27T07:32:00Z
+offset = 1979-05-27T00:32:00.999999-07:00
+ldt = 1979-05-27T07:32:00
+ld = 1979-05-27
+lt = 07:32:00.5
+"""
+
+    def test_datetime_types(self, temp_dir):
+        import datetime as dt
+
+        path = os.path.join(temp_dir, "times.toml")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        config = snapconfig.load(path)
+        assert config["odt"] == dt.datetime(1979, 5, 27, 7, 32, tzinfo=dt.timezone.utc)
+        assert config["offset"].utcoffset() == dt.timedelta(hours=-7)
+        assert config["offset"].microsecond == 999999
+        assert config["ldt"] == dt.datetime(1979, 5, 27, 7, 32)
+        assert config["ldt"].tzinfo is None
+        assert config["ld"] == dt.date(1979, 5, 27)
+        assert config["lt"] == dt.time(7, 32, 0, 500000)
+        assert config.get("ld") == dt.date(1979, 5, 27)
+        assert '"ld":"1979-05-27"' in config.to_json()
+        snapconfig.clear_cache(path)
+
+    def test_loads_datetime(self):
+        import datetime as dt
+
+        result = snapconfig.loads("when = 2024-01-02", format="toml")
+        assert result["when"] == dt.date(2024, 1, 2)
+
+
 class TestINI:
     def test_load_ini(self, ini_file):
         config = snapconfig.load(ini_file)
@@ -255,6 +376,14 @@ no = no
         assert config["section"]["False"] is False
         snapconfig.clear_cache(path)
 
+    def test_ini_repeated_keys_become_arrays(self):
+        content = "[pool]\nserver = a\nserver = b\nserver = c\n[single]\nserver = a\nname = x\nname = y\n"
+        result = snapconfig.loads(content, format="ini")
+        assert result["pool"] == {"server": ["a", "b", "c"]}
+        assert result["single"] == {"server": "a", "name": ["x", "y"]}
+        with pytest.raises(ValueError, match="Duplicate key"):
+            snapconfig.loads(content, format="ini", strict=True)
+
 
 class TestEnv:
     def test_load_env(self, env_file):
@@ -308,12 +437,85 @@ class TestEnv:
         snapconfig.load_dotenv(env_file, override_existing=True)
         assert os.environ["DATABASE_URL"] == "postgres://localhost:5432/mydb"
 
+    def test_parse_env_empty_values(self):
+        assert snapconfig.parse_env("EMPTY=") == {"EMPTY": ""}
+        assert snapconfig.parse_env("EMPTY=", empty_as_null=True) == {"EMPTY": None}
+
+    def test_load_env_recompiles_for_empty_as_null(self, temp_dir):
+        path = os.path.join(temp_dir, ".env")
+        with open(path, "w") as f:
+            f.write("EMPTY=\n")
+        assert snapconfig.load_env(path)["EMPTY"] == ""
+        assert snapconfig.load_env(path, empty_as_null=True)["EMPTY"] is None
+        assert snapconfig.load_env(path)["EMPTY"] == ""
+        snapconfig.clear_cache(path)
+
+    def test_load_dotenv_empty_as_null(self, temp_dir):
+        path = os.path.join(temp_dir, ".env")
+        with open(path, "w") as f:
+            f.write("SNAP_EMPTY=\nSNAP_SET=1\n")
+        os.environ["SNAP_EMPTY"] = "stale"
+        try:
+            count = snapconfig.load_dotenv(path, override_existing=True, empty_as_null=True)
+            assert count == 1
+            assert "SNAP_EMPTY" not in os.environ
+            assert os.environ["SNAP_SET"] == "1"
+
+            snapconfig.load_dotenv(path, override_existing=True)
+            assert os.environ["SNAP_EMPTY"] == ""
+        finally:
+            os.environ.pop("SNAP_EMPTY", None)
+            os.environ.pop("SNAP_SET", None)
+            snapconfig.clear_cache(path)
+
     def test_parse_env(self):
         result = snapconfig.parse_env("FOO=bar\nNUM=42\nBOOL=true")
         assert result["FOO"] == "bar"
         assert result["NUM"] == 42
         assert result["BOOL"] is True
 
+    def test_parse_env_sections(self):
+        content = "APP=demo\n[db]\nexport HOST='localhost'\nPORT=5432\n[cache]\nTTL=60\n"
+        result = snapconfig.parse_env(content, sections=True)
+        assert result == {
+            "APP": "demo",
+            "db": {"HOST": "localhost", "PORT": 5432},
+            "cache": {"TTL": 60},
+        }
+
+    def test_parse_env_multiline_values(self):
+        content = 'PEM="-----BEGIN-----\nMIIB\n-----END-----"\nNOTE=\'a\nb\'\nESC="x\\ny"\nNEXT=ok\n'
+        result = snapconfig.parse_env(content)
+        assert result == {
+            "PEM": "-----BEGIN-----\nMIIB\n-----END-----",
+            "NOTE": "a\nb",
+            "ESC": "x\ny",
+            "NEXT": "ok",
+        }
+
+    def test_parse_env_interpolate(self):
+        content = "HOST=db\nURL=\"pg://${HOST}:$PORT/$HOST\"\nRAW='${HOST}'\nCOST=\\$5\n"
+        assert snapconfig.parse_env(content)["URL"] == "pg://${HOST}:$PORT/$HOST"
+        result = snapconfig.parse_env(content, interpolate=True)
+        assert result["URL"] == "pg://db:/db"
+        assert result["RAW"] == "${HOST}"
+        assert result["COST"] == "$5"
+
+    def test_load_dotenv_interpolate(self, temp_dir):
+        path = os.path.join(temp_dir, ".env")
+        with open(path, "w") as f:
+            f.write("SNAP_HOME=/srv\nSNAP_LOGS=${SNAP_HOME}/logs\n")
+        try:
+            snapconfig.load_dotenv(path, override_existing=True)
+            assert os.environ["SNAP_LOGS"] == "${SNAP_HOME}/logs"
+            # The cached uninterpolated values are not reused.
+            snapconfig.load_dotenv(path, override_existing=True, interpolate=True)
+            assert os.environ["SNAP_LOGS"] == "/srv/logs"
+        finally:
+            os.environ.pop("SNAP_HOME", None)
+            os.environ.pop("SNAP_LOGS", None)
+            snapconfig.clear_cache(path)
+
 
 class TestCaching:
     def test_cache_created(self, json_file):
@@ -350,6 +552,49 @@ class TestCaching:
         assert info["cache_size"] > 0
         assert "cache_fresh" not in info
 
+    def test_cache_info_many(self, temp_dir):
+        cached = os.path.join(temp_dir, "cached.json")
+        uncached = os.path.join(temp_dir, "uncached.json")
+        missing = os.path.join(temp_dir, "missing.json")
+        for path in (cached, uncached):
+            with open(path, "w") as f:
+                json.dump({"a": 1}, f)
+        snapconfig.load(cached)
+
+        infos = snapconfig.cache_info_many([cached, uncached, missing])
+        assert set(infos) == {cached, uncached, missing}
+        assert infos[cached]["cache_fresh"] is True
+        assert infos[uncached]["source_exists"] is True
+        assert infos[uncached]["cache_exists"] is False
+        assert infos[missing]["source_exists"] is False
+        assert infos[cached] == snapconfig.cache_info(cached)
+        snapconfig.clear_cache(cached)
+
+    def test_cache_info_contents(self, temp_dir):
+        path = os.path.join(temp_dir, "config.yaml")
+        with open(path, "w") as f:
+            f.write("a: 1\nb: [2, 3]\n")
+        info = snapconfig.cache_info(path)
+        assert info["format"] == "yaml"
+        assert info["node_count"] is None
+        assert info["root_type"] is None
+
+        snapconfig.load(path)
+        info = snapconfig.cache_info(path)
+        assert info["node_count"] == 5
+        assert info["root_type"] == "object"
+
+    def test_cache_info_invalid_cache(self, json_file):
+        snapconfig.load(json_file)
+        with open(f"{json_file}.snapconfig", "wb") as f:
+            f.write(b"not a cache")
+        info = snapconfig.cache_info(json_file)
+        assert info["cache_exists"] is True
+        assert info["cache_size"] == 11
+        assert info["node_count"] is None
+        assert info["root_type"] is None
+        assert info["format"] == "json"
+
     def test_clear_cache(self, json_file):
         snapconfig.load(json_file)
         assert os.path.exists(f"{json_file}.snapconfig")
@@ -406,6 +651,104 @@ class TestErrors:
             _ = config["array"][100]
 
 
+class TestConditions:
+    CONTENT = """name: app
+linux_paths:
+  when: os == "linux"
+  home: /home
+plugins:
+  - name: core
+  - name: inotify
+    when: os == "linux"
+"""
+
+    def _compile(self, temp_dir, context):
+        path = os.path.join(temp_dir, "gated.yaml")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        cache = os.path.join(temp_dir, "gated.snapconfig")
+        snapconfig.compile(path, cache, eval_conditions=True, context=context)
+        return snapconfig.load_compiled(cache).to_dict()
+
+    def test_matching_context_keeps_block(self, temp_dir):
+        result = self._compile(temp_dir, {"os": "linux"})
+        assert result["linux_paths"] == {"home": "/home"}
+        assert result["plugins"] == [{"name": "core"}, {"name": "inotify"}]
+
+    def test_other_context_drops_block(self, temp_dir):
+        result = self._compile(temp_dir, {"os": "darwin"})
+        assert "linux_paths" not in result
+        assert result["plugins"] == [{"name": "core"}]
+
+    def test_bad_predicate_raises(self, temp_dir):
+        path = os.path.join(temp_dir, "bad.yaml")
+        with open(path, "w") as f:
+            f.write("block:\n  when: os ~ linux\n")
+        with pytest.raises(ValueError, match="Bad condition"):
+            snapconfig.compile(path, eval_conditions=True, context={"os": "linux"})
+
+    def test_disabled_by_default(self, temp_dir):
+        path = os.path.join(temp_dir, "plain.yaml")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        cache = snapconfig.compile(path)
+        assert snapconfig.load_compiled(cache)["linux_paths"]["when"] == 'os == "linux"'
+
+    def test_load_rebuilds_with_compiled_context(self, temp_dir):
+        path = os.path.join(temp_dir, "gated.yaml")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        snapconfig.compile(path, eval_conditions=True, context={"os": "darwin"})
+
+        with open(path, "a") as f:
+            f.write("extra: 1\n")
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        os.utime(path, (future, future))
+        config = snapconfig.load(path)
+        assert config["extra"] == 1
+        assert "linux_paths" not in config
+        assert config["plugins"] == [{"name": "core"}]
+        snapconfig.clear_cache(path)
+
+    def test_context_entries_must_fit_one_line(self, temp_dir):
+        path = os.path.join(temp_dir, "plain.yaml")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        with pytest.raises(ValueError, match="Invalid context entry"):
+            snapconfig.compile(path, eval_conditions=True, context={"os": "a\nb"})
+
+
+class TestCompileStream:
+    def test_streams_compiled_caches(self, temp_dir):
+        sources = iter([
+            ("app", '{"name": "app", "port": 80}', "json"),
+            ("db", "host: localhost\n", "yaml"),
+            ("env", "DEBUG=true\n", "env"),
+        ])
+        results = dict(snapconfig.compile_stream(sources))
+        assert list(results) == ["app", "db", "env"]
+
+        for name, data in results.items():
+            assert isinstance(data, bytes)
+            cache = os.path.join(temp_dir, f"{name}.snapconfig")
+            with open(cache, "wb") as f:
+                f.write(data)
+            results[name] = snapconfig.load_compiled(cache).to_dict()
+        assert results["app"] == {"name": "app", "port": 80}
+        assert results["db"] == {"host": "localhost"}
+        assert results["env"] == {"DEBUG": True}
+
+    def test_errors_are_yielded_per_item(self):
+        stream = snapconfig.compile_stream([("bad", "{oops", "json"), ("good", "[1]", "json")])
+        name, error = next(stream)
+        assert name == "bad"
+        assert isinstance(error, ValueError)
+        name, data = next(stream)
+        assert name == "good" and isinstance(data, bytes)
+        with pytest.raises(StopIteration):
+            next(stream)
+
+
 class TestCompile:
     def test_compile(self, json_file, temp_dir):
         output = os.path.join(temp_dir, "compiled.snapconfig")
@@ -436,6 +779,109 @@ class TestCompile:
         assert config.source_path is None
 
 
+class TestCompressedCache:
+    def test_roundtrip(self, temp_dir):
+        path = os.path.join(temp_dir, "big.json")
+        with open(path, "w") as f:
+            json.dump({"items": [{"name": "item", "value": i % 7} for i in range(2000)]}, f)
+        plain = snapconfig.compile(path, os.path.join(temp_dir, "plain.snapconfig"))
+        packed = snapconfig.compile(path, os.path.join(temp_dir, "packed.snapconfig"), compress=True)
+        assert os.path.getsize(packed) < os.path.getsize(plain)
+
+        config = snapconfig.load_compiled(packed)
+        assert config["items"][1999]["value"] == 1999 % 7
+        assert config.to_dict() == snapconfig.load_compiled(plain).to_dict()
+
+    def test_rebuild_keeps_compression(self, temp_dir):
+        path = os.path.join(temp_dir, "app.json")
+        with open(path, "w") as f:
+            f.write('{"v": 1}')
+        cache = snapconfig.compile(path, compress=True)
+        with open(path, "w") as f:
+            f.write('{"v": 2}')
+        assert snapconfig.load(path, force_recompile=True)["v"] == 2
+        with open(cache, "rb") as f:
+            flags = int.from_bytes(f.read(16)[12:16], "little")
+        assert flags & 2
+        snapconfig.clear_cache(path)
+
+    def test_not_writable(self, json_file):
+        cache = snapconfig.compile(json_file, compress=True)
+        with pytest.raises(ValueError, match="Compressed caches cannot be opened writable"):
+            snapconfig.load_compiled(cache, writable=True)
+        snapconfig.clear_cache(json_file)
+
+
+class TestDedupCache:
+    def test_same_values_smaller_cache(self, temp_dir):
+        path = os.path.join(temp_dir, "flags.json")
+        data = {"flags": {f"feature_{i}": "enabled" if i % 3 else "disabled" for i in range(1000)},
+                "weights": [0.0, -0.0, 1, 1, True, None, None]}
+        with open(path, "w") as f:
+            json.dump(data, f)
+        plain = snapconfig.compile(path, os.path.join(temp_dir, "plain.snapconfig"))
+        deduped = snapconfig.compile(path, os.path.join(temp_dir, "dedup.snapconfig"), dedup=True)
+        assert os.path.getsize(deduped) < os.path.getsize(plain)
+
+        config = snapconfig.load_compiled(deduped)
+        assert config.to_dict() == snapconfig.load_compiled(plain).to_dict() == data
+        assert str(config["weights"][1]) == "-0.0"
+
+    def test_not_writable(self, json_file):
+        cache = snapconfig.compile(json_file, dedup=True)
+        with pytest.raises(ValueError, match="Deduplicated caches cannot be opened writable"):
+            snapconfig.load_compiled(cache, writable=True)
+        snapconfig.clear_cache(json_file)
+
+
+class TestStrict:
+    def test_duplicate_json_keys(self, temp_dir):
+        path = os.path.join(temp_dir, "dup.json")
+        with open(path, "w") as f:
+            f.write('{"port": 1, "port": 2}')
+        snapconfig.compile(path)
+        assert snapconfig.load(path)["port"] == 2
+        with pytest.raises(ValueError, match="Duplicate key: 'port'"):
+            snapconfig.compile(path, strict=True)
+        # Individual flags override the preset.
+        snapconfig.compile(path, strict=True, reject_duplicate_keys=False)
+        snapconfig.clear_cache(path)
+
+    def test_load_rebuilds_with_compiled_checks(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write('{"port": 1, "ratio": 0.5}')
+        snapconfig.compile(path, strict=True, reject_non_finite=False, allow_nan=True)
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        with open(path, "w") as f:
+            f.write('{"port": 1, "port": 2, "ratio": NaN}')
+        os.utime(path, (future, future))
+        with pytest.raises(ValueError, match="Duplicate key: 'port'"):
+            snapconfig.load(path)
+        with open(path, "w") as f:
+            f.write('{"port": 2, "ratio": NaN}')
+        os.utime(path, (future + 10, future + 10))
+        assert math.isnan(snapconfig.load(path)["ratio"])
+        snapconfig.clear_cache(path)
+
+    def test_loads_checks(self):
+        assert snapconfig.loads("x: .nan", format="yaml") is not None
+        with pytest.raises(ValueError, match="Non-finite float: 'x'"):
+            snapconfig.loads("x: .nan", format="yaml", strict=True)
+        with pytest.raises(ValueError, match="Number overflow"):
+            snapconfig.loads("N=123456789012345678901", format="env", reject_overflow=True)
+        assert snapconfig.loads('{"a": 1, "a": 2}', reject_non_finite=True) == {"a": 2}
+
+    def test_unknown_extension(self, temp_dir):
+        path = os.path.join(temp_dir, "settings.txt")
+        with open(path, "w") as f:
+            f.write("A=1\n")
+        snapconfig.compile(path)
+        with pytest.raises(ValueError, match="Unknown format"):
+            snapconfig.compile(path, strict=True)
+        snapconfig.clear_cache(path)
+
+
 class TestPerformance:
     def test_cached_load_is_fast(self, json_file):
         import time
@@ -481,6 +927,63 @@ class TestLoads:
         assert config["BOOL"] is True
 
 
+class TestLoadFromTar:
+    def write_bundle(self, temp_dir, entries):
+        import io
+        import tarfile
+
+        buffer = io.BytesIO()
+        with tarfile.open(fileobj=buffer, mode="w:gz") as tar:
+            for name, text in entries.items():
+                data = text.encode()
+                info = tarfile.TarInfo(name)
+                info.size = len(data)
+                tar.addfile(info, io.BytesIO(data))
+        path = os.path.join(temp_dir, "config.tar.gz")
+        with open(path, "wb") as f:
+            f.write(buffer.getvalue())
+        return path
+
+    def test_reads_yaml_entry(self, temp_dir):
+        if not hasattr(snapconfig, "load_from_tar"):
+            pytest.skip("built without the archive feature")
+        bundle = self.write_bundle(temp_dir, {"README": "hi", "./conf/app.yaml": "db:\n  port: 5432\n"})
+        config = snapconfig.load_from_tar(bundle, "conf/app.yaml")
+        assert config.get("db.port") == 5432
+        assert config.cache_path == ""
+
+        config = snapconfig.load_from_tar(bundle, "README", format="env")
+        assert config.to_dict() == {}
+
+    def test_missing_entry(self, temp_dir):
+        if not hasattr(snapconfig, "load_from_tar"):
+            pytest.skip("built without the archive feature")
+        bundle = self.write_bundle(temp_dir, {"app.yaml": "a: 1\n"})
+        with pytest.raises(IOError, match="File not found: other.yaml"):
+            snapconfig.load_from_tar(bundle, "other.yaml")
+
+
+class TestLoadsBytes:
+    def test_json_with_bom(self):
+        data = b"\xef\xbb\xbf" + b'{"key": "value", "num": 42}'
+        assert snapconfig.loads_bytes(data) == {"key": "value", "num": 42}
+
+    def test_other_formats(self):
+        assert snapconfig.loads_bytes(b"key: value\n", format="yaml") == {"key": "value"}
+        assert snapconfig.loads_bytes("NAME=caf\u00e9".encode(), format="env") == {"NAME": "caf\u00e9"}
+
+    def test_invalid_utf8_raises(self):
+        with pytest.raises(ValueError, match="UTF-8"):
+            snapconfig.loads_bytes(b'{"key": "\xff"}')
+
+    def test_takes_loads_options(self):
+        assert math.isnan(snapconfig.loads_bytes(b"[NaN]", "json", allow_nan=True)[0])
+        assert snapconfig.loads_bytes(b"a: 1\n---\na: 2\n", "yaml", multi=True) == [{"a": 1}, {"a": 2}]
+        assert snapconfig.loads_bytes(b"a;b\n1;2\n", "csv", csv_delimiter=";") == [{"a": 1, "b": 2}]
+        with pytest.raises(ValueError, match="max_keys"):
+            snapconfig.loads_bytes(b'{"a": 1, "b": 2}', max_keys=1)
+
+
 class TestGetDotted:
     def test_get_dotted_path(self, json_file):
         config = snapconfig.load(json_file)
@@ -488,10 +991,32 @@ class TestGetDotted:
         assert config.get("nested.key") == "value"
         assert config.get("nested.deep.level") == 3
 
-    def test_get_raises_on_missing(self, json_file):
+    def test_get_missing_returns_none(self, json_file):
         config = snapconfig.load(json_file)
-        with pytest.raises(KeyError):
-            config.get("nonexistent")
+        assert config.get("nonexistent") is None
+        assert config.get("nested.nonexistent.deeper") is None
+
+    def test_get_default_positional_returned_as_is(self, json_file):
+        config = snapconfig.load(json_file)
+        sentinel = object()
+        assert config.get("server.timeout", 30) == 30
+        assert config.get("nonexistent", sentinel) is sentinel
+
+    def test_get_through_scalar_raises(self, json_file):
+        config = snapconfig.load(json_file)
+        with pytest.raises(TypeError):
+            config.get("string.child", default="fallback")
+
+    def test_traverse_error_names_segment_and_type(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"a": {"b": 1}, "items": [True]}, f)
+        config = snapconfig.load(path)
+        with pytest.raises(TypeError, match=r"^Cannot traverse into 'b' \(int\) while resolving 'a\.b\.c'$"):
+            config.get("a.b.c")
+        with pytest.raises(TypeError, match=r"into '\[0\]' \(bool\) while resolving 'items\[0\]\.x'"):
+            config.get("items[0].x")
+        snapconfig.clear_cache(path)
 
     def test_get_with_default(self, json_file):
         config = snapconfig.load(json_file)
@@ -510,6 +1035,240 @@ class TestGetDotted:
         assert config.get("array.999", default="missing") == "missing"
 
 
+class TestCaseInsensitiveGet:
+    def test_matches_any_case_when_asked(self, temp_dir):
+        path = os.path.join(temp_dir, "mixed.yaml")
+        with open(path, "w") as f:
+            f.write("Database:\n  Host: db\n  host: exact\n  PORT: 5432\n")
+        config = snapconfig.load(path)
+        assert config.get("database.port") is None
+        assert config.get("database.port", case_insensitive=True) == 5432
+        # An exact match wins over a case-folded one.
+        assert config.get("Database.host", case_insensitive=True) == "exact"
+        assert config.get("DATABASE.HOST", case_insensitive=True) == "db"
+        assert config.get("database.user", "x", case_insensitive=True) == "x"
+        snapconfig.clear_cache(path)
+
+
+class TestPatternDefaults:
+    def test_missing_key_uses_first_matching_pattern(self, temp_dir):
+        path = os.path.join(temp_dir, "flags.json")
+        with open(path, "w") as f:
+            f.write('{"feature_on": true, "timeout": 5}')
+        config = snapconfig.load(path)
+        patterns = {"feature_*": False, "*": "fallback"}
+        assert config.get_with_pattern_default("feature_x", patterns) is False
+        assert config.get_with_pattern_default("retries", patterns) == "fallback"
+        assert config.get_with_pattern_default("retries", {"feature_?": 0}) is None
+        snapconfig.clear_cache(path)
+
+    def test_present_key_ignores_patterns(self, temp_dir):
+        path = os.path.join(temp_dir, "flags.json")
+        with open(path, "w") as f:
+            f.write('{"feature_on": true}')
+        config = snapconfig.load(path)
+        assert config.get_with_pattern_default("feature_on", {"feature_*": False}) is True
+        snapconfig.clear_cache(path)
+
+
+class TestTypedGetters:
+    def test_matching_types(self, json_file):
+        config = snapconfig.load(json_file)
+        assert config.get_int("integer") == 42
+        assert config.get_float("float") == 3.14
+        assert config.get_bool("boolean") is True
+        assert config.get_str("nested.key") == "value"
+
+    def test_mismatched_types_raise(self, json_file):
+        config = snapconfig.load(json_file)
+        with pytest.raises(TypeError, match="expected an int"):
+            config.get_int("string")
+        with pytest.raises(TypeError, match="expected a string"):
+            config.get_str("integer")
+        with pytest.raises(TypeError):
+            config.get_bool("integer")
+        with pytest.raises(KeyError):
+            config.get_float("missing")
+
+    def test_get_int_rejects_integral_float(self, temp_dir):
+        path = os.path.join(temp_dir, "num.json")
+        with open(path, "w") as f:
+            f.write('{"workers": 4.0}')
+        config = snapconfig.load(path)
+        with pytest.raises(TypeError, match="is a float"):
+            config.get_int("workers")
+        assert config.get_float("workers") == 4.0
+        snapconfig.clear_cache(path)
+
+    def test_get_float_widens_ints(self, temp_dir):
+        path = os.path.join(temp_dir, "num.json")
+        with open(path, "w") as f:
+            f.write('{"x": 2, "s": "2"}')
+        config = snapconfig.load(path)
+        value = config.get_float("x")
+        assert value == 2.0 and type(value) is float
+        with pytest.raises(TypeError, match="'s' is a string, expected a float"):
+            config.get_float("s")
+        with pytest.raises(TypeError, match="'x' is an int, expected a string"):
+            config.get_str("x")
+        snapconfig.clear_cache(path)
+
+
+@dataclasses.dataclass(frozen=True)
+class _Database:
+    host: str
+    port: int
+    timeout: float = 5.0
+
+
+@dataclasses.dataclass(frozen=True)
+class _Server:
+    name: str
+
+
+@dataclasses.dataclass(frozen=True)
+class _App:
+    name: str
+    database: _Database
+    servers: list = dataclasses.field(default_factory=list)
+
+
+@dataclasses.dataclass(frozen=True)
+class _Flags:
+    enabled: "bool"
+    retries: "int"
+
+
+class TestIntoDataclasses:
+    def write(self, temp_dir, data):
+        path = os.path.join(temp_dir, "app.json")
+        with open(path, "w") as f:
+            json.dump(data, f)
+        return path
+
+    def test_nested_tree(self, temp_dir):
+        path = self.write(temp_dir, {
+            "name": "api",
+            "database": {"host": "db", "port": 5432, "timeout": 3, "extra": True},
+            "servers": [{"name": "a"}, {"name": "b"}],
+        })
+        config = snapconfig.load(path)
+        app = config.into_dataclasses({"": _App, "database": _Database, "servers": _Server})
+        assert app == _App("api", _Database("db", 5432, 3.0), [_Server("a"), _Server("b")])
+        assert isinstance(app.database.timeout, float)
+        snapconfig.clear_cache(path)
+
+    def test_missing_required_field_names_path(self, temp_dir):
+        path = self.write(temp_dir, {"name": "api", "database": {"host": "db"}})
+        config = snapconfig.load(path)
+        with pytest.raises(ValueError, match="Missing required field 'database.port' for _Database"):
+            config.into_dataclasses({"": _App, "database": _Database})
+        snapconfig.clear_cache(path)
+
+    def test_scalar_strings_are_coerced(self, temp_dir):
+        path = self.write(
+            temp_dir, {"name": "api", "database": {"host": "db", "port": " 5432", "timeout": "2.5"}}
+        )
+        app = snapconfig.load(path).into_dataclasses({"": _App, "database": _Database})
+        assert app.database == _Database(host="db", port=5432, timeout=2.5)
+        snapconfig.clear_cache(path)
+
+    def test_string_annotations_are_resolved(self, temp_dir):
+        path = self.write(temp_dir, {"enabled": "yes", "retries": "3"})
+        flags = snapconfig.load(path).into_dataclasses({"": _Flags})
+        assert flags == _Flags(enabled=True, retries=3)
+        snapconfig.clear_cache(path)
+
+    def test_scalar_type_mismatch(self, temp_dir):
+        path = self.write(temp_dir, {"name": "api", "database": {"host": "db", "port": "db-port"}})
+        config = snapconfig.load(path)
+        with pytest.raises(TypeError, match="'database.port' is str, expected int"):
+            config.into_dataclasses({"": _App, "database": _Database})
+        snapconfig.clear_cache(path)
+        path = self.write(temp_dir, {"name": "api", "database": {"host": 1, "port": 5432}})
+        config = snapconfig.load(path)
+        with pytest.raises(TypeError, match="'database.host' is int, expected str"):
+            config.into_dataclasses({"": _App, "database": _Database})
+        with pytest.raises(ValueError, match="root dataclass"):
+            config.into_dataclasses({"database": _Database})
+        snapconfig.clear_cache(path)
+
+
+class TestDiff:
+    def load_pair(self, temp_dir, left, right):
+        configs = []
+        for name, data in (("left.json", left), ("right.json", right)):
+            path = os.path.join(temp_dir, name)
+            with open(path, "w") as f:
+                json.dump(data, f)
+            configs.append(snapconfig.load(path))
+        return configs
+
+    def test_ignored_paths_compare_equal(self, temp_dir):
+        a, b = self.load_pair(
+            temp_dir,
+            {"name": "api", "meta": {"built_at": 1}, "hosts": [{"id": 1, "seen": 5}]},
+            {"name": "api", "meta": {"built_at": 2, "by": "ci"}, "hosts": [{"id": 1, "seen": 9}]},
+        )
+        assert list(a.diff(b)) == ["hosts.0.seen", "meta.built_at", "meta.by"]
+        assert not a.equals(b)
+        assert a.diff(b, ignore=["meta", "hosts.0.seen"]) == {}
+        assert a.equals(b, ignore=["meta", "hosts.0.seen"])
+        assert a.equals(a)
+
+    def test_other_paths_still_differ(self, temp_dir):
+        a, b = self.load_pair(
+            temp_dir,
+            {"name": "api", "meta": {"built_at": 1}, "tags": ["x"]},
+            {"name": "web", "meta": {"built_at": 2}, "tags": ["x", "y"]},
+        )
+        assert a.diff(b, ignore=["meta"]) == {"name": ("api", "web"), "tags.1": (None, "y")}
+        assert not a.equals(b, ignore=["meta"])
+
+
+class TestRequireAll:
+    def test_all_pass(self, json_file):
+        config = snapconfig.load(json_file)
+        spec = {"string": "str", "integer": "int", "float": "float", "nested.deep": "dict", "array": "list"}
+        assert config.require_all(spec) is None
+
+    def test_missing_path(self, json_file):
+        config = snapconfig.load(json_file)
+        with pytest.raises(ValueError, match="Missing required path 'nested.absent'"):
+            config.require_all({"integer": "int", "nested.absent": "str", "string": "int"})
+
+    def test_mistyped_path(self, json_file):
+        config = snapconfig.load(json_file)
+        with pytest.raises(ValueError, match="'nested.deep.level' .* expected str"):
+            config.require_all({"nested.deep.level": "str"})
+        with pytest.raises(ValueError, match="Unknown type name"):
+            config.require_all({"integer": "integer"})
+
+    def test_bracket_paths(self, load_json):
+        config = load_json({"s": {"x": [{"h": 1}, {"h": "b"}]}, "m": [[1, 2]]})
+        assert config.require_all({"s.x[0].h": "int", "s.x[1].h": "str", "m[0][1]": "int"}) is None
+        with pytest.raises(ValueError, match=r"Missing required path 's.x\[2\].h'"):
+            config.require_all({"s.x[2].h": "int"})
+
+
+class TestToDictMaxDepth:
+    def test_summarizes_beyond_depth(self, json_file):
+        config = snapconfig.load(json_file)
+        shallow = config.to_dict(max_depth=1)
+        assert shallow["string"] == "hello"
+        assert shallow["array"] == "<array: 3 items>"
+        assert shallow["nested"] == "<object: 2 keys>"
+
+        deeper = config.to_dict(max_depth=2)
+        assert deeper["array"] == [1, 2, 3]
+        assert deeper["nested"] == {"key": "value", "deep": "<object: 1 key>"}
+
+    def test_default_recurses_fully(self, json_file):
+        config = snapconfig.load(json_file)
+        assert config.to_dict()["nested"]["deep"] == {"level": 3}
+        assert config.to_dict(max_depth=0) == "<object: 7 keys>"
+
+
 class TestIteration:
     def test_iterate_keys(self, json_file):
         config = snapconfig.load(json_file)
@@ -523,12 +1282,56 @@ class TestIteration:
         config = snapconfig.load(json_file)
         assert set(iter(config)) == set(config.keys())
 
+    def test_iterate_array_root_yields_elements(self, temp_dir):
+        path = os.path.join(temp_dir, "array.json")
+        with open(path, "w") as f:
+            json.dump([1, "two", {"three": 3}], f)
+        assert list(snapconfig.load(path)) == [1, "two", {"three": 3}]
+
+    def test_iterate_scalar_root_raises(self, temp_dir):
+        path = os.path.join(temp_dir, "scalar.json")
+        with open(path, "w") as f:
+            f.write('"just a string"')
+        with pytest.raises(TypeError):
+            iter(snapconfig.load(path))
+
     def test_iterate_with_values(self, json_file):
         config = snapconfig.load(json_file)
         items = {key: config[key] for key in config.keys()}
         assert items["string"] == "hello"
         assert items["integer"] == 42
 
+    def test_items(self, json_file):
+        config = snapconfig.load(json_file)
+        items = config.items()
+        assert [k for k, _ in items] == config.keys()
+        assert dict(items) == config.to_dict()
+        for key, value in config.items():
+            assert config[key] == value
+
+    def test_items_requires_object(self, temp_dir):
+        path = os.path.join(temp_dir, "list.json")
+        with open(path, "w") as f:
+            json.dump([1, 2], f)
+        with pytest.raises(TypeError):
+            snapconfig.load(path).items()
+
+    def test_values_match_keys_order(self, json_file):
+        config = snapconfig.load(json_file)
+        assert config.values() == [config[key] for key in config.keys()]
+
+    def test_values_on_array_and_scalar(self, temp_dir):
+        path = os.path.join(temp_dir, "list.json")
+        with open(path, "w") as f:
+            json.dump([1, {"a": 2}], f)
+        assert snapconfig.load(path).values() == [1, {"a": 2}]
+
+        scalar = os.path.join(temp_dir, "scalar.json")
+        with open(scalar, "w") as f:
+            f.write("42")
+        with pytest.raises(TypeError):
+            snapconfig.load(scalar).values()
+
 
 class TestEdgeCases:
     def test_empty_object(self, temp_dir):
@@ -609,6 +1412,23 @@ class TestEdgeCases:
 
 
 class TestInvalidCache:
+    def test_old_version_asks_for_recompile(self, json_file):
+        cache = snapconfig.compile(json_file)
+        with open(cache, "r+b") as f:
+            f.seek(8)
+            f.write((1).to_bytes(4, "little"))
+        with pytest.raises(ValueError, match="Unsupported cache version: 1.*recompile"):
+            snapconfig.load_compiled(cache)
+        # load() with the source around rebuilds it transparently.
+        assert snapconfig.load(json_file)["integer"] == 42
+
+    def test_foreign_file_asks_for_recompile(self, temp_dir):
+        cache = os.path.join(temp_dir, "foreign.snapconfig")
+        with open(cache, "wb") as f:
+            f.write(b"PK\x03\x04" + b"\x00" * 60)
+        with pytest.raises(ValueError, match="Not a snapconfig cache"):
+            snapconfig.load_compiled(cache)
+
     def test_empty_cache_raises(self, temp_dir):
         cache = os.path.join(temp_dir, "empty.snapconfig")
         with open(cache, "wb") as f:
@@ -624,12 +1444,2077 @@ class TestInvalidCache:
             snapconfig.load_compiled(cache)
 
     def test_corrupted_magic_cache_raises(self, temp_dir):
+        source = os.path.join(temp_dir, "fresh.json")
+        with open(source, "w") as f:
+            f.write("{}")
+        with open(snapconfig.compile(source), "rb") as f:
+            version = f.read(12)[8:]  # the current cache version
         cache = os.path.join(temp_dir, "corrupted_magic.snapconfig")
         # Valid header + invalid payload
         with open(cache, "wb") as f:
             f.write(b"SNAPCFG\x00")  # magic
-            f.write((1).to_bytes(4, "little"))  # version
-            f.write(b"\x00" * 4)  # reserved
+            f.write(version)
+            f.write(b"\x00" * 4)  # flags
+            f.write(b"\x00" * 16)  # source hash + reserved
             f.write(b"\x00" * 64)  # payload (invalid rkyv data)
-        with pytest.raises(ValueError):
+        with pytest.raises(ValueError, match="Validation failed"):
             snapconfig.load_compiled(cache)
+
+
+class TestPreserveOrder:
+    def test_json_round_trip_keeps_key_order(self, temp_dir):
+        path = os.path.join(temp_dir, "ordered.json")
+        original = '{"zeta": 1, "alpha": {"y": true, "b": null}, "mid": [1.5, "x"]}'
+        with open(path, "w") as f:
+            f.write(original)
+        cache = os.path.join(temp_dir, "ordered.snapconfig")
+        snapconfig.compile(path, cache, preserve_order=True)
+        config = snapconfig.load_compiled(cache)
+        assert config.to_json() == original.replace(", ", ",").replace(": ", ":")
+        assert list(config) == ["zeta", "alpha", "mid"]
+        assert config["alpha"]["y"] is True
+        assert config.get("alpha.b") is None
+        assert "mid" in config
+
+    def test_default_sorts_keys(self, temp_dir):
+        path = os.path.join(temp_dir, "sorted.json")
+        with open(path, "w") as f:
+            f.write('{"zeta": 1, "alpha": 2}')
+        config = snapconfig.load(path)
+        assert config.to_json() == '{"alpha":2,"zeta":1}'
+        snapconfig.clear_cache(path)
+
+    def test_to_dict_ordered_with_max_depth(self, temp_dir):
+        from collections import OrderedDict
+
+        path = os.path.join(temp_dir, "ordered.json")
+        with open(path, "w") as f:
+            f.write('{"zeta": {"b": {"c": 1}}, "alpha": 1}')
+        config = snapconfig.load(path, preserve_order=True)
+        d = config.to_dict(ordered=True, max_depth=2)
+        assert isinstance(d["zeta"], OrderedDict)
+        assert d == {"zeta": {"b": "<object: 1 key>"}, "alpha": 1}
+        snapconfig.clear_cache(path)
+
+    def test_loads_preserve_order(self):
+        result = snapconfig.loads('{"b": 1, "a": 2}', format="json", preserve_order=True)
+        assert list(result) == ["b", "a"]
+
+    @pytest.mark.parametrize(
+        "fmt,content,expected",
+        [
+            ("yaml", "b: 1\na:\n  d: 1\n  c: 2\n", ["b", "a"]),
+            ("toml", "b = 1\n[a]\nd = 1\nc = 2\n", ["b", "a"]),
+            ("json5", "{b: 1, a: {d: 1, c: 2}}", ["b", "a"]),
+            ("ini", "[b]\nx = 1\n[a]\nd = 1\nc = 2\n", ["default", "b", "a"]),
+            ("env", "B=1\nA=2\nB=3\n", ["B", "A"]),
+            ("properties", "b=1\na=2\n", ["b", "a"]),
+            ("ron", "(b: 1, a: (d: 1, c: 2))", ["b", "a"]),
+        ],
+    )
+    def test_loads_preserve_order_all_formats(self, fmt, content, expected):
+        result = snapconfig.loads(content, format=fmt, preserve_order=True)
+        assert list(result) == expected
+        if isinstance(result.get("a"), dict):
+            assert list(result["a"]) == ["d", "c"]
+        assert list(snapconfig.loads(content, format=fmt)) == sorted(expected)
+
+    def test_yaml_cache_lookups(self, temp_dir):
+        path = os.path.join(temp_dir, "ordered.yaml")
+        with open(path, "w") as f:
+            f.write("zeta: 1\nalpha:\n  y: 2\n  b: 3\n")
+        snapconfig.compile(path, preserve_order=True)
+        config = snapconfig.load(path, preserve_order=True)
+        assert list(config) == ["zeta", "alpha"]
+        assert config.get("alpha.b") == 3
+        assert config["zeta"] == 1
+        snapconfig.clear_cache(path)
+
+    def test_to_dict_ordered(self, temp_dir):
+        from collections import OrderedDict
+
+        path = os.path.join(temp_dir, "ordered.json")
+        with open(path, "w") as f:
+            f.write('{"zeta": 1, "alpha": {"y": 2, "b": 3}, "mid": [{"q": 1, "c": 2}]}')
+        config = snapconfig.load(path, preserve_order=True)
+        d = config.to_dict(ordered=True)
+        assert isinstance(d, OrderedDict)
+        assert list(d) == ["zeta", "alpha", "mid"]
+        assert isinstance(d["alpha"], OrderedDict)
+        assert list(d["alpha"]) == ["y", "b"]
+        assert list(d["mid"][0]) == ["q", "c"]
+
+        plain = config.to_dict()
+        assert type(plain) is dict
+        snapconfig.clear_cache(path)
+
+    def test_load_recompiles_when_order_setting_changes(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write('{"zeta": 1, "alpha": 2}')
+        assert list(snapconfig.load(path)) == ["alpha", "zeta"]
+        assert list(snapconfig.load(path, preserve_order=True)) == ["zeta", "alpha"]
+        assert list(snapconfig.load(path)) == ["alpha", "zeta"]
+        snapconfig.clear_cache(path)
+
+    def test_to_dict_ordered_without_order_metadata(self, json_file):
+        config = snapconfig.load(json_file)
+        assert type(config.to_dict(ordered=True)) is dict
+
+
+class TestKeyWhitespace:
+    def test_spaced_key_trimmed(self):
+        config = snapconfig.loads("[section]\n  key = value\n", format="ini")
+        assert config["section"] == {"key": "value"}
+        env = snapconfig.loads("  KEY = value\n", format="env")
+        assert env == {"KEY": "value"}
+
+    def test_untrimmed_key_kept(self):
+        config = snapconfig.loads("[section]\n  key = value\n", format="ini", trim_keys=False)
+        assert config["section"] == {"  key": "value"}
+
+    def test_strict_keys_rejects_spaced_key(self, temp_dir):
+        with pytest.raises(ValueError, match="Invalid key"):
+            snapconfig.loads("[section]\n  key = value\n", format="ini", strict_keys=True)
+        path = os.path.join(temp_dir, "spaced.env")
+        with open(path, "w") as f:
+            f.write("  KEY=value\n")
+        with pytest.raises(ValueError):
+            snapconfig.compile(path, strict_keys=True)
+
+    def test_load_rebuilds_with_compiled_key_options(self, temp_dir):
+        strict = os.path.join(temp_dir, "strict.env")
+        untrimmed = os.path.join(temp_dir, "untrimmed.ini")
+        with open(strict, "w") as f:
+            f.write("KEY=value\n")
+        with open(untrimmed, "w") as f:
+            f.write("[s]\n  key = value\n")
+        snapconfig.compile(strict, strict_keys=True)
+        snapconfig.compile(untrimmed, trim_keys=False)
+        with open(strict, "w") as f:
+            f.write("  KEY=value\n")
+        with open(untrimmed, "w") as f:
+            f.write("[s]\n  key = other\n")
+        for path in (strict, untrimmed):
+            future = os.path.getmtime(path + ".snapconfig") + 10
+            os.utime(path, (future, future))
+        with pytest.raises(ValueError, match="Invalid key"):
+            snapconfig.load(strict)
+        assert snapconfig.load(untrimmed)["s"] == {"  key": "other"}
+        snapconfig.clear_cache(strict)
+        snapconfig.clear_cache(untrimmed)
+
+
+class TestSetScalar:
+    def _compile(self, temp_dir):
+        path = os.path.join(temp_dir, "counters.json")
+        with open(path, "w") as f:
+            json.dump({"hits": 1, "ratio": 0.5, "enabled": False, "name": "svc"}, f)
+        cache = os.path.join(temp_dir, "counters.snapconfig")
+        snapconfig.compile(path, cache)
+        return cache
+
+    def test_increment_int_persists(self, temp_dir):
+        cache = self._compile(temp_dir)
+        config = snapconfig.load_compiled(cache, writable=True)
+        config.set_scalar("hits", config["hits"] + 1)
+        config.set_scalar("ratio", 0.75)
+        config.set_scalar("enabled", True)
+        del config
+
+        reopened = snapconfig.load_compiled(cache)
+        assert reopened["hits"] == 2
+        assert reopened["ratio"] == 0.75
+        assert reopened["enabled"] is True
+
+    def test_set_scalar_errors(self, temp_dir):
+        cache = self._compile(temp_dir)
+        readonly = snapconfig.load_compiled(cache)
+        with pytest.raises(ValueError):
+            readonly.set_scalar("hits", 5)
+
+        config = snapconfig.load_compiled(cache, writable=True)
+        with pytest.raises(TypeError):
+            config.set_scalar("name", 5)
+        with pytest.raises(TypeError):
+            config.set_scalar("hits", "five")
+        with pytest.raises(KeyError):
+            config.set_scalar("missing", 1)
+
+
+class TestNdarray:
+    def test_int_array(self, temp_dir):
+        np = pytest.importorskip("numpy")
+        path = os.path.join(temp_dir, "arrays.json")
+        with open(path, "w") as f:
+            json.dump({"ints": [1, 2, 3], "mixed": [1, 2.5], "bad": [1, "x"]}, f)
+        config = snapconfig.load(path)
+        if not hasattr(config, "get_ndarray"):
+            pytest.skip("built without the numpy feature")
+
+        ints = config.get_ndarray("ints")
+        assert isinstance(ints, np.ndarray)
+        assert ints.dtype == np.int64
+        assert ints.tolist() == [1, 2, 3]
+        assert config.get_ndarray("mixed").dtype == np.float64
+        with pytest.raises(ValueError):
+            config.get_ndarray("bad")
+
+    def test_ints_beyond_int64(self, temp_dir):
+        np = pytest.importorskip("numpy")
+        path = os.path.join(temp_dir, "big.yaml")
+        with open(path, "w") as f:
+            f.write(
+                "u: [18446744073709551615, 9223372036854775808]\n"
+                "a: [1, 18446744073709551615]\n"
+                "big: [1, 18446744073709551616]\n"
+            )
+        config = snapconfig.load(path)
+        if not hasattr(config, "get_ndarray"):
+            pytest.skip("built without the numpy feature")
+
+        u = config.get_ndarray("u")
+        assert u.dtype == np.uint64
+        assert u.tolist() == [18446744073709551615, 9223372036854775808]
+        assert config.get_ndarray("a").dtype == np.float64
+        with pytest.raises(OverflowError, match="too large for int64"):
+            config.get_ndarray("big")
+
+
+class TestGetAdapted:
+    DATA = {"name": "svc", "db": {"port": "5432", "hosts": ["a", "b"]}}
+
+    def test_int_adapter(self, load_json):
+        assert load_json(self.DATA).get_adapted("db.port", int) == 5432
+
+    def test_lambda_adapter(self, load_json):
+        config = load_json(self.DATA)
+        assert config.get_adapted("db.hosts", lambda hosts: ",".join(hosts)) == "a,b"
+
+    def test_adapter_errors_propagate(self, load_json):
+        config = load_json(self.DATA)
+        with pytest.raises(ValueError):
+            config.get_adapted("name", int)
+        with pytest.raises(KeyError):
+            config.get_adapted("db.missing", int)
+
+
+class TestFlags:
+    MAPPING = {"read": 1, "write": 2, "exec": 4}
+
+    def test_combines_flags(self, temp_dir):
+        path = os.path.join(temp_dir, "perms.toml")
+        with open(path, "w") as f:
+            f.write('[perms]\nadmin = ["read", "write", "exec"]\nguest = ["read"]\nnone = []\n')
+        config = snapconfig.load(path)
+        assert config.get_flags("perms.admin", self.MAPPING) == 7
+        assert config.get_flags("perms.guest", self.MAPPING) == 1
+        assert config.get_flags("perms.none", self.MAPPING) == 0
+        snapconfig.clear_cache(path)
+
+    def test_unknown_flag(self, temp_dir):
+        path = os.path.join(temp_dir, "perms.json")
+        with open(path, "w") as f:
+            json.dump({"perms": ["read", "sudo"], "name": "x"}, f)
+        config = snapconfig.load(path)
+        with pytest.raises(ValueError, match="sudo"):
+            config.get_flags("perms", self.MAPPING)
+        with pytest.raises(TypeError):
+            config.get_flags("name", self.MAPPING)
+        snapconfig.clear_cache(path)
+
+
+class TestIndexArrayBy:
+    def test_array_of_tables(self, temp_dir):
+        path = os.path.join(temp_dir, "servers.toml")
+        with open(path, "w") as f:
+            f.write('[[servers]]\nname = "alpha"\nport = 1\n\n[[servers]]\nname = "beta"\nport = 2\n')
+        config = snapconfig.load(path)
+        servers = config.index_array_by("servers", "name")
+        assert servers == {
+            "alpha": {"name": "alpha", "port": 1},
+            "beta": {"name": "beta", "port": 2},
+        }
+        snapconfig.clear_cache(path)
+
+    def test_duplicate_and_missing_keys(self, temp_dir):
+        path = os.path.join(temp_dir, "servers.json")
+        with open(path, "w") as f:
+            json.dump({"dup": [{"name": "a"}, {"name": "a"}], "partial": [{"name": "a"}, {"port": 2}]}, f)
+        config = snapconfig.load(path)
+        with pytest.raises(ValueError, match="Duplicate"):
+            config.index_array_by("dup", "name")
+        with pytest.raises(ValueError, match="no 'name' field"):
+            config.index_array_by("partial", "name")
+        snapconfig.clear_cache(path)
+
+
+class TestQuantity:
+    def test_value_with_unit(self, temp_dir):
+        path = os.path.join(temp_dir, "sim.json")
+        with open(path, "w") as f:
+            json.dump({"length": "5 meters", "dt": "0.25 s", "bad": "fast", "n": 3}, f)
+        config = snapconfig.load(path)
+        length = config.get_quantity("length")
+        if isinstance(length, tuple):
+            assert length == (5, "meters")
+            assert config.get_quantity("dt") == (0.25, "s")
+        else:
+            assert length.magnitude == 5
+            assert str(length.units) == "meter"
+
+        with pytest.raises(ValueError):
+            config.get_quantity("bad")
+        with pytest.raises(TypeError):
+            config.get_quantity("n")
+        snapconfig.clear_cache(path)
+
+
+class TestWatchPaths:
+    def _rewrite(self, path, data, bump):
+        with open(path, "w") as f:
+            json.dump(data, f)
+        stamp = os.path.getmtime(path) + bump
+        os.utime(path, (stamp, stamp))
+
+    def test_unwatched_edit_keeps_cache(self, temp_dir):
+        path = os.path.join(temp_dir, "service.json")
+        self._rewrite(path, {"db": {"host": "a"}, "log": "info"}, 0)
+        snapconfig.load(path, watch_paths=["db.host"])
+
+        self._rewrite(path, {"db": {"host": "a"}, "log": "debug"}, 10)
+        config = snapconfig.load(path, watch_paths=["db.host"])
+        assert config["log"] == "info"
+        assert snapconfig.cache_info(path)["cache_fresh"] is True
+
+        self._rewrite(path, {"db": {"host": "b"}, "log": "debug"}, 20)
+        config = snapconfig.load(path, watch_paths=["db.host"])
+        assert config.get("db.host") == "b"
+        assert config["log"] == "debug"
+        snapconfig.clear_cache(path)
+
+    def test_bracket_watch_path(self, temp_dir):
+        path = os.path.join(temp_dir, "service.json")
+        self._rewrite(path, {"hosts": [{"name": "a"}], "log": "info"}, 0)
+        snapconfig.load(path, watch_paths=["hosts[0].name"])
+
+        self._rewrite(path, {"hosts": [{"name": "a"}], "log": "debug"}, 10)
+        assert snapconfig.load(path, watch_paths=["hosts[0].name"])["log"] == "info"
+
+        self._rewrite(path, {"hosts": [{"name": "b"}], "log": "debug"}, 20)
+        assert snapconfig.load(path, watch_paths=["hosts[0].name"])["log"] == "debug"
+        snapconfig.clear_cache(path)
+
+    def test_without_watch_paths_any_edit_refreshes(self, temp_dir):
+        path = os.path.join(temp_dir, "service.json")
+        self._rewrite(path, {"db": {"host": "a"}, "log": "info"}, 0)
+        snapconfig.load(path)
+        self._rewrite(path, {"db": {"host": "a"}, "log": "debug"}, 10)
+        assert snapconfig.load(path)["log"] == "debug"
+        snapconfig.clear_cache(path)
+
+
+class TestValidateModes:
+    def _write(self, path, data, mtime):
+        with open(path, "w") as f:
+            json.dump(data, f)
+        os.utime(path, (mtime, mtime))
+
+    def test_hash_ignores_mtime(self, temp_dir):
+        path = os.path.join(temp_dir, "app.json")
+        self._write(path, {"v": 1}, 1_000_000)
+        snapconfig.load(path, validate="hash")
+
+        # Same content with a newer mtime (e.g. a fresh checkout) keeps the cache.
+        cache = f"{path}.snapconfig"
+        cache_mtime = os.path.getmtime(cache)
+        self._write(path, {"v": 1}, cache_mtime + 100)
+        assert snapconfig.load(path, validate="hash")["v"] == 1
+        assert os.path.getmtime(cache) == cache_mtime
+
+        # Changed content with an older mtime (e.g. a restored layer) recompiles.
+        self._write(path, {"v": 2}, 1_000_000)
+        assert snapconfig.load(path, validate="hash")["v"] == 2
+        assert snapconfig.load(path)["v"] == 2
+        snapconfig.clear_cache(path)
+
+    def test_none_skips_checks(self, temp_dir):
+        path = os.path.join(temp_dir, "app.json")
+        self._write(path, {"v": 1}, 1_000_000)
+        snapconfig.load(path, validate="none")
+        self._write(path, {"v": 2}, os.path.getmtime(f"{path}.snapconfig") + 100)
+        assert snapconfig.load(path, validate="none")["v"] == 1
+        assert snapconfig.load(path)["v"] == 2
+        snapconfig.clear_cache(path)
+
+    def test_unknown_mode(self, json_file):
+        with pytest.raises(ValueError, match="validate"):
+            snapconfig.load(json_file, validate="sha")
+
+
+class TestCorruptCacheRecovery:
+    def test_load_recompiles_corrupt_cache(self, json_file):
+        snapconfig.load(json_file)
+        cache = f"{json_file}.snapconfig"
+        with open(cache, "wb") as f:
+            f.write(b"\x00" * 64)
+        # Keep the corrupt cache "fresh" so only validation can catch it.
+        future = os.path.getmtime(json_file) + 10
+        os.utime(cache, (future, future))
+
+        config = snapconfig.load(json_file)
+        assert config["string"] == "hello"
+        assert snapconfig.load_compiled(cache)["integer"] == 42
+
+    def test_corrupt_cache_without_source_still_raises(self, json_file):
+        snapconfig.load(json_file)
+        with open(f"{json_file}.snapconfig", "wb") as f:
+            f.write(b"\x00" * 64)
+        os.remove(json_file)
+        with pytest.raises(ValueError):
+            snapconfig.load(json_file)
+
+
+class TestJsonComments:
+    CONTENT = """{
+    // the service URL
+    "url": "http://example.com/path", /* inline */ "retries": 3,
+    /* multi
+       line */
+    "note": "keep // this and /* this */"
+}"""
+
+    def test_line_and_block_comments(self):
+        config = snapconfig.loads(self.CONTENT, format="json", strip_comments=True)
+        assert config["url"] == "http://example.com/path"
+        assert config["retries"] == 3
+
+    def test_comment_markers_in_strings_preserved(self):
+        config = snapconfig.loads(self.CONTENT, format="json", strip_comments=True)
+        assert config["note"] == "keep // this and /* this */"
+
+    def test_comments_rejected_by_default(self, temp_dir):
+        with pytest.raises(ValueError):
+            snapconfig.loads(self.CONTENT, format="json")
+        path = os.path.join(temp_dir, "commented.json")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        cache = snapconfig.compile(path, strip_comments=True)
+        assert snapconfig.load_compiled(cache)["retries"] == 3
+
+    def test_load_rebuilds_with_strip_comments(self, temp_dir):
+        path = os.path.join(temp_dir, "commented.json")
+        with open(path, "w") as f:
+            f.write('{"a": 1 // c\n}')
+        snapconfig.compile(path, strip_comments=True)
+        with open(path, "w") as f:
+            f.write('{"a": 2 /* edited */}')
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        os.utime(path, (future, future))
+        assert snapconfig.load(path)["a"] == 2
+        snapconfig.clear_cache(path)
+
+
+class TestProtobufStruct:
+    def test_nested_round_trip(self, temp_dir):
+        struct_pb2 = pytest.importorskip("google.protobuf.struct_pb2")
+        json_format = pytest.importorskip("google.protobuf.json_format")
+        path = os.path.join(temp_dir, "service.json")
+        data = {"name": "api", "replicas": 3, "tls": {"enabled": True, "ciphers": ["a", "b"]}, "owner": None}
+        with open(path, "w") as f:
+            json.dump(data, f)
+        config = snapconfig.load(path)
+        if not hasattr(config, "to_protobuf_struct"):
+            pytest.skip("built without the protobuf feature")
+
+        message = struct_pb2.Struct()
+        message.ParseFromString(config.to_protobuf_struct())
+        assert json_format.MessageToDict(message) == {**data, "replicas": 3.0}
+
+
+class TestConfigParser:
+    def test_sections_and_defaults(self, temp_dir):
+        path = os.path.join(temp_dir, "app.yaml")
+        with open(path, "w") as f:
+            f.write("app_name: demo\ndatabase:\n  Host: db\n  port: 5432\n  url: '%2F'\ncache:\n  enabled: true\n  ttl: 1.5\n  key: null\n")
+        parser = snapconfig.load(path).to_configparser()
+        assert parser.sections() == ["cache", "database"]
+        assert parser["database"]["Host"] == "db"
+        assert parser.getint("database", "port") == 5432
+        assert parser["database"]["url"] == "%2F"
+        assert parser.getboolean("cache", "enabled") is True
+        assert parser["cache"]["key"] == ""
+        assert parser["cache"]["app_name"] == "demo"  # DEFAULT is visible in every section
+        snapconfig.clear_cache(path)
+
+    def test_too_deep_raises(self, json_file):
+        with pytest.raises(ValueError, match="'array' is nested too deeply"):
+            snapconfig.load(json_file).to_configparser()
+
+
+class TestMsgpack:
+    def test_matches_to_dict(self, temp_dir):
+        msgpack = pytest.importorskip("msgpack")
+        path = os.path.join(temp_dir, "service.json")
+        data = {"name": "api", "replicas": 3, "ratio": 0.5, "tls": {"enabled": True, "ciphers": ["a", "b"]}, "owner": None}
+        with open(path, "w") as f:
+            json.dump(data, f)
+        config = snapconfig.load(path)
+        assert msgpack.unpackb(config.to_msgpack()) == config.to_dict() == data
+
+    def test_returns_bytes(self, json_file):
+        config = snapconfig.load(json_file)
+        packed = config.to_msgpack()
+        assert isinstance(packed, bytes)
+        # fixmap with one entry per top-level key
+        assert packed[0] == 0x80 | len(config)
+
+
+class TestJson5:
+    CONTENT = """{
+    // hand-edited
+    host: 'localhost',
+    port: 8080,
+    tags: ["a", "b",],
+    /* nested */ db: {name: "app",},
+}"""
+
+    def test_loads_json5(self):
+        config = snapconfig.loads(self.CONTENT, format="json5")
+        assert config == {
+            "host": "localhost",
+            "port": 8080,
+            "tags": ["a", "b"],
+            "db": {"name": "app"},
+        }
+
+    def test_load_json5_file(self, temp_dir):
+        path = os.path.join(temp_dir, "settings.json5")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        config = snapconfig.load(path)
+        assert config.get("db.name") == "app"
+        assert config["tags"][1] == "b"
+        snapconfig.clear_cache(path)
+
+    def test_invalid_json5_raises(self):
+        with pytest.raises(ValueError, match="JSON5 parse error"):
+            snapconfig.loads("{host: }", format="json5")
+
+
+class TestLoadTyped:
+    SCHEMA = """
+[db.port]
+type = "int"
+
+[db.host]
+type = "str"
+default = "localhost"
+"""
+
+    def test_defaults_and_coercion(self, temp_dir):
+        config_path = os.path.join(temp_dir, "config.json")
+        schema_path = os.path.join(temp_dir, "schema.toml")
+        with open(config_path, "w") as f:
+            json.dump({"db": {"port": "5432"}}, f)
+        with open(schema_path, "w") as f:
+            f.write(self.SCHEMA)
+        config = snapconfig.load_typed(config_path, schema_path)
+        assert config.get("db.port") == 5432
+        assert config.get("db.host") == "localhost"
+
+    def test_violation_names_path(self, temp_dir):
+        config_path = os.path.join(temp_dir, "config.json")
+        schema_path = os.path.join(temp_dir, "schema.toml")
+        with open(config_path, "w") as f:
+            json.dump({"db": {"port": "not-a-port"}}, f)
+        with open(schema_path, "w") as f:
+            f.write(self.SCHEMA)
+        with pytest.raises(ValueError, match="db.port"):
+            snapconfig.load_typed(config_path, schema_path)
+
+
+class TestYamlMultiDocument:
+    STREAM = "kind: Service\nname: web\n---\nkind: Deployment\nname: web\n"
+
+    def test_multi_returns_document_list(self):
+        docs = snapconfig.loads(self.STREAM, format="yaml", multi=True)
+        assert docs == [
+            {"kind": "Service", "name": "web"},
+            {"kind": "Deployment", "name": "web"},
+        ]
+
+    def test_multi_single_document_is_root(self):
+        assert snapconfig.loads("kind: Service\n", format="yaml", multi=True) == {
+            "kind": "Service"
+        }
+
+    def test_default_reads_first_document_only(self):
+        with pytest.raises(ValueError):
+            snapconfig.loads(self.STREAM, format="yaml")
+
+    def test_multi_rejects_other_formats(self):
+        with pytest.raises(ValueError, match="only supported for YAML"):
+            snapconfig.loads("{}", format="json", multi=True)
+
+
+class TestEmitWarnings:
+    def test_duplicate_key_warns(self):
+        with warnings.catch_warnings(record=True) as caught:
+            warnings.simplefilter("always")
+            config = snapconfig.loads(
+                '{"port": 1, "port": 2}', format="json", emit_warnings=True
+            )
+        assert config == {"port": 2}
+        assert [w.category for w in caught] == [snapconfig.SnapconfigWarning]
+        assert "'port'" in str(caught[0].message)
+
+    def test_non_finite_float_warns_on_compile(self, temp_dir):
+        path = os.path.join(temp_dir, "limits.toml")
+        with open(path, "w") as f:
+            f.write("max = inf\n")
+        with warnings.catch_warnings(record=True) as caught:
+            warnings.simplefilter("always")
+            snapconfig.compile(path, emit_warnings=True)
+        assert len(caught) == 1
+        assert "'max' is inf" in str(caught[0].message)
+
+    def test_silent_by_default(self):
+        with warnings.catch_warnings():
+            warnings.simplefilter("error")
+            snapconfig.loads('{"port": 1, "port": 2}', format="json")
+
+    def test_warning_category_is_user_warning(self):
+        assert issubclass(snapconfig.SnapconfigWarning, UserWarning)
+
+
+class TestEnvOverride:
+    def test_prefixed_env_overrides_nested_key(self, temp_dir, monkeypatch):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"database": {"host": "db", "ssl": False}, "name": "app"}, f)
+        monkeypatch.setenv("APP_DATABASE_HOST", "prod-db")
+        monkeypatch.setenv("APP_DATABASE_SSL", "true")
+        monkeypatch.setenv("NAME", "ignored without prefix")
+        config = snapconfig.load(path, env_override=True, env_prefix="APP_")
+        assert config.get("database.host") == "prod-db"
+        assert config.get("database.ssl") is True
+        assert config.get("name") == "app"
+
+    def test_override_does_not_touch_cache(self, temp_dir, monkeypatch):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"port": 80}, f)
+        monkeypatch.setenv("PORT", "8080")
+        assert snapconfig.load(path, env_override=True)["port"] == 8080
+        assert not os.path.exists(path + ".snapconfig")
+        assert snapconfig.load(path)["port"] == 80
+        snapconfig.clear_cache(path)
+
+    def test_custom_separator(self, temp_dir, monkeypatch):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"database": {"port": 5432}}, f)
+        monkeypatch.setenv("APP__DATABASE__PORT", "6543")
+        config = snapconfig.load(
+            path, env_override=True, env_prefix="APP__", env_separator="__"
+        )
+        assert config.get("database.port") == 6543
+
+    def test_preserve_order_lookups(self, temp_dir, monkeypatch):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write('{"zeta": 1, "alpha": 2, "mid": 3}')
+        monkeypatch.setenv("MID", "30")
+        config = snapconfig.load(path, preserve_order=True, env_override=True)
+        assert config.keys() == ["zeta", "alpha", "mid"]
+        assert config["alpha"] == 2
+        assert config["mid"] == 30
+
+    def test_cache_options_are_rejected(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"port": 80}, f)
+        with pytest.raises(ValueError, match="cache_path cannot be used"):
+            snapconfig.load(path, env_override=True, cache_path=path + ".cache")
+        with pytest.raises(ValueError, match="force_recompile, watch_paths, validate"):
+            snapconfig.load(
+                path, env_override=True, force_recompile=True, watch_paths=["port"], validate="hash"
+            )
+        assert not os.path.exists(path + ".snapconfig")
+
+
+class TestFlatten:
+    DATA = {
+        "db": {"host": "localhost", "password": None},
+        "servers": [{"host": "a"}, {"host": "b"}],
+        "tags": [],
+    }
+
+    def test_flatten_dotted_paths(self, load_json):
+        flat = load_json(self.DATA).flatten()
+        assert flat == {
+            "db.host": "localhost",
+            "db.password": None,
+            "servers.0.host": "a",
+            "servers.1.host": "b",
+            "tags": [],
+        }
+
+    def test_flatten_custom_separator(self, load_json):
+        flat = load_json(self.DATA).flatten(separator="__")
+        assert flat["servers__1__host"] == "b"
+
+    def test_null_repr_empty(self, load_json):
+        flat = load_json(self.DATA).flatten(null_repr="")
+        assert flat["db.password"] == ""
+
+    def test_null_repr_null(self, load_json):
+        flat = load_json(self.DATA).flatten(null_repr="null")
+        assert flat["db.password"] == "null"
+
+    def test_drop_nulls(self, load_json):
+        flat = load_json(self.DATA).flatten(drop_nulls=True)
+        assert "db.password" not in flat
+        assert flat["db.host"] == "localhost"
+
+
+class TestGetSecret:
+    DATA = {"token": "s3cr3t", "port": 8080}
+
+    def test_secret_is_masked(self, load_json):
+        secret = load_json(self.DATA).get_secret("token")
+        assert repr(secret) == "***"
+        assert str(secret) == "***"
+        assert "s3cr3t" not in f"{secret!r} {secret}"
+        assert secret.reveal() == "s3cr3t"
+
+    def test_missing_secret_raises(self, load_json):
+        with pytest.raises(KeyError):
+            load_json(self.DATA).get_secret("password")
+
+    def test_non_string_secret_raises(self, load_json):
+        with pytest.raises(TypeError, match="expected a string"):
+            load_json(self.DATA).get_secret("port")
+
+
+class TestMerge:
+    def write(self, temp_dir, name, data):
+        path = os.path.join(temp_dir, name)
+        with open(path, "w") as f:
+            json.dump(data, f)
+        return snapconfig.load(path)
+
+    def test_later_configs_win(self, temp_dir):
+        base = self.write(
+            temp_dir, "base.json", {"db": {"host": "localhost", "port": 5432}, "tags": ["a"]}
+        )
+        prod = self.write(temp_dir, "prod.json", {"db": {"host": "prod"}, "tags": ["b"]})
+        merged = snapconfig.merge([base, prod])
+        assert merged.to_dict() == {"db": {"host": "prod", "port": 5432}, "tags": ["b"]}
+        assert merged.get("db.port") == 5432
+
+    def test_concat_arrays(self, temp_dir):
+        base = self.write(temp_dir, "base.json", {"tags": ["a"]})
+        extra = self.write(temp_dir, "extra.json", {"tags": ["b"], "new": 1})
+        merged = snapconfig.merge([base, extra], concat_arrays=True)
+        assert merged["tags"] == ["a", "b"]
+        assert merged["new"] == 1
+
+    def test_scalar_replaces_object(self, temp_dir):
+        base = self.write(temp_dir, "base.json", {"db": {"host": "x"}})
+        over = self.write(temp_dir, "over.json", {"db": None})
+        assert snapconfig.merge([base, over])["db"] is None
+
+    def test_merge_requires_configs(self):
+        with pytest.raises(ValueError, match="at least one"):
+            snapconfig.merge([])
+
+
+class TestIpAddresses:
+    DATA = {
+        "gateway": "192.168.1.1",
+        "subnet": "2001:db8::/32",
+        "bogus": "999.1.1.1",
+        "port": 53,
+    }
+
+    def test_ipv4_address(self, load_json):
+        addr = load_json(self.DATA).get_ip("gateway")
+        assert addr == ipaddress.IPv4Address("192.168.1.1")
+
+    def test_ipv6_network(self, load_json):
+        net = load_json(self.DATA).get_network("subnet")
+        assert net == ipaddress.IPv6Network("2001:db8::/32")
+        assert ipaddress.ip_address("2001:db8::1") in net
+
+    def test_invalid_address_raises(self, load_json):
+        with pytest.raises(ValueError, match="'bogus'"):
+            load_json(self.DATA).get_ip("bogus")
+
+    def test_non_string_raises(self, load_json):
+        with pytest.raises(TypeError):
+            load_json(self.DATA).get_network("port")
+
+
+class TestLazyDict:
+    def test_lazy_lookup(self, json_file):
+        config = snapconfig.load(json_file)
+        lazy = config.lazy()
+        assert isinstance(lazy, collections.abc.Mapping)
+        assert "nested" in lazy
+        assert "missing" not in lazy
+        assert sorted(lazy.keys()) == sorted(config.keys())
+        assert list(lazy) == lazy.keys()
+        assert len(lazy) == len(config)
+        assert isinstance(lazy["nested"], snapconfig.LazyDict)
+        assert lazy["nested"]["key"] == config.get("nested.key")
+        assert lazy["nested"]["deep"]["level"] == 3
+        assert lazy["array"] == [1, 2, 3]
+        assert lazy.get("missing", 1) == 1
+        with pytest.raises(KeyError):
+            lazy["missing"]
+
+    def test_lazy_outlives_config_name(self, json_file):
+        lazy = snapconfig.load(json_file).lazy()
+        nested = lazy["nested"]
+        del lazy
+        assert nested["key"] == snapconfig.load(json_file).get("nested.key")
+
+    def test_mapping_methods(self, json_file):
+        lazy = snapconfig.load(json_file).lazy()
+        nested = lazy["nested"]
+        assert nested.items() == list(zip(nested.keys(), nested.values()))
+        assert dict(nested.items())["key"] == "value"
+        assert isinstance(dict(nested.items())["deep"], snapconfig.LazyDict)
+        assert nested == {"key": "value", "deep": {"level": 3}}
+        assert nested != {"key": "value"}
+        assert lazy == snapconfig.load(json_file).lazy()
+        assert lazy != nested
+        assert nested != ["key", "deep"]
+
+
+@dataclasses.dataclass(frozen=True)
+class _Record:
+    id: int
+    name: str
+    weight: float = 1.0
+
+
+class TestIterRecords:
+    def test_yields_dataclasses(self, load_json):
+        config = load_json(
+            {
+                "records": [
+                    {"id": 1, "name": "a"},
+                    {"id": 2, "name": "b", "weight": 2},
+                    {"id": 3, "name": "c", "weight": 0.5},
+                ]
+            }
+        )
+        records = config.iter_records("records", _Record)
+        assert next(records) == _Record(1, "a")
+        assert list(records) == [_Record(2, "b", 2.0), _Record(3, "c", 0.5)]
+
+    def test_error_names_element_index(self, load_json):
+        config = load_json({"records": [{"id": 1, "name": "a"}, {"id": "x", "name": "b"}]})
+        records = config.iter_records("records", _Record)
+        assert next(records) == _Record(1, "a")
+        with pytest.raises(TypeError, match="'records.1.id'"):
+            next(records)
+
+    def test_scalar_types(self, load_json):
+        config = load_json({"records": [1, 2, 3]})
+        assert list(config.iter_records("records", int)) == [1, 2, 3]
+        with pytest.raises(TypeError, match="'records.0'"):
+            next(config.iter_records("records", str))
+
+
+class TestParseErrorLocations:
+    def test_compile_reports_file_line_column(self, temp_dir):
+        path = os.path.join(temp_dir, "config.toml")
+        with open(path, "w") as f:
+            f.write('[server]\nhost = "a"\nport = \n')
+        with pytest.raises(ValueError) as excinfo:
+            snapconfig.compile(path)
+        assert str(excinfo.value).startswith(f"{path}:3:8: TOML parse error")
+
+    def test_json_offset_becomes_line_column(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write('{\n  "a": 1,\n  "b": }\n')
+        with pytest.raises(ValueError, match=r"config\.json:3:\d+: JSON parse error"):
+            snapconfig.load(path)
+
+    def test_loads_uses_string_placeholder(self):
+        with pytest.raises(ValueError, match=r"^<string>:3:1: YAML parse error"):
+            snapconfig.loads("a: 1\nb: [1, 2\n", format="yaml")
+
+
+class TestArrayKeyCoverage:
+    def test_ragged_records(self, temp_dir):
+        path = os.path.join(temp_dir, "users.json")
+        with open(path, "w") as f:
+            json.dump(
+                {
+                    "users": [
+                        {"id": 1, "name": "a", "email": "a@x"},
+                        {"id": 2, "name": "b"},
+                        {"id": 3, "phone": "555"},
+                    ],
+                    "tags": ["x"],
+                },
+                f,
+            )
+        config = snapconfig.load(path)
+        assert config.array_key_coverage("users") == {
+            "email": 1,
+            "id": 3,
+            "name": 2,
+            "phone": 1,
+        }
+        with pytest.raises(ValueError, match=r"'tags\[0\]' is not an object"):
+            config.array_key_coverage("tags")
+        snapconfig.clear_cache(path)
+
+
+class TestXml:
+    CONTENT = """<?xml version="1.0"?>
+<config env="prod">
+  <database>
+    <host>db.local</host>
+    <port>5432</port>
+  </database>
+  <server name="a"/>
+  <server name="b"/>
+  <motd>Hello <b>there</b> friend</motd>
+</config>
+"""
+
+    def test_loads_xml(self):
+        config = snapconfig.loads(self.CONTENT, format="xml")
+        assert config == {
+            "config": {
+                "@env": "prod",
+                "database": {"host": "db.local", "port": 5432},
+                "server": [{"@name": "a"}, {"@name": "b"}],
+                "motd": {"b": "there", "#text": "Hello  friend"},
+            }
+        }
+
+    def test_load_xml_file(self, temp_dir):
+        path = os.path.join(temp_dir, "app.xml")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        config = snapconfig.load(path)
+        assert config.get("config.database.port") == 5432
+        assert config.get("config.server.1.@name") == "b"
+        snapconfig.clear_cache(path)
+
+    def test_invalid_xml_raises(self):
+        with pytest.raises(ValueError, match="XML parse error"):
+            snapconfig.loads("<a><b></a>", format="xml")
+
+
+class TestCacheDir:
+    def test_load_with_read_only_source_dir(self, temp_dir):
+        source_dir = os.path.join(temp_dir, "etc")
+        cache_dir = os.path.join(temp_dir, "cache")
+        os.mkdir(source_dir)
+        path = os.path.join(source_dir, "app.json")
+        with open(path, "w") as f:
+            json.dump({"port": 8080}, f)
+        os.chmod(source_dir, 0o555)
+        try:
+            config = snapconfig.load(path, cache_dir=cache_dir)
+            assert config["port"] == 8080
+            assert not os.path.exists(path + ".snapconfig")
+            assert os.path.dirname(config.cache_path) == cache_dir
+            assert os.path.basename(config.cache_path).startswith("app.json-")
+            assert snapconfig.load(path, cache_dir=cache_dir).cache_path == config.cache_path
+            assert snapconfig.clear_cache(path, cache_dir=cache_dir)
+        finally:
+            os.chmod(source_dir, 0o755)
+
+    def test_same_name_sources_do_not_collide(self, temp_dir):
+        cache_dir = os.path.join(temp_dir, "cache")
+        paths = []
+        for sub in ("a", "b"):
+            os.mkdir(os.path.join(temp_dir, sub))
+            path = os.path.join(temp_dir, sub, "config.json")
+            with open(path, "w") as f:
+                json.dump({"dir": sub}, f)
+            paths.append(path)
+        compiled = [snapconfig.compile(p, cache_dir=cache_dir) for p in paths]
+        assert compiled[0] != compiled[1]
+        assert [snapconfig.load(p, cache_dir=cache_dir)["dir"] for p in paths] == ["a", "b"]
+
+
+class TestGetMany:
+    def test_results_in_order_with_missing_as_default(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"db": {"host": "localhost", "port": 5432}, "servers": [{"name": "a"}]}, f)
+        config = snapconfig.load(path)
+        paths = ["db.port", "missing", "servers.0.name", "db.host", "servers.5"]
+        assert config.get_many(paths) == [5432, None, "a", "localhost", None]
+        assert config.get_many(paths, default=0) == [5432, 0, "a", "localhost", 0]
+        assert config.get_many([]) == []
+        snapconfig.clear_cache(path)
+
+
+class TestToShell:
+    def test_export_lines_with_quoting(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump(
+                {
+                    "database": {"host": "db.local", "port": 5432},
+                    "motd": "it's a \"nice\" day",
+                    "servers": ["a", "b"],
+                    "feature-flag": True,
+                    "empty": None,
+                },
+                f,
+            )
+        config = snapconfig.load(path)
+        assert config.to_shell(prefix="APP_").splitlines() == [
+            "export APP_DATABASE_HOST='db.local'",
+            "export APP_DATABASE_PORT='5432'",
+            "export APP_EMPTY=''",
+            "export APP_FEATURE_FLAG='true'",
+            "export APP_MOTD='it'\\''s a \"nice\" day'",
+            "export APP_SERVERS_0='a'",
+            "export APP_SERVERS_1='b'",
+        ]
+        assert "export DATABASE__HOST='db.local'\n" in config.to_shell(sep="__")
+        snapconfig.clear_cache(path)
+
+    def test_shell_round_trip(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"motd": "it's $HOME `x` \\ \"q\"\nline2"}, f)
+        config = snapconfig.load(path)
+        script = config.to_shell() + 'printf %s "$MOTD"'
+        result = subprocess.run(["sh", "-c", script], capture_output=True, text=True, check=True)
+        assert result.stdout == config["motd"]
+        snapconfig.clear_cache(path)
+
+
+class TestGetMatching:
+    DATA = {"hostname": "db-1.example.com", "bad": "DB_1!", "port": 5432}
+
+    def test_matching_value(self, load_json):
+        config = load_json(self.DATA)
+        assert config.get_matching("hostname", r"^[a-z0-9.-]+$") == "db-1.example.com"
+
+    def test_mismatch_names_path_and_pattern(self, load_json):
+        config = load_json(self.DATA)
+        with pytest.raises(ValueError, match=r"'bad' does not match .*a-z0-9"):
+            config.get_matching("bad", r"^[a-z0-9.-]+$")
+
+    def test_non_string_and_invalid_pattern(self, load_json):
+        config = load_json(self.DATA)
+        with pytest.raises(TypeError):
+            config.get_matching("port", r"\d+")
+        with pytest.raises(ValueError, match="Invalid pattern"):
+            config.get_matching("hostname", r"(")
+        with pytest.raises(KeyError):
+            config.get_matching("missing", r".*")
+
+
+class TestLoadFileobj:
+    def test_bytes_io(self):
+        config = snapconfig.load_fileobj(io.BytesIO(b'\xef\xbb\xbf{"port": 8080}'), format="json")
+        assert config["port"] == 8080
+        assert config.cache_path == ""
+        assert config.to_dict() == {"port": 8080}
+
+    def test_string_io(self):
+        config = snapconfig.load_fileobj(io.StringIO("db:\n  host: x\n"), format="yaml")
+        assert config.get("db.host") == "x"
+
+    def test_preserve_order(self):
+        stream = io.StringIO('{"b": 1, "a": 2}')
+        assert snapconfig.load_fileobj(stream, format="json", preserve_order=True).keys() == ["b", "a"]
+
+    def test_bad_read_result_and_format(self):
+        class Reader:
+            def read(self):
+                return 42
+
+        with pytest.raises(TypeError, match="expected bytes or str"):
+            snapconfig.load_fileobj(Reader(), format="json")
+        with pytest.raises(ValueError, match="Unknown format"):
+            snapconfig.load_fileobj(io.StringIO("{}"), format="nope")
+        with pytest.raises(ValueError, match="not valid UTF-8"):
+            snapconfig.load_fileobj(io.BytesIO(b"\xff"), format="json")
+
+
+class TestRon:
+    CONTENT = """// service settings
+Service(
+    name: "api",
+    ports: [80, 443],
+    limits: {"cpu": 2},
+    backup: None,
+    replica: Some("r1"),
+)
+"""
+
+    def test_loads_ron(self):
+        config = snapconfig.loads(self.CONTENT, format="ron")
+        assert config == {
+            "name": "api",
+            "ports": [80, 443],
+            "limits": {"cpu": 2},
+            "backup": None,
+            "replica": "r1",
+        }
+
+    def test_load_ron_file(self, temp_dir):
+        path = os.path.join(temp_dir, "service.ron")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        config = snapconfig.load(path)
+        assert config.get("limits.cpu") == 2
+        assert config["ports"][1] == 443
+        snapconfig.clear_cache(path)
+
+    def test_enum_variants_are_tagged(self):
+        config = snapconfig.loads(
+            '(listeners: [Tcp(port: 80), Udp(port: 80), Pair(1, 2)], mode: Fast)', format="ron"
+        )
+        assert config["listeners"] == [{"Tcp": {"port": 80}}, {"Udp": {"port": 80}}, {"Pair": [1, 2]}]
+        assert config["mode"] is None
+
+    def test_invalid_ron_raises(self):
+        with pytest.raises(ValueError, match="RON parse error"):
+            snapconfig.loads("(a: [1, 2", format="ron")
+        with pytest.raises(ValueError, match="RON parse error: 1:16"):
+            snapconfig.loads("(a: Tcp(1), b: )", format="ron")
+
+
+class TestTomlMergeKey:
+    CONTENT = """
+[[host]]
+name = "web"
+port = 80
+
+[[host]]
+name = "db"
+
+[[host]]
+name = "web"
+tls = true
+"""
+
+    def test_loads_merges_entries_with_same_key(self):
+        config = snapconfig.loads(self.CONTENT, format="toml", toml_merge_key="name")
+        assert config["host"] == [{"name": "web", "port": 80, "tls": True}, {"name": "db"}]
+        assert len(snapconfig.loads(self.CONTENT, format="toml")["host"]) == 3
+
+    def test_compile(self, temp_dir):
+        path = os.path.join(temp_dir, "hosts.toml")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        snapconfig.compile(path, toml_merge_key="name")
+        config = snapconfig.load(path)
+        assert len(config["host"]) == 2
+        snapconfig.clear_cache(path)
+
+    def test_load_rebuilds_with_compiled_merge_key(self, temp_dir):
+        path = os.path.join(temp_dir, "hosts.toml")
+        with open(path, "w") as f:
+            f.write(self.CONTENT)
+        snapconfig.compile(path, toml_merge_key="name")
+        with open(path, "a") as f:
+            f.write('\n[[host]]\nname = "db"\nport = 5432\n')
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        os.utime(path, (future, future))
+        config = snapconfig.load(path)
+        assert config["host"] == [{"name": "web", "port": 80, "tls": True}, {"name": "db", "port": 5432}]
+        snapconfig.clear_cache(path)
+
+
+class TestPaths:
+    def test_leaf_paths(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump(
+                {
+                    "database": {"host": "db", "port": 5432},
+                    "servers": ["a", {"name": "b"}],
+                    "tags": [],
+                    "debug": None,
+                },
+                f,
+            )
+        config = snapconfig.load(path)
+        assert config.paths() == [
+            "database.host",
+            "database.port",
+            "debug",
+            "servers.0",
+            "servers.1.name",
+            "tags",
+        ]
+        assert config.paths() == list(config.flatten())
+        snapconfig.clear_cache(path)
+
+
+class TestNormalizeUnicode:
+    def test_decomposed_becomes_composed(self, load_json):
+        decomposed = "café"
+        config = load_json({decomposed: decomposed, "names": ["Zoë"]})
+        normalized = config.normalize_unicode()
+        assert normalized.to_dict() == {"café": "café", "names": ["Zoë"]}
+        assert normalized["café"] == "café"
+        assert config[decomposed] == decomposed
+        assert normalized.normalize_unicode("NFD").to_dict() == config.to_dict()
+
+    def test_colliding_keys_and_bad_form(self, load_json):
+        config = load_json({"é": 1, "é": 2})
+        with pytest.raises(ValueError, match="collide"):
+            config.normalize_unicode()
+        with pytest.raises(ValueError, match="form must be"):
+            config.normalize_unicode("NFX")
+
+
+class TestAsEnviron:
+    DATA = {"database": {"host": "db", "port": 5432}, "debug": False, "hosts": ["a"]}
+
+    def test_nested_value_by_env_name(self, load_json):
+        env = load_json(self.DATA).as_environ(prefix="APP__")
+        assert env["APP__DATABASE__HOST"] == "db"
+        assert env["APP__DATABASE__PORT"] == "5432"
+        assert env.get("APP__DEBUG") == "false"
+        assert env["APP__HOSTS__0"] == "a"
+        assert "APP__DATABASE" not in env
+        assert env.get("MISSING", "x") == "x"
+        with pytest.raises(KeyError):
+            env["database.host"]
+
+    def test_mapping_protocol(self, load_json):
+        env = load_json(self.DATA).as_environ(sep="_")
+        assert isinstance(env, collections.abc.Mapping)
+        assert list(env) == ["DATABASE_HOST", "DATABASE_PORT", "DEBUG", "HOSTS_0"]
+        assert len(env) == 4
+        assert dict(env)["DEBUG"] == "false"
+        assert env.items()[0] == ("DATABASE_HOST", "db")
+        assert env.values() == ["db", "5432", "false", "a"]
+        assert env == dict(env.items())
+        assert env == load_json(self.DATA).as_environ(sep="_")
+        assert env != {"DEBUG": "false"}
+
+
+class TestBracketPaths:
+    DATA = {"a": {"b": [0, 1, {"c": "deep"}]}, "grid": [[1, 2], [3, 4]], "m": {"2": "key"}}
+
+    def test_bracket_indices(self, load_json):
+        config = load_json(self.DATA)
+        assert config.get("a.b[2].c") == "deep"
+        assert config.get("a.b.2.c") == "deep"
+        assert config.get("grid[1][0]") == 3
+        assert config.get_int("grid[0][1]") == 2
+        assert config.get("a.b[9]", default="none") == "none"
+        assert config.get("m[2]") is None
+        assert config.get("m.2") == "key"
+
+    def test_invalid_brackets_raise(self, load_json):
+        config = load_json(self.DATA)
+        for path in ("a.b[2", "a.b[x]", "a.b[]", "a.b[2]c", "a.b[-1]"):
+            with pytest.raises(ValueError, match="Invalid path"):
+                config.get(path)
+
+    def test_keys_containing_brackets(self, load_json):
+        config = load_json({"labels[env]": "prod", "arr[0]": "literal", "arr": ["indexed"]})
+        assert config.get("labels[env]") == "prod"
+        assert config.get("arr[0]") == "literal"
+        assert config.get("arr.0") == "indexed"
+        for path in config.paths():
+            assert config.get(path) == config.flatten()[path]
+
+
+class TestKeyTransform:
+    @staticmethod
+    def snake(key):
+        return "".join("_" + c.lower() if c.isupper() else c for c in key)
+
+    def test_camel_to_snake(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"dbHost": "x", "serverList": [{"maxConn": 5}], "zeta": 1}, f)
+        snapconfig.compile(path, key_transform=self.snake)
+        config = snapconfig.load(path)
+        assert config.keys() == ["db_host", "server_list", "zeta"]
+        assert config.get("server_list.0.max_conn") == 5
+        assert "dbHost" not in config
+        snapconfig.clear_cache(path)
+
+    def test_load_refuses_to_rebuild_without_transform(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"dbHost": "x"}, f)
+        snapconfig.compile(path, key_transform=self.snake)
+        with open(path, "w") as f:
+            json.dump({"dbHost": "y"}, f)
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        os.utime(path, (future, future))
+        with pytest.raises(ValueError, match="compiled with a key_transform"):
+            snapconfig.load(path)
+        cache = snapconfig.compile(path, key_transform=self.snake)
+        assert snapconfig.load_compiled(cache)["db_host"] == "y"
+        snapconfig.clear_cache(path)
+
+    def test_collision_and_callable_errors(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"dbHost": 1, "db_host": 2}, f)
+        with pytest.raises(ValueError, match="Duplicate key.*'db_host'"):
+            snapconfig.compile(path, key_transform=self.snake)
+
+        def boom(key):
+            raise RuntimeError("bad " + key)
+
+        with pytest.raises(RuntimeError, match="bad"):
+            snapconfig.compile(path, key_transform=boom)
+        with pytest.raises(TypeError):
+            snapconfig.compile(path, key_transform=len)
+
+
+class TestSub:
+    def test_view_paths_are_relative(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"db": {"host": "h", "pool": {"size": 4}}, "servers": [{"port": 80}]}, f)
+        config = snapconfig.load(path)
+        db = config.sub("db")
+        assert db.keys() == ["host", "pool"]
+        assert db.get("pool.size") == 4
+        assert db.sub("pool")["size"] == 4
+        assert db.source_path == config.source_path
+        assert config.sub("servers").get("0.port") == 80
+        del config
+        assert db["host"] == "h"
+
+        with pytest.raises(KeyError):
+            db.sub("missing")
+        with pytest.raises(TypeError, match="'host' is a string"):
+            db.sub("host")
+
+    def test_shared_backing_blocks_set_scalar(self, temp_dir):
+        path = os.path.join(temp_dir, "counters.json")
+        with open(path, "w") as f:
+            json.dump({"stats": {"hits": 1}}, f)
+        cache = os.path.join(temp_dir, "counters.snapconfig")
+        snapconfig.compile(path, cache)
+        config = snapconfig.load_compiled(cache, writable=True)
+        stats = config.sub("stats")
+        with pytest.raises(ValueError, match="shared"):
+            config.set_scalar("stats.hits", 2)
+        del stats
+        config.set_scalar("stats.hits", 2)
+        assert config.get("stats.hits") == 2
+
+
+class TestInferSchema:
+    def test_nested_config(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump(
+                {
+                    "db": {"host": "h", "port": 5432, "ratio": 0.5, "tls": None},
+                    "tags": ["a", "b"],
+                    "mixed": [1, "two", 3],
+                    "empty": [],
+                },
+                f,
+            )
+        schema = snapconfig.load(path).infer_schema()
+        assert schema["$schema"].startswith("https://json-schema.org/")
+        assert schema["type"] == "object"
+        assert schema["required"] == ["db", "empty", "mixed", "tags"]
+
+        db = schema["properties"]["db"]
+        assert db["required"] == ["host", "port", "ratio", "tls"]
+        assert db["properties"]["port"] == {"type": "integer"}
+        assert db["properties"]["ratio"] == {"type": "number"}
+        assert db["properties"]["tls"] == {"type": "null"}
+
+        props = schema["properties"]
+        assert props["tags"] == {"type": "array", "items": {"type": "string"}}
+        assert props["mixed"]["items"] == {"anyOf": [{"type": "integer"}, {"type": "string"}]}
+        assert props["empty"] == {"type": "array"}
+
+
+class TestMmapSource:
+    @pytest.mark.parametrize("ext", ["json", "yaml"])
+    def test_matches_standard_compile(self, temp_dir, ext):
+        path = os.path.join(temp_dir, f"large.{ext}")
+        with open(path, "w") as f:
+            for i in range(20000):
+                if ext == "json":
+                    f.write(("{" if i == 0 else ",") + f'"item{i}": {{"id": {i}, "name": "item-{i}"}}')
+                else:
+                    f.write(f"item{i}:\n  id: {i}\n  name: item-{i}\n")
+            if ext == "json":
+                f.write("}")
+        standard = os.path.join(temp_dir, "standard.snapconfig")
+        mapped = os.path.join(temp_dir, "mapped.snapconfig")
+        snapconfig.compile(path, standard)
+        snapconfig.compile(path, mapped, mmap_source=True)
+        with open(standard, "rb") as a, open(mapped, "rb") as b:
+            assert a.read() == b.read()
+
+    def test_empty_and_invalid_utf8(self, temp_dir):
+        path = os.path.join(temp_dir, "empty.env")
+        open(path, "w").close()
+        cache = snapconfig.compile(path, mmap_source=True)
+        assert snapconfig.load_compiled(cache).keys() == []
+
+        path = os.path.join(temp_dir, "bad.json")
+        with open(path, "wb") as f:
+            f.write(b'{"a": "\xff"}')
+        with pytest.raises(OSError):
+            snapconfig.compile(path, mmap_source=True)
+
+
+class TestPickle:
+    DATA = {"db": {"host": "h", "ports": [1, 2]}, "name": "svc"}
+
+    def test_round_trip_reopens_cache(self, load_json):
+        config = load_json(self.DATA)
+        copy = pickle.loads(pickle.dumps(config))
+        assert copy.cache_path == config.cache_path
+        assert copy.source_path == config.source_path
+        assert copy.to_dict() == config.to_dict()
+
+        view = pickle.loads(pickle.dumps(config.sub("db")))
+        assert view.keys() == ["host", "ports"]
+        assert view.get("ports.1") == 2
+
+    def test_missing_cache_and_in_memory(self, load_json):
+        config = load_json(self.DATA)
+        data = pickle.dumps(config)
+        os.remove(config.cache_path)
+        with pytest.raises(OSError, match="Cannot unpickle SnapConfig"):
+            pickle.loads(data)
+
+        with pytest.raises(TypeError, match="no cache file"):
+            pickle.dumps(snapconfig.load_fileobj(io.StringIO('{"a": 1}'), "json"))
+
+    def test_rebuilt_cache_is_rejected(self, load_json):
+        config = load_json(self.DATA)
+        data = pickle.dumps(config.sub("db"))
+        with open(config.source_path, "w") as f:
+            json.dump({"db": {"host": "other", "ports": [3]}, "name": "svc"}, f)
+        snapconfig.compile(config.source_path)
+        with pytest.raises(ValueError, match="changed since it was pickled"):
+            pickle.loads(data)
+
+
+class TestEq:
+    def test_structural_equality(self, temp_dir):
+        a = os.path.join(temp_dir, "a.json")
+        b = os.path.join(temp_dir, "b.yaml")
+        with open(a, "w") as f:
+            json.dump({"x": 1, "y": [1.5, "s"], "z": {"k": None}}, f)
+        with open(b, "w") as f:
+            f.write("z:\n  k: null\ny: [1.5, s]\nx: 1\n")
+        left, right = snapconfig.load(a), snapconfig.load(b)
+        assert left == right
+        assert left.sub("z") == right.sub("z")
+        assert left != left.sub("z")
+        assert (left == {"x": 1}) is False
+        assert left.__eq__({"x": 1}) is NotImplemented
+
+    def test_exact_floats_and_order(self, temp_dir):
+        def parse(text):
+            return snapconfig.load_fileobj(io.StringIO(text), "yaml")
+
+        config = parse("a: [1, 2]\nnan: .nan\n")
+        assert config.sub("a") != parse("[2, 1]")
+        assert parse("v: 1") != parse("v: 1.0")
+        assert parse("v: 0.5") == parse("v: 0.5")
+        assert config != config
+
+
+class TestGetTimedelta:
+    def load(self, text):
+        return snapconfig.load_fileobj(io.StringIO(text), "yaml")
+
+    def test_compound_durations(self):
+        import datetime as dt
+
+        config = self.load("a: 1h30m\nb: 500ms\nc: 2d\nd: 1.5h 10s\ne: 1w2d3h4m5s6ms7us\n")
+        assert config.get_timedelta("a") == dt.timedelta(hours=1, minutes=30)
+        assert config.get_timedelta("b") == dt.timedelta(milliseconds=500)
+        assert config.get_timedelta("c") == dt.timedelta(days=2)
+        assert config.get_timedelta("d") == dt.timedelta(hours=1, minutes=30, seconds=10)
+        assert config.get_timedelta("e") == dt.timedelta(
+            weeks=1, days=2, hours=3, minutes=4, seconds=5, milliseconds=6, microseconds=7
+        )
+
+    def test_invalid_duration(self):
+        config = self.load("a: 10 minutes\nb: h\nc: ''\nd: 5\n")
+        for path in ("a", "b", "c"):
+            with pytest.raises(ValueError, match=f"'{path}': invalid duration"):
+                config.get_timedelta(path)
+        with pytest.raises(TypeError):
+            config.get_timedelta("d")
+
+
+class TestSeparatorInKeys:
+    def test_env_quoted_and_escaped_keys(self, temp_dir):
+        path = os.path.join(temp_dir, "app.env")
+        with open(path, "w") as f:
+            f.write('"A=B"=1\nC\\=D=2\nURL=http://h/?x=1\n')
+        config = snapconfig.load(path)
+        assert config["A=B"] == 1
+        assert config["C=D"] == 2
+        assert config["URL"] == "http://h/?x=1"
+        snapconfig.clear_cache(path)
+
+    def test_properties_separators(self, temp_dir):
+        path = os.path.join(temp_dir, "app.properties")
+        with open(path, "w") as f:
+            f.write("a\\=b\\:c = 1\nurl: http://h:80/?x=1\n")
+        config = snapconfig.load(path)
+        assert config["a=b:c"] == 1
+        assert config["url"] == "http://h:80/?x=1"
+        snapconfig.clear_cache(path)
+
+
+class TestLoadEnv:
+    def write(self, temp_dir):
+        path = os.path.join(temp_dir, "config.yaml")
+        with open(path, "w") as f:
+            f.write(
+                "version: 3\n"
+                "development:\n  db: {host: localhost}\n"
+                "production:\n  db: {host: prod.example}\n"
+            )
+        return path
+
+    def test_selects_section(self, temp_dir):
+        path = self.write(temp_dir)
+        config = snapconfig.load(path, env="production")
+        assert config.get("db.host") == "prod.example"
+        assert config.keys() == ["db"]
+        assert snapconfig.load(path).get("version") == 3
+        snapconfig.clear_cache(path)
+
+    def test_missing_section_lists_available(self, temp_dir):
+        path = self.write(temp_dir)
+        with pytest.raises(ValueError, match="'staging' not found; available sections: development, production"):
+            snapconfig.load(path, env="staging")
+        with pytest.raises(TypeError, match="'version' is an int"):
+            snapconfig.load(path, env="version")
+        snapconfig.clear_cache(path)
+
+
+class TestCanonicalJson:
+    def test_equal_configs_match(self, temp_dir):
+        a = os.path.join(temp_dir, "a.json")
+        b = os.path.join(temp_dir, "b.yaml")
+        with open(a, "w") as f:
+            f.write('{"b": [1, 2.50], "a": {"y": null, "x": true}}')
+        with open(b, "w") as f:
+            f.write("a:\n  x: true\n  y: null\nb: [1, 2.5]\n")
+        left = snapconfig.load(a, preserve_order=True).to_canonical_json()
+        assert left == snapconfig.load(b).to_canonical_json()
+        assert left == '{"a":{"x":true,"y":null},"b":[1,2.5]}'
+
+    def test_number_and_string_formatting(self):
+        config = snapconfig.load_fileobj(
+            io.StringIO(
+                '{"f": [1.0, -0.0, 1e21, 1e20, 1e-7, 0.000001, 123.456, -1.5e-10], '
+                '"big": 9007199254740993, "s": "\\u0008\\t\\u001f\\"\\\\é/", '
+                '"\\ud83d\\ude00": 1, "\\uffff": 2}'
+            ),
+            "json",
+        )
+        assert config.to_canonical_json() == (
+            '{"big":9007199254740993,'
+            '"f":[1,0,1e+21,100000000000000000000,1e-7,0.000001,123.456,-1.5e-10],'
+            '"s":"\\b\\t\\u001f\\"\\\\é/",'
+            '"😀":1,"\uffff":2}'
+        )
+
+
+class TestCsv:
+    def test_rows_as_objects(self, temp_dir):
+        path = os.path.join(temp_dir, "hosts.csv")
+        with open(path, "w") as f:
+            f.write("name,port,enabled\nweb,80,true\n\"db, main\",5432,false\n")
+        config = snapconfig.load(path)
+        assert config.to_dict() == [
+            {"name": "web", "port": 80, "enabled": True},
+            {"name": "db, main", "port": 5432, "enabled": False},
+        ]
+        assert config.get("1.port") == 5432
+        snapconfig.clear_cache(path)
+
+    def test_loads_options(self):
+        rows = snapconfig.loads("a;1\nb;2\n", "csv", csv_delimiter=";", csv_has_header=False)
+        assert rows == [["a", 1], ["b", 2]]
+        with pytest.raises(ValueError, match="csv_delimiter"):
+            snapconfig.loads("a", "csv", csv_delimiter=";;")
+        with pytest.raises(ValueError, match="CSV parse error"):
+            snapconfig.loads("a,b\n1\n", "csv")
+
+    def test_load_rebuilds_with_compiled_options(self, temp_dir):
+        path = os.path.join(temp_dir, "hosts.csv")
+        with open(path, "w") as f:
+            f.write("web;80\n")
+        snapconfig.compile(path, csv_delimiter=";", csv_has_header=False)
+        with open(path, "a") as f:
+            f.write("db;5432\n")
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        os.utime(path, (future, future))
+        assert snapconfig.load(path).to_dict() == [["web", 80], ["db", 5432]]
+        snapconfig.clear_cache(path)
+
+
+class TestCompileOverlay:
+    def write(self, temp_dir):
+        base = os.path.join(temp_dir, "config.toml")
+        local = os.path.join(temp_dir, "config.local.yaml")
+        with open(base, "w") as f:
+            f.write('name = "svc"\n[db]\nhost = "localhost"\nport = 5432\n')
+        with open(local, "w") as f:
+            f.write("db:\n  host: db.internal\n")
+        return base, local
+
+    def test_yaml_overrides_nested_toml_key(self, temp_dir):
+        base, local = self.write(temp_dir)
+        cache = snapconfig.compile_overlay([base, local])
+        assert cache == base + ".snapconfig"
+        config = snapconfig.load(base)
+        assert config.to_dict() == {"name": "svc", "db": {"host": "db.internal", "port": 5432}}
+        assert snapconfig.load_compiled(cache).get("db.host") == "db.internal"
+
+    def test_load_tracks_every_input(self, temp_dir):
+        base, local = self.write(temp_dir)
+        snapconfig.compile_overlay([base, local])
+
+        with open(local, "w") as f:
+            f.write("db:\n  port: 6543\n")
+        future = os.path.getmtime(base + ".snapconfig") + 10
+        os.utime(local, (future, future))
+        config = snapconfig.load(base)
+        assert config.get("db.port") == 6543
+        assert config.get("db.host") == "localhost"
+
+        with open(local, "w") as f:
+            f.write("db:\n  port: 1\n")
+        assert snapconfig.load(base, validate="hash").get("db.port") == 1
+
+    def test_corrupt_cache_is_rebuilt_from_every_input(self, temp_dir):
+        base, local = self.write(temp_dir)
+        cache = snapconfig.compile_overlay([base, local])
+        with open(cache, "r+b") as f:
+            f.seek(-16, os.SEEK_END)
+            f.write(b"\xff" * 16)
+        assert snapconfig.load(base).get("db.host") == "db.internal"
+
+    def test_missing_input(self, temp_dir):
+        base, local = self.write(temp_dir)
+        snapconfig.compile_overlay([base, local])
+        assert snapconfig.cache_info(base)["cache_fresh"] is True
+        os.remove(local)
+        assert snapconfig.cache_info(base)["cache_fresh"] is False
+        for validate in ("mtime", "hash"):
+            with pytest.raises(OSError, match="File not found: .*config.local.yaml"):
+                snapconfig.load(base, validate=validate)
+
+    def test_errors(self, temp_dir):
+        with pytest.raises(ValueError, match="at least one path"):
+            snapconfig.compile_overlay([])
+        with pytest.raises(OSError, match="File not found"):
+            snapconfig.compile_overlay([os.path.join(temp_dir, "missing.toml")])
+
+
+class TestDiffChanges:
+    def test_changes_additions_removals(self):
+        def parse(text):
+            return snapconfig.load_fileobj(io.StringIO(text), "json")
+
+        old = parse('{"db": {"host": "a", "port": 1}, "gone": true, "list": [1, 2], "meta": {"t": 1}}')
+        new = parse('{"db": {"host": "a", "port": 2}, "added": {"x": 1}, "list": [1, 3, 4], "meta": {"t": 2}}')
+        assert old.diff(new, ignore=["meta"]) == {
+            "db.port": (1, 2),
+            "gone": (True, None),
+            "added": (None, {"x": 1}),
+            "list.1": (2, 3),
+            "list.2": (None, 4),
+        }
+        assert old.diff(old) == {}
+
+    def test_mixed_key_order(self):
+        text = '{"z": 1, "m": {"y": 2, "b": 3}, "a": 4}'
+        ordered = snapconfig.load_fileobj(io.StringIO(text), "json", preserve_order=True)
+        sorted_ = snapconfig.load_fileobj(io.StringIO(text.replace("4", "5")), "json")
+        assert ordered.diff(sorted_) == {"a": (4, 5)}
+        assert sorted_.diff(ordered) == {"a": (5, 4)}
+        assert ordered.overrides({"z": 1, "m": {"y": 2, "b": 0}, "a": 4}) == ["m.b"]
+
+
+class TestLimits:
+    def test_object_over_max_keys(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"db": {f"k{i}": i for i in range(5)}}, f)
+        with pytest.raises(ValueError, match=r"'db' has 5 keys \(max_keys=4\)"):
+            snapconfig.compile(path, max_keys=4)
+        snapconfig.compile(path, max_keys=5)
+
+    def test_array_over_max_array_len(self):
+        with pytest.raises(ValueError, match=r"'items' has 3 elements \(max_array_len=2\)"):
+            snapconfig.loads("items: [1, 2, 3]", "yaml", max_array_len=2)
+        assert snapconfig.loads("items: [1, 2]", "yaml", max_array_len=2) == {"items": [1, 2]}
+
+    def test_load_rebuilds_with_compiled_limits(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"items": [1, 2]}, f)
+        snapconfig.compile(path, max_keys=1, max_array_len=2)
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        with open(path, "w") as f:
+            json.dump({"items": [1, 2, 3, 4, 5]}, f)
+        os.utime(path, (future, future))
+        with pytest.raises(ValueError, match=r"'items' has 5 elements \(max_array_len=2\)"):
+            snapconfig.load(path)
+        with open(path, "w") as f:
+            json.dump({"items": [], "extra": 1}, f)
+        os.utime(path, (future + 10, future + 10))
+        with pytest.raises(ValueError, match=r"the root has 2 keys \(max_keys=1\)"):
+            snapconfig.load(path)
+        snapconfig.clear_cache(path)
+
+
+class TestLoadDefaults:
+    def test_defaults_merge_under_config(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({"db": {"host": "db.internal"}, "debug": False}, f)
+        defaults = {"db": {"host": "localhost", "port": 5432}, "debug": True, "workers": (1, 2)}
+        config = snapconfig.load(path, defaults=defaults)
+        assert config.to_dict() == {
+            "db": {"host": "db.internal", "port": 5432},
+            "debug": False,
+            "workers": [1, 2],
+        }
+        assert config.source_path == path
+        assert config.cache_path == ""
+        # The cache still holds only the file's own values.
+        assert "workers" not in snapconfig.load(path)
+        snapconfig.clear_cache(path)
+
+    def test_unsupported_default_types(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            json.dump({}, f)
+        with pytest.raises(TypeError, match="keys must be str"):
+            snapconfig.load(path, defaults={1: "x"})
+        with pytest.raises(TypeError, match="Cannot store"):
+            snapconfig.load(path, defaults={"x": object()})
+        snapconfig.clear_cache(path)
+
+
+class TestCompileDir:
+    def write(self, temp_dir, files):
+        for name, text in files.items():
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write(text)
+
+    def test_compiles_recognised_files(self, temp_dir):
+        self.write(temp_dir, {
+            "a.json": '{"x": 1}',
+            "b.yaml": "x: 2\n",
+            "c.toml": "x = 3\n",
+            "notes.txt": "not a config",
+        })
+        os.mkdir(os.path.join(temp_dir, "sub"))
+        caches = snapconfig.compile_dir(temp_dir)
+        assert [os.path.basename(c) for c in caches] == [
+            "a.json.snapconfig", "b.yaml.snapconfig", "c.toml.snapconfig",
+        ]
+        assert [snapconfig.load_compiled(c).get("x") for c in caches] == [1, 2, 3]
+        # existing caches aren't picked up as sources on a second run
+        assert len(snapconfig.compile_dir(temp_dir)) == 3
+
+    def test_pattern(self, temp_dir):
+        self.write(temp_dir, {"a.json": '{"x": 1}', "b.yaml": "x: 2\n"})
+        caches = snapconfig.compile_dir(temp_dir, pattern="*.yaml")
+        assert [os.path.basename(c) for c in caches] == ["b.yaml.snapconfig"]
+
+    def test_failures_are_collected(self, temp_dir):
+        self.write(temp_dir, {"bad.json": "{oops", "good.json": '{"x": 1}'})
+        with pytest.raises(ValueError, match="Failed to compile 1 of 2 files") as exc:
+            snapconfig.compile_dir(temp_dir)
+        assert "bad.json" in str(exc.value)
+        assert os.path.exists(os.path.join(temp_dir, "good.json.snapconfig"))
+
+    def test_missing_dir(self, temp_dir):
+        with pytest.raises(OSError):
+            snapconfig.compile_dir(os.path.join(temp_dir, "nope"))
+
+
+class TestBytesValues:
+    DEFAULTS = {"key": b"\x00\xffsecret"}
+
+    def test_get_returns_bytes(self, load_json):
+        config = load_json({"name": "svc"}, defaults=self.DEFAULTS)
+        assert config.get("key") == b"\x00\xffsecret"
+        assert config.to_dict() == {"key": b"\x00\xffsecret", "name": "svc"}
+
+    def test_text_output_is_base64(self, load_json):
+        config = load_json({"name": "svc"}, defaults=self.DEFAULTS)
+        assert json.loads(config.to_json())["key"] == "AP9zZWNyZXQ="
+
+    def test_equality(self, load_json):
+        config = load_json({"name": "svc"}, defaults=self.DEFAULTS)
+        assert config == load_json({"name": "svc"}, defaults=self.DEFAULTS)
+
+
+class TestOverrides:
+    DEFAULTS = {
+        "name": "svc",
+        "debug": False,
+        "workers": 4,
+        "db": {"host": "localhost", "port": 5432},
+    }
+
+    def config(self, text):
+        return snapconfig.load_fileobj(io.StringIO(text), "json")
+
+    def test_reports_changed_paths(self):
+        config = self.config(
+            '{"name": "svc", "debug": true, "workers": 4,'
+            ' "db": {"host": "localhost", "port": 6543}}'
+        )
+        assert sorted(config.overrides(self.DEFAULTS)) == ["db.port", "debug"]
+
+    def test_unchanged_config_has_no_overrides(self):
+        config = self.config(json.dumps(self.DEFAULTS))
+        assert config.overrides(self.DEFAULTS) == []
+
+    def test_extra_keys_count_missing_keys_dont(self):
+        config = self.config('{"name": "svc", "region": "eu"}')
+        assert config.overrides(self.DEFAULTS) == ["region"]
+
+    def test_defaults_must_be_config_values(self):
+        config = self.config('{"name": "svc"}')
+        with pytest.raises(TypeError):
+            config.overrides({"name": object()})
+
+
+class TestDeprecatedKeys:
+    def compile(self, temp_dir, **kwargs):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write('{"host": "old", "database": {"host": "new", "pool": 5}}')
+        snapconfig.compile(
+            path, deprecated=["host=database.host", "database.pool"], **kwargs
+        )
+        return path
+
+    def test_deprecated_key_warns(self, temp_dir):
+        config = snapconfig.load_compiled(self.compile(temp_dir) + ".snapconfig")
+        with pytest.warns(DeprecationWarning, match="'host' is deprecated; use 'database.host'"):
+            assert config.get("host") == "old"
+        with pytest.warns(DeprecationWarning, match="'host' is deprecated"):
+            assert config["host"] == "old"
+        with pytest.warns(DeprecationWarning, match="'database.pool' is deprecated$"):
+            assert config.get("database.pool") == 5
+
+    def test_normal_key_does_not_warn(self, temp_dir):
+        config = snapconfig.load_compiled(self.compile(temp_dir) + ".snapconfig")
+        with warnings.catch_warnings():
+            warnings.simplefilter("error")
+            assert config.get("database.host") == "new"
+            assert config["database"]["host"] == "new"
+            assert config.get("missing") is None
+
+    def test_kept_when_load_rebuilds(self, temp_dir):
+        path = self.compile(temp_dir, compress=True)
+        with open(path, "w") as f:
+            f.write('{"host": "older"}')
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        os.utime(path, (future, future))
+        config = snapconfig.load(path)
+        with pytest.warns(DeprecationWarning):
+            assert config.get("host") == "older"
+
+    def test_sub_view_paths_are_relative(self, temp_dir):
+        config = snapconfig.load(self.compile(temp_dir))
+        with pytest.warns(DeprecationWarning, match="'pool' is deprecated"):
+            assert config.sub("database").get("pool") == 5
+
+    def test_any_spelling_of_the_path_warns(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write('{"OLD": {"X": 1}, "arr": [{"o": 2}], "new": 1}')
+        snapconfig.compile(path, deprecated=["OLD.X", "arr.0.o"], dedup=True)
+        config = snapconfig.load(path)
+        with pytest.warns(DeprecationWarning, match="'arr\\[0\\].o' is deprecated"):
+            assert config.get("arr[0].o") == 2
+        with pytest.warns(DeprecationWarning, match="'old.x' is deprecated"):
+            assert config.get("old.x", case_insensitive=True) == 1
+        with pytest.warns(DeprecationWarning, match="'o' is deprecated"):
+            assert config.sub("arr[0]").get("o") == 2
+        # "new" shares the deduplicated node of "OLD.X" but is not deprecated.
+        with warnings.catch_warnings():
+            warnings.simplefilter("error")
+            assert config.get("new") == 1
+
+    def test_invalid_entry(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write("{}")
+        with pytest.raises(ValueError, match="Invalid deprecated entry"):
+            snapconfig.compile(path, deprecated=["=new"])
+
+
+class TestJsonNonFinite:
+    TEXT = '{"low": -Infinity, "high": Infinity, "ratio": NaN, "label": "NaN", "unset": null}'
+
+    def test_rejected_by_default(self):
+        with pytest.raises(ValueError):
+            snapconfig.loads(self.TEXT)
+
+    def test_loads_allow_nan(self):
+        data = snapconfig.loads(self.TEXT, allow_nan=True)
+        assert data["low"] == float("-inf")
+        assert data["high"] == float("inf")
+        assert math.isnan(data["ratio"])
+        assert data["label"] == "NaN"
+        assert data["unset"] is None
+
+    def test_survives_cache_round_trip(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write(self.TEXT)
+        config = snapconfig.load_compiled(snapconfig.compile(path, allow_nan=True))
+        assert config.get("low") == float("-inf")
+        assert config.get("high") == float("inf")
+        assert math.isnan(config.get("ratio"))
+
+    def test_load_rebuilds_with_allow_nan(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write(self.TEXT)
+        snapconfig.compile(path, allow_nan=True)
+        with open(path, "w") as f:
+            f.write(self.TEXT.replace('"unset": null', '"unset": NaN'))
+        future = os.path.getmtime(path + ".snapconfig") + 10
+        os.utime(path, (future, future))
+        assert math.isnan(snapconfig.load(path).get("unset"))
+        snapconfig.clear_cache(path)
+
+    def compiled(self, temp_dir):
+        path = os.path.join(temp_dir, "config.json")
+        with open(path, "w") as f:
+            f.write('{"a": NaN, "b": [1.5, -Infinity]}')
+        return snapconfig.load_compiled(snapconfig.compile(path, allow_nan=True))
+
+    def test_to_json(self, temp_dir):
+        config = self.compiled(temp_dir)
+        with pytest.raises(ValueError, match="Cannot serialize"):
+            config.to_json()
+        assert json.loads(config.to_json(non_finite="null")) == {"a": None, "b": [1.5, None]}
+        with pytest.raises(ValueError, match="non_finite must be 'error' or 'null'"):
+            config.to_json(non_finite="keep")
+
+    def test_to_dict(self, temp_dir):
+        config = self.compiled(temp_dir)
+        assert math.isnan(config.to_dict()["a"])
+        assert config.to_dict(non_finite="null") == {"a": None, "b": [1.5, None]}
+        with pytest.raises(ValueError, match="non-finite float"):
+            config.to_dict(non_finite="error")
+
+
+class TestLoadLayered:
+    def tree(self, temp_dir):
+        repo = os.path.join(temp_dir, "repo")
+        leaf = os.path.join(repo, "services", "api")
+        os.makedirs(leaf)
+        os.mkdir(os.path.join(repo, ".git"))
+        files = {
+            temp_dir: 'outside = true\n',
+            repo: 'name = "mono"\n[db]\nhost = "localhost"\nport = 5432\n',
+            os.path.join(repo, "services"): '[db]\nport = 6543\n',
+        }
+        for directory, text in files.items():
+            with open(os.path.join(directory, "settings.toml"), "w") as f:
+                f.write(text)
+        return repo, leaf
+
+    def test_deeper_directories_win(self, temp_dir):
+        repo, leaf = self.tree(temp_dir)
+        config = snapconfig.load_layered(leaf, "settings.toml")
+        assert config.to_dict() == {"name": "mono", "db": {"host": "localhost", "port": 6543}}
+        assert config.source_path == os.path.join(
+            os.path.realpath(repo), "services", "settings.toml"
+        )
+
+    def test_walks_to_root_without_marker(self, temp_dir):
+        _, leaf = self.tree(temp_dir)
+        config = snapconfig.load_layered(leaf, "settings.toml", marker=None)
+        assert config.get("outside") is True
+        assert config.get("db.port") == 6543
+
+    def test_no_layers(self, temp_dir):
+        _, leaf = self.tree(temp_dir)
+        with pytest.raises(OSError, match="missing.toml"):
+            snapconfig.load_layered(leaf, "missing.toml")
+
+
+class TestValidateJsonSchema:
+    SCHEMA = {
+        "type": "object",
+        "required": ["name", "db"],
+        "properties": {
+            "name": {"type": "string"},
+            "db": {
+                "type": "object",
+                "required": ["host"],
+                "properties": {"port": {"type": "integer", "maximum": 65535}},
+            },
+            "servers": {"type": "array", "items": {"type": "string"}},
+        },
+    }
+
+    def config(self, data):
+        config = snapconfig.load_fileobj(io.StringIO(json.dumps(data)), "json")
+        if not hasattr(config, "validate"):
+            pytest.skip("built without the jsonschema feature")
+        return config
+
+    def test_valid_config(self, temp_dir):
+        config = self.config({"name": "svc", "db": {"host": "x", "port": 5432}})
+        config.validate(json.dumps(self.SCHEMA))
+        path = os.path.join(temp_dir, "schema.json")
+        with open(path, "w") as f:
+            json.dump(self.SCHEMA, f)
+        config.validate(path)
+
+    def test_reports_every_failure_with_paths(self):
+        config = self.config({"name": 1, "db": {"port": 70000}, "servers": ["a", 2]})
+        with pytest.raises(ValueError) as exc:
+            config.validate(json.dumps(self.SCHEMA))
+        message = str(exc.value)
+        assert "(4 errors)" in message
+        assert "\n  name: " in message
+        assert "\n  db: " in message and "host" in message
+        assert "\n  db.port: " in message
+        assert "\n  servers.1: " in message
+
+    def test_bad_schema(self):
+        config = self.config({"name": "svc"})
+        with pytest.raises(ValueError, match="neither an existing file nor valid JSON"):
+            config.validate("not json")
+        with pytest.raises(ValueError, match="Invalid JSON Schema"):
+            config.validate('{"type": 12}')